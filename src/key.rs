@@ -0,0 +1,163 @@
+//! Names of pairs as C strings, without allocating for short ones.
+
+use crate::{NvError, NvResult};
+use std::{borrow::Borrow,
+          ffi::{CStr, CString},
+          fmt,
+          hash::{Hash, Hasher},
+          ops::Deref};
+
+/// Size of the buffer holding short names, including the terminating NUL byte.
+const INLINE: usize = 64;
+
+/// Name of a pair, ready to be passed to C. Returned by [`IntoCStr`](crate::IntoCStr).
+///
+/// Names shorter than 64 bytes are copied into a buffer inside of the key instead of being
+/// allocated, so looking up pairs by `&str` doesn't allocate.
+///
+/// ```
+/// use libnv::NvKey;
+///
+/// let key = NvKey::new("vdev_tree").unwrap();
+/// assert!(key.is_inline());
+/// assert_eq!(c"vdev_tree", &*key);
+/// ```
+#[derive(Clone)]
+pub struct NvKey<'a>(Repr<'a>);
+
+#[derive(Clone)]
+enum Repr<'a> {
+    Borrowed(&'a CStr),
+    Owned(CString),
+    /// `buf` holds `len` bytes of the name followed by NUL.
+    Inline {
+        buf: [u8; INLINE],
+        len: u8,
+    },
+}
+
+impl<'a> NvKey<'a> {
+    /// Copy `name`, failing with [`NvError::InvalidString`] if it contains a NUL byte.
+    pub fn new<S: AsRef<[u8]> + ?Sized>(name: &S) -> NvResult<Self> {
+        let name = name.as_ref();
+        if name.len() >= INLINE {
+            return Ok(NvKey(Repr::Owned(CString::new(name)?)));
+        }
+        if name.contains(&0) {
+            // Only allocates to build the error.
+            return Err(CString::new(name).unwrap_err().into());
+        }
+        let mut buf = [0; INLINE];
+        buf[..name.len()].copy_from_slice(name);
+        Ok(NvKey(Repr::Inline { buf, len: name.len() as u8 }))
+    }
+
+    /// Wrap a name that is already NUL-terminated, without copying it.
+    pub const fn from_c_str(name: &'a CStr) -> Self { NvKey(Repr::Borrowed(name)) }
+
+    /// Take an owned name, without copying it.
+    pub fn from_c_string(name: CString) -> Self { NvKey(Repr::Owned(name)) }
+
+    /// Take an owned name. Short names are moved into the key, longer ones reuse the allocation
+    /// of `name`.
+    pub(crate) fn from_vec(name: Vec<u8>) -> NvResult<Self> {
+        if name.len() < INLINE {
+            NvKey::new(&name)
+        } else {
+            Ok(NvKey(Repr::Owned(CString::new(name)?)))
+        }
+    }
+
+    /// Borrow the name.
+    pub fn as_c_str(&self) -> &CStr {
+        match &self.0 {
+            Repr::Borrowed(name) => name,
+            Repr::Owned(name) => name,
+            Repr::Inline { buf, len } => CStr::from_bytes_with_nul(&buf[..=*len as usize])
+                .expect("inline key is NUL-terminated"),
+        }
+    }
+
+    /// Whether the name is stored inside of the key.
+    pub fn is_inline(&self) -> bool { matches!(self.0, Repr::Inline { .. }) }
+
+    /// Copy the name if it's borrowed.
+    pub fn into_owned(self) -> NvKey<'static> {
+        match self.0 {
+            Repr::Borrowed(name) => NvKey::new(name.to_bytes()).expect("C strings contain no NUL"),
+            Repr::Owned(name) => NvKey(Repr::Owned(name)),
+            Repr::Inline { buf, len } => NvKey(Repr::Inline { buf, len }),
+        }
+    }
+}
+
+impl Deref for NvKey<'_> {
+    type Target = CStr;
+
+    fn deref(&self) -> &CStr { self.as_c_str() }
+}
+
+impl AsRef<CStr> for NvKey<'_> {
+    fn as_ref(&self) -> &CStr { self }
+}
+
+impl Borrow<CStr> for NvKey<'_> {
+    fn borrow(&self) -> &CStr { self }
+}
+
+impl fmt::Debug for NvKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { self.as_c_str().fmt(f) }
+}
+
+impl PartialEq for NvKey<'_> {
+    fn eq(&self, other: &Self) -> bool { self.as_c_str() == other.as_c_str() }
+}
+
+impl Eq for NvKey<'_> {}
+
+impl Hash for NvKey<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) { self.as_c_str().hash(state) }
+}
+
+impl From<NvKey<'_>> for CString {
+    fn from(key: NvKey<'_>) -> Self {
+        match key.0 {
+            Repr::Owned(name) => name,
+            _ => key.as_c_str().to_owned(),
+        }
+    }
+}
+
+impl TryFrom<&str> for NvKey<'_> {
+    type Error = NvError;
+
+    fn try_from(name: &str) -> NvResult<Self> { NvKey::new(name) }
+}
+
+/// Name of a pair known at compile time, as a `&'static CStr`. Names containing NUL bytes fail
+/// to compile.
+///
+/// ```
+/// use libnv::{nvkey, nvpair::NvList};
+///
+/// const TXG: &std::ffi::CStr = nvkey!("txg");
+///
+/// let mut list = NvList::default();
+/// list.insert(TXG, 7u64).unwrap();
+/// assert_eq!(7, list.get_u64(nvkey!("txg")).unwrap());
+/// ```
+#[macro_export]
+macro_rules! nvkey {
+    ($name:literal) => {{
+        const KEY: &::std::ffi::CStr = $crate::__nvkey(::std::concat!($name, "\0"));
+        KEY
+    }};
+}
+
+#[doc(hidden)]
+pub const fn __nvkey(name: &str) -> &CStr {
+    match CStr::from_bytes_with_nul(name.as_bytes()) {
+        Ok(name) => name,
+        Err(_) => panic!("names of pairs can't contain NUL bytes"),
+    }
+}
@@ -14,9 +14,14 @@
 
 #[cfg(feature = "nvpair")] pub mod nvpair;
 
+mod key;
+
+#[doc(hidden)] pub use key::__nvkey;
+pub use key::NvKey;
 use std::{borrow::Cow,
-          ffi::{CStr, CString, NulError},
-          io};
+          ffi::{CStr, CString, NulError, OsStr},
+          io,
+          os::unix::ffi::OsStrExt};
 
 use quick_error::quick_error;
 
@@ -27,61 +32,283 @@ quick_error! {
         /// Name a.k.a. key can't contain NULL byte. You going to get this error if you try so.
         InvalidString(err: NulError) {
             from()
+            source(err)
         }
         /// error return by ffi. See libc for more information.
         NativeError(code: i32) {}
         /// Trying to set an error on n/v list that already has error
         AlreadySet {}
-        /// No value found for given name.
-        NotFound {}
+        /// No pair with given name. `key` is empty when the library didn't say which pair it
+        /// looked for.
+        NotFound { key: String } {
+            display("{}no such pair", key_prefix(key))
+        }
         /// Library failed to allocate.
-        OutOfMemory {}
+        OutOfMemory {
+            display("out of memory")
+        }
         /// Other IO errors
-        Io(err: io::Error) {}
+        Io(err: io::Error) {
+            display("{}", err)
+            source(err)
+        }
         /// Operation not support on a list given flags used to create the list.
-        OperationNotSupported {}
+        OperationNotSupported {
+            display("operation not supported")
+        }
+        /// The library rejected an argument, i.e. a value of unknown type.
+        InvalidArgument {
+            display("invalid argument")
+        }
+        /// Packed list is truncated or malformed.
+        CorruptBuffer {
+            display("corrupt packed list")
+        }
+        /// Packed list uses an encoding the library doesn't know.
+        UnsupportedEncoding {
+            display("unsupported encoding")
+        }
+        /// A pair with the same name already exists and the list doesn't allow duplicates.
+        AlreadyExists {
+            display("pair already exists")
+        }
+        /// Value exists, but it has a different type than the one requested. `key` is empty for
+        /// values that don't belong to a list.
+        TypeMismatch { key: String, expected: &'static str, found: &'static str } {
+            display("{}expected value of type {}, found {}", key_prefix(key), expected, found)
+        }
+        /// Packed list was created with other flags than the ones given to unpack it. Flags are
+        /// the raw `NV_UNIQUE_NAME*` bits for `nvpair` and `NV_FLAG_*` bits for `libnv`.
+        FlagsMismatch { expected: u32, found: u32 } {
+            display("packed list has flags {:#x}, expected {:#x}", found, expected)
+        }
+        /// Buffer can't hold the packed list.
+        BufferTooSmall { required: usize, available: usize } {
+            display("buffer of {} bytes is too small, {} bytes required", available, required)
+        }
+        /// Text given to one of the parsers is malformed.
+        Parse { line: usize, column: usize, message: String } {
+            display("line {}, column {}: {}", line, column, message)
+        }
         /// Got non-utf8 string from the library.
         InvalidStringEncoding(err: std::str::Utf8Error) {
             from()
+            source(err)
+        }
+        /// `op` failed on the pair named `key`, or on the whole list if `key` is empty.
+        Failed { op: Operation, key: String, source: Box<NvError> } {
+            display("{} failed{}: {}", op, key_suffix(key), source)
+            source(&**source)
         }
     }
 }
+
+/// Operation of the library that failed, see [`NvError::Failed`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Operation {
+    Insert,
+    Lookup,
+    Pack,
+    Unpack,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Operation::Insert => "insert",
+            Operation::Lookup => "lookup",
+            Operation::Pack => "pack",
+            Operation::Unpack => "unpack",
+        })
+    }
+}
+
+/// Name of a pair to put in front of an error message.
+fn key_prefix(key: &str) -> String {
+    if key.is_empty() {
+        String::new()
+    } else {
+        format!("{:?}: ", key)
+    }
+}
+
+/// Name of a pair to put after the operation in an error message.
+fn key_suffix(key: &str) -> String {
+    if key.is_empty() {
+        String::new()
+    } else {
+        format!(" for {:?}", key)
+    }
+}
+
 impl NvError {
+    /// Error for `errno` set by libnv or libnvpair. Both report the same conditions with the same
+    /// codes, except for unpacking, see [`NvError::failed`].
     pub(crate) fn from_errno(errno: i32) -> Self {
         match errno {
-            libc::ENOENT => NvError::NotFound,
+            libc::ENOENT => NvError::NotFound { key: String::new() },
             libc::ENOMEM => NvError::OutOfMemory,
+            libc::EINVAL => NvError::InvalidArgument,
+            libc::EFAULT => NvError::CorruptBuffer,
+            libc::EEXIST => NvError::AlreadyExists,
+            // Same value as `ENOTSUP` on supported platforms.
             libc::EOPNOTSUPP => NvError::OperationNotSupported,
             n => NvError::Io(io::Error::from_raw_os_error(n)),
         }
     }
+
+    /// Error for `errno` returned by `op` on the pair named `key`. Missing pairs are reported as
+    /// [`NvError::NotFound`], which already names the pair.
+    ///
+    /// Unpacking reports malformed buffers with `EINVAL` or `EFAULT` and unknown encodings with
+    /// `ENOTSUP`.
+    pub(crate) fn failed(op: Operation, key: Option<&CStr>, errno: i32) -> Self {
+        let key = key.map(|key| key.to_string_lossy().into_owned()).unwrap_or_default();
+        let err = match (op, errno) {
+            (Operation::Unpack, libc::EINVAL | libc::EFAULT) => NvError::CorruptBuffer,
+            (Operation::Unpack, libc::ENOTSUP) => NvError::UnsupportedEncoding,
+            (_, errno) => NvError::from_errno(errno),
+        };
+        match err {
+            NvError::NotFound { .. } => NvError::NotFound { key },
+            err => NvError::Failed { op, key, source: Box::new(err) },
+        }
+    }
+
+    pub(crate) fn not_found(key: &CStr) -> Self {
+        NvError::NotFound { key: key.to_string_lossy().into_owned() }
+    }
+
+    /// The error without the operation and the pair it happened on.
+    pub fn root_cause(&self) -> &NvError {
+        match self {
+            NvError::Failed { source, .. } => source.root_cause(),
+            err => err,
+        }
+    }
+
+    /// Operation that failed, if known.
+    pub fn operation(&self) -> Option<Operation> {
+        match self {
+            NvError::Failed { op, .. } => Some(*op),
+            NvError::NotFound { .. } | NvError::TypeMismatch { .. } => Some(Operation::Lookup),
+            NvError::FlagsMismatch { .. } => Some(Operation::Unpack),
+            _ => None,
+        }
+    }
+
+    /// Name of the pair the error happened on, if known.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            NvError::NotFound { key }
+            | NvError::TypeMismatch { key, .. }
+            | NvError::Failed { key, .. } => Some(key.as_str()).filter(|key| !key.is_empty()),
+            _ => None,
+        }
+    }
+
+    /// The errno reported by the library, if the error came from one.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self.root_cause() {
+            NvError::NativeError(code) => Some(*code),
+            NvError::NotFound { .. } => Some(libc::ENOENT),
+            NvError::OutOfMemory => Some(libc::ENOMEM),
+            NvError::OperationNotSupported => Some(libc::EOPNOTSUPP),
+            NvError::InvalidArgument => Some(libc::EINVAL),
+            NvError::CorruptBuffer => Some(libc::EFAULT),
+            NvError::UnsupportedEncoding => Some(libc::ENOTSUP),
+            NvError::AlreadyExists => Some(libc::EEXIST),
+            NvError::Io(err) => err.raw_os_error(),
+            _ => None,
+        }
+    }
+
+    fn io_kind(&self) -> io::ErrorKind {
+        match self.root_cause() {
+            NvError::Io(err) => err.kind(),
+            NvError::NotFound { .. } => io::ErrorKind::NotFound,
+            NvError::OutOfMemory => io::ErrorKind::OutOfMemory,
+            NvError::OperationNotSupported | NvError::UnsupportedEncoding => {
+                io::ErrorKind::Unsupported
+            },
+            NvError::InvalidArgument => io::ErrorKind::InvalidInput,
+            NvError::CorruptBuffer | NvError::FlagsMismatch { .. } => io::ErrorKind::InvalidData,
+            NvError::AlreadyExists => io::ErrorKind::AlreadyExists,
+            NvError::InvalidString(_) | NvError::TypeMismatch { .. } => io::ErrorKind::InvalidInput,
+            NvError::BufferTooSmall { .. } => io::ErrorKind::InvalidInput,
+            NvError::Parse { .. } | NvError::InvalidStringEncoding(_) => io::ErrorKind::InvalidData,
+            NvError::NativeError(code) => io::Error::from_raw_os_error(*code).kind(),
+            NvError::AlreadySet | NvError::Failed { .. } => io::ErrorKind::Other,
+        }
+    }
+}
+
+/// Keeps I/O errors as they are, other errors become the payload of an `io::Error` of the
+/// closest kind.
+impl From<NvError> for io::Error {
+    fn from(err: NvError) -> Self {
+        match err {
+            NvError::Io(err) => err,
+            err => io::Error::new(err.io_kind(), err),
+        }
+    }
 }
 
 /// Short-cut to Result<T, NvError>.
 pub type NvResult<T> = Result<T, NvError>;
 
 /// Trait to keep public interface friendly (i.e. support rust types like `&str`) and at the same
-/// time allow using lower level types like `CString` & `CStr`.
+/// time allow using lower level types like `CString` & `CStr`. Short names given as Rust strings
+/// are converted without allocating, see [`NvKey`].
 pub trait IntoCStr<'a> {
-    fn into_c_str(self) -> NvResult<Cow<'a, CStr>>;
+    fn into_c_str(self) -> NvResult<NvKey<'a>>;
+}
+
+impl<'a> IntoCStr<'a> for NvKey<'a> {
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { Ok(self) }
+}
+
+impl<'a> IntoCStr<'a> for &'a NvKey<'_> {
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { Ok(NvKey::from_c_str(self)) }
 }
 
 impl<'a> IntoCStr<'a> for &'a CStr {
-    fn into_c_str(self) -> NvResult<Cow<'a, CStr>> { Ok(Cow::from(self)) }
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { Ok(NvKey::from_c_str(self)) }
 }
 
 impl<'a> IntoCStr<'a> for CString {
-    fn into_c_str(self) -> NvResult<Cow<'a, CStr>> { Ok(Cow::from(self)) }
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { Ok(NvKey::from_c_string(self)) }
+}
+
+impl<'a> IntoCStr<'a> for Box<CStr> {
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { Ok(NvKey::from_c_string(self.into())) }
 }
 
 impl<'a> IntoCStr<'a> for &str {
-    fn into_c_str(self) -> NvResult<Cow<'a, CStr>> {
-        CString::new(self).map(Cow::from).map_err(NvError::from)
-    }
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { NvKey::new(self) }
+}
+
+impl<'a> IntoCStr<'a> for &String {
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { NvKey::new(self) }
 }
 
 impl<'a> IntoCStr<'a> for String {
-    fn into_c_str(self) -> NvResult<Cow<'a, CStr>> {
-        CString::new(self).map(Cow::from).map_err(NvError::from)
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { NvKey::from_vec(self.into_bytes()) }
+}
+
+impl<'a> IntoCStr<'a> for Cow<'_, str> {
+    fn into_c_str(self) -> NvResult<NvKey<'a>> {
+        match self {
+            Cow::Borrowed(name) => NvKey::new(name),
+            Cow::Owned(name) => name.into_c_str(),
+        }
     }
 }
+
+impl<'a> IntoCStr<'a> for &OsStr {
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { NvKey::new(self.as_bytes()) }
+}
+
+impl<'a> IntoCStr<'a> for &[u8] {
+    fn into_c_str(self) -> NvResult<NvKey<'a>> { NvKey::new(self) }
+}
@@ -17,7 +17,6 @@
 //! - Insert/Remove binary
 //! - Take operations
 //! - Iterator interface
-use libc::ENOMEM;
 
 // Importing all because it's cold, I dont want to turn on heater and it's hard
 // to type.
@@ -28,7 +27,7 @@ use std::{convert::{From, Into},
                unix::io::AsRawFd},
           slice};
 
-use crate::{IntoCStr, NvError, NvResult};
+use crate::{IntoCStr, NvError, NvResult, Operation};
 
 /// Enumeration of available data types that the API supports.
 pub enum NvType {
@@ -178,16 +177,7 @@ impl<'a> BorrowedPackedNvList<'a> {
     /// The `flags` should be the same that were originally passed to [`NvList::new`], if it was
     /// created by this library.  Otherwise, they should refer to whatever top level nvlist is
     /// expected.
-    pub fn unpack(&self, flags: NvFlag) -> NvResult<NvList> {
-        let raw =
-            unsafe { nvlist_unpack(self.buf.as_ptr() as *const c_void, self.len(), flags as i32) };
-        if raw.is_null() {
-            let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
-            Err(NvError::from_errno(errno))
-        } else {
-            Ok(NvList { ptr: raw })
-        }
-    }
+    pub fn unpack(&self, flags: NvFlag) -> NvResult<NvList> { unpack(self.buf, flags) }
 }
 
 impl PackedNvList {
@@ -207,13 +197,30 @@ impl PackedNvList {
     /// created by this library.  Otherwise, they should refer to whatever top level nvlist is
     /// expected.
     pub fn unpack(&self, flags: NvFlag) -> NvResult<NvList> {
-        let raw = unsafe { nvlist_unpack(self.ptr, self.size, flags as i32) };
-        if raw.is_null() {
-            let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
-            Err(NvError::from_errno(errno))
-        } else {
-            Ok(NvList { ptr: raw })
-        }
+        unpack(unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.size) }, flags)
+    }
+}
+
+/// Offset of the flags in the header of a packed list, after its magic and version bytes.
+const HEADER_FLAGS: usize = 2;
+
+/// Flags of the header that are passed to `nvlist_create`, `NV_FLAG_PUBLIC_MASK`.
+const PUBLIC_FLAGS: u8 = 0x03;
+
+/// Unpack `buf`. libnv fails with `EILSEQ` when the flags stored in the header of the buffer
+/// differ from `flags`, which is reported as [`NvError::FlagsMismatch`].
+fn unpack(buf: &[u8], flags: NvFlag) -> NvResult<NvList> {
+    let raw = unsafe { nvlist_unpack(buf.as_ptr() as *const c_void, buf.len(), flags as i32) };
+    if !raw.is_null() {
+        return Ok(NvList { ptr: raw });
+    }
+    let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
+    match buf.get(HEADER_FLAGS) {
+        Some(found) if errno == libc::EILSEQ => Err(NvError::FlagsMismatch {
+            expected: flags as u32,
+            found:    u32::from(found & PUBLIC_FLAGS),
+        }),
+        _ => Err(NvError::failed(Operation::Unpack, None, errno)),
     }
 }
 
@@ -237,7 +244,7 @@ impl NvList {
     fn check_if_error(&self) -> NvResult<()> {
         match self.error() {
             0 => Ok(()),
-            errno => Err(NvError::NativeError(errno)),
+            errno => Err(NvError::from_errno(errno)),
         }
     }
 
@@ -252,7 +259,7 @@ impl NvList {
     pub fn new(flags: NvFlag) -> NvResult<NvList> {
         let raw_list = unsafe { nvlist_create(flags as i32) };
         if raw_list.is_null() {
-            Err(NvError::NativeError(ENOMEM))
+            Err(NvError::OutOfMemory)
         } else {
             Ok(NvList { ptr: raw_list })
         }
@@ -878,7 +885,7 @@ impl NvList {
         let ptr = unsafe { nvlist_pack(self.ptr, &mut packed.size) };
         if ptr.is_null() {
             let errno = std::io::Error::last_os_error().raw_os_error().unwrap_or(0);
-            Err(NvError::from_errno(errno))
+            Err(NvError::failed(Operation::Pack, None, errno))
         } else {
             packed.ptr = ptr;
             Ok(packed)
@@ -931,7 +938,22 @@ mod test {
             unsafe {
                 nvlist_add_descriptor(nv.as_ptr(), name.as_ptr(), 1);
             }
-            assert!(matches!(nv.pack().unwrap_err(), NvError::OperationNotSupported));
+            let err = nv.pack().unwrap_err();
+            assert_eq!(Some(Operation::Pack), err.operation());
+            assert!(matches!(err.root_cause(), NvError::OperationNotSupported));
+        }
+    }
+
+    mod nvlist_add {
+        use super::*;
+
+        #[test]
+        fn duplicate() {
+            let mut nv = NvList::new(NvFlag::None).unwrap();
+            nv.insert_number("Answer", 42u64).unwrap();
+            let err = nv.insert_number("Answer", 43u64).unwrap_err();
+            assert!(matches!(err, NvError::AlreadyExists));
+            assert_eq!(Some(libc::EEXIST), err.raw_os_error());
         }
     }
 
@@ -943,7 +965,9 @@ mod test {
             let mut nv = NvList::new(NvFlag::None).unwrap();
             nv.insert_number("Answer", 42u64).unwrap();
             let packed = nv.pack().unwrap();
-            assert!(matches!(packed.unpack(NvFlag::IgnoreCase).unwrap_err(), NvError::Io(_)));
+            let err = packed.unpack(NvFlag::IgnoreCase).unwrap_err();
+            assert_eq!(Some(Operation::Unpack), err.operation());
+            assert!(matches!(err.root_cause(), NvError::FlagsMismatch { expected: 1, found: 0 }));
         }
 
         #[test]
@@ -973,7 +997,9 @@ mod test {
                 ptr:  buf.as_mut_ptr() as *mut c_void,
                 size: 100,
             });
-            assert!(matches!(packed.unpack(NvFlag::None).unwrap_err(), NvError::Io(_)));
+            let err = packed.unpack(NvFlag::None).unwrap_err();
+            assert_eq!(Some(Operation::Unpack), err.operation());
+            assert!(matches!(err.root_cause(), NvError::CorruptBuffer));
             // Drop packed without running its destructor
         }
 
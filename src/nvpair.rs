@@ -2,16 +2,42 @@
 
 use nvpair_sys as sys;
 
-use crate::{IntoCStr, NvError, NvResult};
-use std::{collections::HashMap,
-          convert::TryInto,
+use crate::{IntoCStr, NvError, NvResult, Operation};
+use std::{borrow::{Borrow, BorrowMut},
+          cell::{Cell, UnsafeCell},
+          collections::HashMap,
           ffi::CStr,
           fmt::Formatter,
+          iter::FusedIterator,
+          marker::PhantomData,
           mem::MaybeUninit,
+          ops::{Deref, DerefMut},
           os::{raw::{c_char, c_void},
                unix::io::AsRawFd},
           ptr::null_mut};
 
+mod allocator;
+mod canonical;
+mod display;
+mod flatten;
+mod json;
+mod redact;
+mod secret;
+#[cfg(feature = "serde")] mod serialize;
+mod shared;
+mod stream;
+pub mod text;
+mod visit;
+
+pub use allocator::{FailingAllocator, FixedAllocator, NvAllocator};
+pub use display::{DisplayNvList, DisplayOptions, DisplayStyle, Printer};
+pub use json::{JsonOptions, TypeHints};
+pub use redact::{RedactedNvList, Redactor};
+pub use secret::{SecretBytes, SecretNvList};
+pub use shared::{FrozenNvList, FrozenNvListGuard, SharedNvList};
+pub use stream::PackedNvListReader;
+pub use visit::{Flow, KeyPath, Segment, Visitor};
+
 unsafe extern "C" {
     pub fn nvlist_print_json(fp: *mut libc::FILE, nvl: *const sys::nvlist_t) -> i32;
 }
@@ -19,7 +45,7 @@ unsafe extern "C" {
 /// own types if you don't want to convert to primitive types every time.
 pub trait NvTypeOp {
     /// Add self to given list.
-    fn add_to_list<'a, N: IntoCStr<'a>>(&self, list: &mut NvList, name: N) -> NvResult<()>;
+    fn add_to_list<'a, N: IntoCStr<'a>>(&self, list: &mut NvListRef, name: N) -> NvResult<()>;
 }
 
 #[repr(i32)]
@@ -42,10 +68,216 @@ pub enum NvFlag {
     UniqueNameType = 0b010,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Type of the value stored in a pair. Mirrors `data_type_t` from libnvpair.
+#[repr(i32)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum DataType {
+    /// Matches any type. Only meaningful as an argument to lookup functions.
+    DontCare     = -1,
+    /// Type is not known to the library.
+    Unknown      = 0,
+    /// There is no value associated with the name, only the name itself.
+    Boolean      = 1,
+    /// The value is an `u8` stored as `uchar_t`.
+    Byte         = 2,
+    /// The value is an `i16`.
+    Int16        = 3,
+    /// The value is an `u16`.
+    Uint16       = 4,
+    /// The value is an `i32`.
+    Int32        = 5,
+    /// The value is an `u32`.
+    Uint32       = 6,
+    /// The value is an `i64`.
+    Int64        = 7,
+    /// The value is an `u64`.
+    Uint64       = 8,
+    /// The value is a C string.
+    String       = 9,
+    /// The value is an array of bytes.
+    ByteArray    = 10,
+    /// The value is an array of `i16`.
+    Int16Array   = 11,
+    /// The value is an array of `u16`.
+    Uint16Array  = 12,
+    /// The value is an array of `i32`.
+    Int32Array   = 13,
+    /// The value is an array of `u32`.
+    Uint32Array  = 14,
+    /// The value is an array of `i64`.
+    Int64Array   = 15,
+    /// The value is an array of `u64`.
+    Uint64Array  = 16,
+    /// The value is an array of C strings.
+    StringArray  = 17,
+    /// The value is a high resolution time in nanoseconds.
+    Hrtime       = 18,
+    /// The value is another `nvlist`.
+    NvList       = 19,
+    /// The value is an array of other `nvlist`'s.
+    NvListArray  = 20,
+    /// The value is a `bool`.
+    BooleanValue = 21,
+    /// The value is an `i8`.
+    Int8         = 22,
+    /// The value is an `u8`.
+    Uint8        = 23,
+    /// The value is an array of `bool`.
+    BooleanArray = 24,
+    /// The value is an array of `i8`.
+    Int8Array    = 25,
+    /// The value is an array of `u8`.
+    Uint8Array   = 26,
+    /// The value is a `f64`.
+    Double       = 27,
+}
+
+impl DataType {
+    /// Raw `data_type_t` value of this type.
+    pub fn as_raw(self) -> sys::data_type_t::Type { self as sys::data_type_t::Type }
+
+    /// Returns `true` if values of this type are arrays.
+    pub fn is_array(self) -> bool {
+        matches!(
+            self,
+            DataType::ByteArray
+                | DataType::Int16Array
+                | DataType::Uint16Array
+                | DataType::Int32Array
+                | DataType::Uint32Array
+                | DataType::Int64Array
+                | DataType::Uint64Array
+                | DataType::StringArray
+                | DataType::NvListArray
+                | DataType::BooleanArray
+                | DataType::Int8Array
+                | DataType::Uint8Array
+        )
+    }
+
+    /// Name of the type as used by libnvpair, i.e. `DATA_TYPE_UINT64` is `uint64`.
+    pub fn name(self) -> &'static str {
+        match self {
+            DataType::DontCare => "dontcare",
+            DataType::Unknown => "unknown",
+            DataType::Boolean => "boolean",
+            DataType::Byte => "byte",
+            DataType::Int16 => "int16",
+            DataType::Uint16 => "uint16",
+            DataType::Int32 => "int32",
+            DataType::Uint32 => "uint32",
+            DataType::Int64 => "int64",
+            DataType::Uint64 => "uint64",
+            DataType::String => "string",
+            DataType::ByteArray => "byte_array",
+            DataType::Int16Array => "int16_array",
+            DataType::Uint16Array => "uint16_array",
+            DataType::Int32Array => "int32_array",
+            DataType::Uint32Array => "uint32_array",
+            DataType::Int64Array => "int64_array",
+            DataType::Uint64Array => "uint64_array",
+            DataType::StringArray => "string_array",
+            DataType::Hrtime => "hrtime",
+            DataType::NvList => "nvlist",
+            DataType::NvListArray => "nvlist_array",
+            DataType::BooleanValue => "boolean_value",
+            DataType::Int8 => "int8",
+            DataType::Uint8 => "uint8",
+            DataType::BooleanArray => "boolean_array",
+            DataType::Int8Array => "int8_array",
+            DataType::Uint8Array => "uint8_array",
+            DataType::Double => "double",
+        }
+    }
+}
+
+impl std::str::FromStr for DataType {
+    type Err = NvError;
+
+    /// Parse a name returned by [`DataType::name`].
+    fn from_str(name: &str) -> NvResult<Self> {
+        Ok(match name {
+            "dontcare" => DataType::DontCare,
+            "unknown" => DataType::Unknown,
+            "boolean" => DataType::Boolean,
+            "byte" => DataType::Byte,
+            "int16" => DataType::Int16,
+            "uint16" => DataType::Uint16,
+            "int32" => DataType::Int32,
+            "uint32" => DataType::Uint32,
+            "int64" => DataType::Int64,
+            "uint64" => DataType::Uint64,
+            "string" => DataType::String,
+            "byte_array" => DataType::ByteArray,
+            "int16_array" => DataType::Int16Array,
+            "uint16_array" => DataType::Uint16Array,
+            "int32_array" => DataType::Int32Array,
+            "uint32_array" => DataType::Uint32Array,
+            "int64_array" => DataType::Int64Array,
+            "uint64_array" => DataType::Uint64Array,
+            "string_array" => DataType::StringArray,
+            "hrtime" => DataType::Hrtime,
+            "nvlist" => DataType::NvList,
+            "nvlist_array" => DataType::NvListArray,
+            "boolean_value" => DataType::BooleanValue,
+            "int8" => DataType::Int8,
+            "uint8" => DataType::Uint8,
+            "boolean_array" => DataType::BooleanArray,
+            "int8_array" => DataType::Int8Array,
+            "uint8_array" => DataType::Uint8Array,
+            "double" => DataType::Double,
+            _ => return Err(invalid_input(format!("unknown type {}", name))),
+        })
+    }
+}
+
+impl From<sys::data_type_t::Type> for DataType {
+    /// Values unknown to this library are turned into `DataType::Unknown`.
+    fn from(src: sys::data_type_t::Type) -> Self {
+        use sys::data_type_t::*;
+        match src {
+            DATA_TYPE_DONTCARE => DataType::DontCare,
+            DATA_TYPE_BOOLEAN => DataType::Boolean,
+            DATA_TYPE_BYTE => DataType::Byte,
+            DATA_TYPE_INT16 => DataType::Int16,
+            DATA_TYPE_UINT16 => DataType::Uint16,
+            DATA_TYPE_INT32 => DataType::Int32,
+            DATA_TYPE_UINT32 => DataType::Uint32,
+            DATA_TYPE_INT64 => DataType::Int64,
+            DATA_TYPE_UINT64 => DataType::Uint64,
+            DATA_TYPE_STRING => DataType::String,
+            DATA_TYPE_BYTE_ARRAY => DataType::ByteArray,
+            DATA_TYPE_INT16_ARRAY => DataType::Int16Array,
+            DATA_TYPE_UINT16_ARRAY => DataType::Uint16Array,
+            DATA_TYPE_INT32_ARRAY => DataType::Int32Array,
+            DATA_TYPE_UINT32_ARRAY => DataType::Uint32Array,
+            DATA_TYPE_INT64_ARRAY => DataType::Int64Array,
+            DATA_TYPE_UINT64_ARRAY => DataType::Uint64Array,
+            DATA_TYPE_STRING_ARRAY => DataType::StringArray,
+            DATA_TYPE_HRTIME => DataType::Hrtime,
+            DATA_TYPE_NVLIST => DataType::NvList,
+            DATA_TYPE_NVLIST_ARRAY => DataType::NvListArray,
+            DATA_TYPE_BOOLEAN_VALUE => DataType::BooleanValue,
+            DATA_TYPE_INT8 => DataType::Int8,
+            DATA_TYPE_UINT8 => DataType::Uint8,
+            DATA_TYPE_BOOLEAN_ARRAY => DataType::BooleanArray,
+            DATA_TYPE_INT8_ARRAY => DataType::Int8Array,
+            DATA_TYPE_UINT8_ARRAY => DataType::Uint8Array,
+            DATA_TYPE_DOUBLE => DataType::Double,
+            _ => DataType::Unknown,
+        }
+    }
+}
+
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { f.write_str(self.name()) }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Unknown,
     Bool(bool),
+    Byte(u8),
     Int8(i8),
     Uint8(u8),
     Int16(i16),
@@ -54,16 +286,63 @@ pub enum Value {
     Uint32(u32),
     Int64(i64),
     Uint64(u64),
+    Hrtime(i64),
+    Double(f64),
     String(String),
     NvList(NvList),
+    BoolArray(Vec<bool>),
+    ByteArray(Vec<u8>),
+    Int8Array(Vec<i8>),
+    Uint8Array(Vec<u8>),
+    Int16Array(Vec<i16>),
+    Uint16Array(Vec<u16>),
+    Int32Array(Vec<i32>),
+    Uint32Array(Vec<u32>),
+    Int64Array(Vec<i64>),
+    Uint64Array(Vec<u64>),
+    StringArray(Vec<String>),
+    NvListArray(Vec<NvList>),
 }
 
 impl Value {
+    /// Type this value is stored as when inserted into a list.
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Value::Unknown => DataType::Unknown,
+            Value::Bool(_) => DataType::BooleanValue,
+            Value::Byte(_) => DataType::Byte,
+            Value::Int8(_) => DataType::Int8,
+            Value::Uint8(_) => DataType::Uint8,
+            Value::Int16(_) => DataType::Int16,
+            Value::Uint16(_) => DataType::Uint16,
+            Value::Int32(_) => DataType::Int32,
+            Value::Uint32(_) => DataType::Uint32,
+            Value::Int64(_) => DataType::Int64,
+            Value::Uint64(_) => DataType::Uint64,
+            Value::Hrtime(_) => DataType::Hrtime,
+            Value::Double(_) => DataType::Double,
+            Value::String(_) => DataType::String,
+            Value::NvList(_) => DataType::NvList,
+            Value::BoolArray(_) => DataType::BooleanArray,
+            Value::ByteArray(_) => DataType::ByteArray,
+            Value::Int8Array(_) => DataType::Int8Array,
+            Value::Uint8Array(_) => DataType::Uint8Array,
+            Value::Int16Array(_) => DataType::Int16Array,
+            Value::Uint16Array(_) => DataType::Uint16Array,
+            Value::Int32Array(_) => DataType::Int32Array,
+            Value::Uint32Array(_) => DataType::Uint32Array,
+            Value::Int64Array(_) => DataType::Int64Array,
+            Value::Uint64Array(_) => DataType::Uint64Array,
+            Value::StringArray(_) => DataType::StringArray,
+            Value::NvListArray(_) => DataType::NvListArray,
+        }
+    }
+
     pub fn as_bool(&self) -> NvResult<bool> {
         if let Value::Bool(val) = self {
             Ok(*val)
         } else {
-            Err(NvError::OperationNotSupported)
+            Err(self.type_mismatch(DataType::BooleanValue))
         }
     }
 
@@ -71,7 +350,7 @@ impl Value {
         if let Value::Int8(val) = self {
             Ok(*val)
         } else {
-            Err(NvError::OperationNotSupported)
+            Err(self.type_mismatch(DataType::Int8))
         }
     }
 
@@ -79,7 +358,15 @@ impl Value {
         if let Value::Uint8(val) = self {
             Ok(*val)
         } else {
-            Err(NvError::OperationNotSupported)
+            Err(self.type_mismatch(DataType::Uint8))
+        }
+    }
+
+    fn type_mismatch(&self, expected: DataType) -> NvError {
+        NvError::TypeMismatch {
+            key:      String::new(),
+            expected: expected.name(),
+            found:    self.data_type().name(),
         }
     }
 }
@@ -117,16 +404,107 @@ impl From<&str> for Value {
 impl From<NvList> for Value {
     fn from(src: NvList) -> Self { Value::NvList(src) }
 }
-#[derive(Eq, PartialEq)]
+impl From<f64> for Value {
+    fn from(src: f64) -> Self { Value::Double(src) }
+}
+impl From<Vec<bool>> for Value {
+    fn from(src: Vec<bool>) -> Self { Value::BoolArray(src) }
+}
+impl From<Vec<i8>> for Value {
+    fn from(src: Vec<i8>) -> Self { Value::Int8Array(src) }
+}
+impl From<Vec<u8>> for Value {
+    fn from(src: Vec<u8>) -> Self { Value::Uint8Array(src) }
+}
+impl From<Vec<i16>> for Value {
+    fn from(src: Vec<i16>) -> Self { Value::Int16Array(src) }
+}
+impl From<Vec<u16>> for Value {
+    fn from(src: Vec<u16>) -> Self { Value::Uint16Array(src) }
+}
+impl From<Vec<i32>> for Value {
+    fn from(src: Vec<i32>) -> Self { Value::Int32Array(src) }
+}
+impl From<Vec<u32>> for Value {
+    fn from(src: Vec<u32>) -> Self { Value::Uint32Array(src) }
+}
+impl From<Vec<i64>> for Value {
+    fn from(src: Vec<i64>) -> Self { Value::Int64Array(src) }
+}
+impl From<Vec<u64>> for Value {
+    fn from(src: Vec<u64>) -> Self { Value::Uint64Array(src) }
+}
+impl From<Vec<String>> for Value {
+    fn from(src: Vec<String>) -> Self { Value::StringArray(src) }
+}
+impl From<Vec<NvList>> for Value {
+    fn from(src: Vec<NvList>) -> Self { Value::NvListArray(src) }
+}
+
 pub struct NvList {
     ptr: *mut sys::nvlist_t,
 }
 
+// libnvpair keeps no per-thread state and allocators of lists are `Send + Sync`, so a list can be
+// used from any thread. It isn't `Sync`: libnvpair doesn't lock lists, and walking pairs of a list
+// writes a hint into it. Use `FrozenNvList` to share a list.
+unsafe impl Send for NvList {}
+
+/// A list that is owned by someone else. [`NvList`] dereferences to it, so do lists nested in
+/// other lists borrowed with [`NvListRef::get_nvlist_ref`] and [`NvListRef::get_nvlist_mut`].
+///
+/// It is never created by value, only references to it are handed out. A reference is a pointer
+/// to the underlying `nvlist_t`, so swapping two of them doesn't swap the lists.
+pub struct NvListRef(PhantomData<UnsafeCell<*mut ()>>);
+
+/// A mutable view of a list nested in another list. The parent stays mutably borrowed for as long
+/// as the view is alive.
+pub type NvListMut<'a> = &'a mut NvListRef;
+
+impl Deref for NvList {
+    type Target = NvListRef;
+
+    fn deref(&self) -> &NvListRef { unsafe { NvListRef::from_ptr(self.ptr) } }
+}
+
+impl DerefMut for NvList {
+    fn deref_mut(&mut self) -> &mut NvListRef { unsafe { NvListRef::from_ptr_mut(self.ptr) } }
+}
+
+impl AsRef<NvListRef> for NvList {
+    fn as_ref(&self) -> &NvListRef { self }
+}
+
+impl AsMut<NvListRef> for NvList {
+    fn as_mut(&mut self) -> &mut NvListRef { self }
+}
+
+impl Borrow<NvListRef> for NvList {
+    fn borrow(&self) -> &NvListRef { self }
+}
+
+impl BorrowMut<NvListRef> for NvList {
+    fn borrow_mut(&mut self) -> &mut NvListRef { self }
+}
+
+impl ToOwned for NvListRef {
+    type Owned = NvList;
+
+    /// # Panics
+    ///
+    /// Panics if libnvpair runs out of memory, see [`NvListRef::try_clone`].
+    fn to_owned(&self) -> NvList { self.try_clone().expect("Failed to copy list") }
+}
+
 impl Drop for NvList {
     fn drop(&mut self) { unsafe { sys::nvlist_free(self.ptr) } }
 }
 
 /// Return new list with no flags.
+///
+/// # Panics
+///
+/// Panics if libnvpair runs out of memory.
 impl Default for NvList {
     fn default() -> NvList {
         NvList::new(NvFlag::UniqueNameType).expect("Failed to create new list")
@@ -136,7 +514,11 @@ macro_rules! impl_list_op {
     ($type_:ty, $method:ident, false) => {
         impl NvTypeOp for $type_ {
             /// Add a `$type_` value to the `NvList`
-            fn add_to_list<'a, N: IntoCStr<'a>>(&self, list: &mut NvList, name: N) -> NvResult<()> {
+            fn add_to_list<'a, N: IntoCStr<'a>>(
+                &self,
+                list: &mut NvListRef,
+                name: N,
+            ) -> NvResult<()> {
                 return list.$method(name, *self);
             }
         }
@@ -144,7 +526,11 @@ macro_rules! impl_list_op {
     ($type_:ty, $method:ident, true) => {
         impl NvTypeOp for $type_ {
             /// Add a `$type_` value to the `NvList`
-            fn add_to_list<'a, N: IntoCStr<'a>>(&self, list: &mut NvList, name: N) -> NvResult<()> {
+            fn add_to_list<'a, N: IntoCStr<'a>>(
+                &self,
+                list: &mut NvListRef,
+                name: N,
+            ) -> NvResult<()> {
                 return list.$method(name, &*self);
             }
         }
@@ -152,24 +538,25 @@ macro_rules! impl_list_op {
 }
 
 macro_rules! nvpair_type_array_method {
-    ($type_:ty, $rmethod_insert:ident, $smethod_insert:ident, $rmethod_get:ident, $smethod_get:ident) => {
+    ($type_:ty, $data_type:ident, $rmethod_insert:ident, $smethod_insert:ident, $rmethod_get:ident, $smethod_get:ident) => {
         /// Add `&[$type_]` value to the list.
         pub fn $rmethod_insert<'a, N: IntoCStr<'a>>(
             &mut self,
             name: N,
-            value: &mut [$type_],
+            value: &[$type_],
         ) -> NvResult<()> {
             let c_name = name.into_c_str()?;
+            // libnvpair copies the array and never writes to it.
             let errno = unsafe {
                 sys::$smethod_insert(
-                    self.ptr,
+                    self.as_ptr(),
                     c_name.as_ptr(),
-                    value.as_mut_ptr(),
+                    value.as_ptr() as *mut $type_,
                     value.len() as u32,
                 )
             };
             if errno != 0 {
-                Err(NvError::from_errno(errno))
+                Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
             } else {
                 Ok(())
             }
@@ -180,18 +567,18 @@ macro_rules! nvpair_type_array_method {
             let c_name = name.into_c_str()?;
             let mut ptr = null_mut();
             let mut len = 0;
-            let errno = unsafe { sys::$smethod_get(self.ptr, c_name.as_ptr(), &mut ptr, &mut len) };
+            let errno =
+                unsafe { sys::$smethod_get(self.as_ptr(), c_name.as_ptr(), &mut ptr, &mut len) };
             if errno != 0 {
-                Err(NvError::from_errno(errno))
+                Err(self.lookup_error(&c_name, DataType::$data_type, errno))
             } else {
-                let ret = unsafe { std::slice::from_raw_parts(&mut *ptr, len.try_into().unwrap()) };
-                Ok(ret)
+                Ok(unsafe { raw_slice(ptr, len) })
             }
         }
     };
 }
 macro_rules! nvpair_type_method {
-    ($type_:ty, $rmethod_insert:ident, $smethod_insert:ident, $rmethod_get:ident, $smethod_get:ident) => {
+    ($type_:ty, $data_type:ident, $rmethod_insert:ident, $smethod_insert:ident, $rmethod_get:ident, $smethod_get:ident) => {
         /// Add `$type_` value to the list.
         pub fn $rmethod_insert<'a, N: IntoCStr<'a>>(
             &mut self,
@@ -199,9 +586,9 @@ macro_rules! nvpair_type_method {
             value: $type_,
         ) -> NvResult<()> {
             let c_name = name.into_c_str()?;
-            let errno = unsafe { sys::$smethod_insert(self.ptr, c_name.as_ptr(), value) };
+            let errno = unsafe { sys::$smethod_insert(self.as_ptr(), c_name.as_ptr(), value) };
             if errno != 0 {
-                Err(NvError::from_errno(errno))
+                Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
             } else {
                 Ok(())
             }
@@ -211,9 +598,10 @@ macro_rules! nvpair_type_method {
         pub fn $rmethod_get<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<$type_> {
             let c_name = name.into_c_str()?;
             let mut ptr = MaybeUninit::<$type_>::uninit();
-            let errno = unsafe { sys::$smethod_get(self.ptr, c_name.as_ptr(), ptr.as_mut_ptr()) };
+            let errno =
+                unsafe { sys::$smethod_get(self.as_ptr(), c_name.as_ptr(), ptr.as_mut_ptr()) };
             if errno != 0 {
-                Err(NvError::from_errno(errno))
+                Err(self.lookup_error(&c_name, DataType::$data_type, errno))
             } else {
                 let ret = unsafe { ptr.assume_init() };
                 Ok(ret)
@@ -223,35 +611,143 @@ macro_rules! nvpair_type_method {
 }
 
 impl Clone for NvList {
-    fn clone(&self) -> Self {
-        let mut new = null_mut();
-        unsafe {
-            sys::nvlist_dup(self.as_ptr(), &mut new, 0);
+    /// # Panics
+    ///
+    /// Panics if libnvpair runs out of memory, see [`NvListRef::try_clone`].
+    fn clone(&self) -> Self { NvListRef::to_owned(self) }
+}
+
+impl PartialEq for NvList {
+    fn eq(&self, other: &Self) -> bool { **self == **other }
+}
 
-            NvList::from_ptr(new)
+impl PartialEq for NvListRef {
+    /// Lists are equal when they have pairs with the same names, types and values in the same
+    /// order.
+    fn eq(&self, other: &Self) -> bool {
+        let mut left = self.iter();
+        let mut right = other.iter();
+        loop {
+            match (left.next(), right.next()) {
+                (None, None) => return true,
+                (Some(l), Some(r)) => {
+                    if l.key() != r.key()
+                        || l.data_type() != r.data_type()
+                        || l.value() != r.value()
+                    {
+                        return false;
+                    }
+                },
+                _ => return false,
+            }
         }
     }
 }
 
 impl NvList {
-    nvpair_type_method!(i8, insert_i8, nvlist_add_int8, get_i8, nvlist_lookup_int8);
+    pub fn new(flags: NvFlag) -> NvResult<Self> {
+        let mut raw_list = null_mut();
+        let errno = unsafe { sys::nvlist_alloc(&mut raw_list, flags as u32, 0) };
+        if errno != 0 {
+            Err(NvError::from_errno(errno))
+        } else {
+            Ok(NvList { ptr: raw_list })
+        }
+    }
+
+    /// Take ownership of a raw NvList from C.
+    ///
+    /// # Safety
+    ///
+    /// This provided pointer must be valid, and after this function returns
+    /// nothing else may access the raw pointer except through the returned
+    /// object.
+    // Note: this cannot be `impl From<*mut nvlist_t> for Self` because that
+    // trait is only for safe conversions.
+    pub unsafe fn from_ptr(ptr: *mut sys::nvlist_t) -> Self { Self { ptr } }
+
+    pub fn into_hashmap(self) -> HashMap<String, Value> {
+        let mut ret = HashMap::new();
+        for pair in self.iter() {
+            let key = pair.key().to_string_lossy().to_string();
+            ret.insert(key, pair.value());
+        }
+        ret
+    }
+
+    /// Like [`NvList::into_hashmap`], but keeps the order of pairs. With the `serde` feature the
+    /// list itself can be serialized too, which also keeps `boolean` pairs apart from
+    /// `boolean_value` ones.
+    pub fn into_vec(self) -> Vec<(String, Value)> {
+        self.iter().map(|pair| (pair.key().to_string_lossy().into_owned(), pair.value())).collect()
+    }
+
+    /// Make a deep copy of a list owned by someone else. The copy uses the allocator of the
+    /// original, `nvlist_dup` would use the default one.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid list.
+    unsafe fn dup_raw(ptr: *mut sys::nvlist_t) -> NvResult<NvList> {
+        let mut new = null_mut();
+        let errno = unsafe { sys::nvlist_xdup(ptr, &mut new, sys::nvlist_lookup_nv_alloc(ptr)) };
+        if errno != 0 {
+            Err(NvError::from_errno(errno))
+        } else {
+            Ok(unsafe { NvList::from_ptr(new) })
+        }
+    }
+}
+
+impl NvListRef {
+    nvpair_type_method!(i8, Int8, insert_i8, nvlist_add_int8, get_i8, nvlist_lookup_int8);
 
-    nvpair_type_method!(u8, insert_u8, nvlist_add_uint8, get_u8, nvlist_lookup_uint8);
+    nvpair_type_method!(u8, Uint8, insert_u8, nvlist_add_uint8, get_u8, nvlist_lookup_uint8);
 
-    nvpair_type_method!(i16, insert_i16, nvlist_add_int16, get_i16, nvlist_lookup_int16);
+    nvpair_type_method!(i16, Int16, insert_i16, nvlist_add_int16, get_i16, nvlist_lookup_int16);
 
-    nvpair_type_method!(u16, insert_u16, nvlist_add_uint16, get_u16, nvlist_lookup_uint16);
+    nvpair_type_method!(u16, Uint16, insert_u16, nvlist_add_uint16, get_u16, nvlist_lookup_uint16);
 
-    nvpair_type_method!(i32, insert_i32, nvlist_add_int32, get_i32, nvlist_lookup_int32);
+    nvpair_type_method!(i32, Int32, insert_i32, nvlist_add_int32, get_i32, nvlist_lookup_int32);
 
-    nvpair_type_method!(u32, insert_u32, nvlist_add_uint32, get_u32, nvlist_lookup_uint32);
+    nvpair_type_method!(u32, Uint32, insert_u32, nvlist_add_uint32, get_u32, nvlist_lookup_uint32);
 
-    nvpair_type_method!(i64, insert_i64, nvlist_add_int64, get_i64, nvlist_lookup_int64);
+    nvpair_type_method!(i64, Int64, insert_i64, nvlist_add_int64, get_i64, nvlist_lookup_int64);
 
-    nvpair_type_method!(u64, insert_u64, nvlist_add_uint64, get_u64, nvlist_lookup_uint64);
+    nvpair_type_method!(u64, Uint64, insert_u64, nvlist_add_uint64, get_u64, nvlist_lookup_uint64);
+
+    nvpair_type_method!(u8, Byte, insert_byte, nvlist_add_byte, get_byte, nvlist_lookup_byte);
+
+    nvpair_type_method!(
+        i64,
+        Hrtime,
+        insert_hrtime,
+        nvlist_add_hrtime,
+        get_hrtime,
+        nvlist_lookup_hrtime
+    );
+
+    nvpair_type_method!(
+        f64,
+        Double,
+        insert_double,
+        nvlist_add_double,
+        get_double,
+        nvlist_lookup_double
+    );
+
+    nvpair_type_array_method!(
+        u8,
+        ByteArray,
+        insert_byte_array,
+        nvlist_add_byte_array,
+        get_byte_array,
+        nvlist_lookup_byte_array
+    );
 
     nvpair_type_array_method!(
         i8,
+        Int8Array,
         insert_i8_array,
         nvlist_add_int8_array,
         get_i8_array,
@@ -260,6 +756,7 @@ impl NvList {
 
     nvpair_type_array_method!(
         u8,
+        Uint8Array,
         insert_u8_array,
         nvlist_add_uint8_array,
         get_u8_array,
@@ -268,6 +765,7 @@ impl NvList {
 
     nvpair_type_array_method!(
         i16,
+        Int16Array,
         insert_i16_array,
         nvlist_add_int16_array,
         get_i16_array,
@@ -276,6 +774,7 @@ impl NvList {
 
     nvpair_type_array_method!(
         u16,
+        Uint16Array,
         insert_u16_array,
         nvlist_add_uint16_array,
         get_u16_array,
@@ -284,6 +783,7 @@ impl NvList {
 
     nvpair_type_array_method!(
         i32,
+        Int32Array,
         insert_i32_array,
         nvlist_add_int32_array,
         get_i32_array,
@@ -292,6 +792,7 @@ impl NvList {
 
     nvpair_type_array_method!(
         u32,
+        Uint32Array,
         insert_u32_array,
         nvlist_add_uint32_array,
         get_u32_array,
@@ -300,6 +801,7 @@ impl NvList {
 
     nvpair_type_array_method!(
         i64,
+        Int64Array,
         insert_i64_array,
         nvlist_add_int64_array,
         get_i64_array,
@@ -308,47 +810,64 @@ impl NvList {
 
     nvpair_type_array_method!(
         u64,
+        Uint64Array,
         insert_u64_array,
         nvlist_add_uint64_array,
         get_u64_array,
         nvlist_lookup_uint64_array
     );
 
-    /// Make a copy of a pointer. Danger zone.
-    pub fn as_ptr(&self) -> *mut sys::nvlist_t { self.ptr }
-
-    pub fn new(flags: NvFlag) -> NvResult<Self> {
-        let mut raw_list = null_mut();
-        let errno = unsafe { sys::nvlist_alloc(&mut raw_list, flags as u32, 0) };
-        if errno != 0 {
-            Err(NvError::from_errno(errno))
-        } else {
-            Ok(NvList { ptr: raw_list })
-        }
-    }
+    /// Borrow a list owned by someone else.
+    ///
+    /// # Safety
+    ///
+    /// The provided pointer must be valid for `'a` and nothing may modify the list while the
+    /// returned reference is alive.
+    pub unsafe fn from_ptr<'a>(ptr: *mut sys::nvlist_t) -> &'a Self { unsafe { &*ptr.cast() } }
 
-    /// Take ownership of a raw NvList from C.
+    /// Mutably borrow a list owned by someone else.
     ///
     /// # Safety
     ///
-    /// This provided pointer must be valid, and after this function returns
-    /// nothing else may access the raw pointer except through the returned
-    /// object.
-    // Note: this cannot be `impl From<*mut nvlist_t> for Self` because that
-    // trait is only for safe conversions.
-    pub unsafe fn from_ptr(ptr: *mut sys::nvlist_t) -> Self { Self { ptr } }
+    /// The provided pointer must be valid for `'a` and nothing else may access the list while the
+    /// returned reference is alive.
+    pub unsafe fn from_ptr_mut<'a>(ptr: *mut sys::nvlist_t) -> &'a mut Self {
+        unsafe { &mut *ptr.cast() }
+    }
 
-    pub fn iter(&self) -> impl Iterator<Item = NvPairRef> + '_ {
-        NvListIter { list: self, position: null_mut() }
+    /// Make a copy of a pointer. Danger zone.
+    pub fn as_ptr(&self) -> *mut sys::nvlist_t { self as *const Self as *mut sys::nvlist_t }
+
+    /// Iterate over pairs of the list in insertion order. The iterator can walk from the end
+    /// too.
+    pub fn iter(&self) -> NvListIter<'_> { NvListIter::after(self, null_mut()) }
+
+    /// Get a cursor at the ghost position of the list.
+    pub fn cursor(&self) -> Cursor<'_> { Cursor { list: self, current: null_mut() } }
+
+    /// Number of pairs in the list. This walks the whole list.
+    pub fn len(&self) -> usize {
+        let mut len = 0;
+        let mut pair = null_mut();
+        loop {
+            pair = unsafe { sys::nvlist_next_nvpair(self.as_ptr(), pair) };
+            if pair.is_null() {
+                return len;
+            }
+            len += 1;
+        }
     }
 
-    pub fn into_hashmap(self) -> HashMap<String, Value> {
-        let mut ret = HashMap::new();
-        for pair in self.iter() {
-            let key = pair.key().to_string_lossy().to_string();
-            ret.insert(key, pair.value());
+    /// The flags the list was created with.
+    pub fn flags(&self) -> NvFlag {
+        let flags = unsafe { sys::nvlist_nvflag(self.as_ptr()) };
+        if flags & sys::NV_UNIQUE_NAME_TYPE != 0 {
+            NvFlag::UniqueNameType
+        } else if flags & sys::NV_UNIQUE_NAME != 0 {
+            NvFlag::UniqueName
+        } else {
+            NvFlag::None
         }
-        ret
     }
 
     pub fn is_empty(&self) -> bool {
@@ -362,6 +881,50 @@ impl NvList {
         Ok(ret != sys::boolean_t::B_FALSE)
     }
 
+    /// Returns `true` if a pair with given name and type exists in the list.
+    pub fn exists_with_type<'a, N: IntoCStr<'a>>(
+        &self,
+        name: N,
+        data_type: DataType,
+    ) -> NvResult<bool> {
+        let c_name = name.into_c_str()?;
+        Ok(self.iter().any(|pair| pair.key() == c_name.as_ref() && pair.data_type() == data_type))
+    }
+
+    /// Get type of the value stored under given name without decoding the value.
+    pub fn type_of<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<DataType> {
+        let c_name = name.into_c_str()?;
+        self.find_pair(&c_name)
+            .map(|pair| pair.data_type())
+            .ok_or_else(|| NvError::not_found(&c_name))
+    }
+
+    /// Find the first pair with given name.
+    ///
+    /// This walks the list instead of using `nvlist_lookup_nvpair`, because the latter only works
+    /// on lists created with `NV_UNIQUE_NAME`.
+    fn find_pair(&self, name: &CStr) -> Option<NvPairRef> {
+        self.iter().find(|pair| pair.key() == name)
+    }
+
+    /// Turn errno returned by `nvlist_lookup_*` into an error. libnvpair reports `ENOENT` when
+    /// pair exists but has a different type, so check for that first.
+    fn lookup_error(&self, name: &CStr, expected: DataType, errno: i32) -> NvError {
+        if errno == libc::ENOENT {
+            if let Some(pair) = self.find_pair(name) {
+                let found = pair.data_type();
+                if found != expected {
+                    return NvError::TypeMismatch {
+                        key:      name.to_string_lossy().into_owned(),
+                        expected: expected.name(),
+                        found:    found.name(),
+                    };
+                }
+            }
+        }
+        NvError::failed(Operation::Lookup, Some(name), errno)
+    }
+
     pub fn insert<'a, N: IntoCStr<'a>, T: NvTypeOp>(&mut self, name: N, value: T) -> NvResult<()> {
         value.add_to_list(self, name)
     }
@@ -380,9 +943,9 @@ impl NvList {
                 sys::boolean_t::B_FALSE
             }
         };
-        let errno = unsafe { sys::nvlist_add_boolean_value(self.ptr, c_name.as_ptr(), v) };
+        let errno = unsafe { sys::nvlist_add_boolean_value(self.as_ptr(), c_name.as_ptr(), v) };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
         } else {
             Ok(())
         }
@@ -391,9 +954,9 @@ impl NvList {
     /// Add a `bool` to the list.
     pub fn insert_boolean<'a, N: IntoCStr<'a>>(&mut self, name: N) -> NvResult<()> {
         let c_name = name.into_c_str()?;
-        let errno = unsafe { sys::nvlist_add_boolean(self.ptr, c_name.as_ptr()) };
+        let errno = unsafe { sys::nvlist_add_boolean(self.as_ptr(), c_name.as_ptr()) };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
         } else {
             Ok(())
         }
@@ -405,10 +968,10 @@ impl NvList {
         let mut ptr = MaybeUninit::<sys::boolean_t::Type>::uninit();
 
         let errno = unsafe {
-            sys::nvlist_lookup_boolean_value(self.ptr, c_name.as_ptr(), ptr.as_mut_ptr())
+            sys::nvlist_lookup_boolean_value(self.as_ptr(), c_name.as_ptr(), ptr.as_mut_ptr())
         };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(self.lookup_error(&c_name, DataType::BooleanValue, errno))
         } else {
             let ret = unsafe { ptr.assume_init() };
             Ok(ret != sys::boolean_t::B_FALSE)
@@ -419,14 +982,107 @@ impl NvList {
     pub fn get_boolean<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<bool> {
         let c_name = name.into_c_str()?;
 
-        let errno = unsafe { sys::nvlist_lookup_boolean(self.ptr, c_name.as_ptr()) };
+        let errno = unsafe { sys::nvlist_lookup_boolean(self.as_ptr(), c_name.as_ptr()) };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(self.lookup_error(&c_name, DataType::Boolean, errno))
         } else {
             Ok(true)
         }
     }
 
+    /// Add an array of `bool` to the list.
+    pub fn insert_boolean_array<'a, N: IntoCStr<'a>>(
+        &mut self,
+        name: N,
+        value: &[bool],
+    ) -> NvResult<()> {
+        let c_name = name.into_c_str()?;
+        let mut raw: Vec<sys::boolean_t::Type> = value
+            .iter()
+            .map(|v| if *v { sys::boolean_t::B_TRUE } else { sys::boolean_t::B_FALSE })
+            .collect();
+        let errno = unsafe {
+            sys::nvlist_add_boolean_array(
+                self.as_ptr(),
+                c_name.as_ptr(),
+                raw.as_mut_ptr(),
+                raw.len() as u32,
+            )
+        };
+        if errno != 0 {
+            Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get an array of `bool` from the list.
+    pub fn get_boolean_array<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<Vec<bool>> {
+        let c_name = name.into_c_str()?;
+        let mut ptr = null_mut();
+        let mut len = 0;
+        let errno = unsafe {
+            sys::nvlist_lookup_boolean_array(self.as_ptr(), c_name.as_ptr(), &mut ptr, &mut len)
+        };
+        if errno != 0 {
+            Err(self.lookup_error(&c_name, DataType::BooleanArray, errno))
+        } else {
+            let raw = unsafe { raw_slice(ptr, len) };
+            Ok(raw.iter().map(|v| *v != sys::boolean_t::B_FALSE).collect())
+        }
+    }
+
+    /// Add an array of strings to the list.
+    pub fn insert_string_array<
+        'a,
+        'b,
+        N: IntoCStr<'a>,
+        V: IntoCStr<'b>,
+        I: IntoIterator<Item = V>,
+    >(
+        &mut self,
+        name: N,
+        value: I,
+    ) -> NvResult<()> {
+        let c_name = name.into_c_str()?;
+        let strings = value.into_iter().map(IntoCStr::into_c_str).collect::<NvResult<Vec<_>>>()?;
+        let pointers: Vec<*mut c_char> =
+            strings.iter().map(|e| e.as_ptr() as *mut c_char).collect();
+        let errno = unsafe {
+            sys::nvlist_add_string_array(
+                self.as_ptr(),
+                c_name.as_ptr(),
+                pointers.as_ptr(),
+                pointers.len() as u32,
+            )
+        };
+        if errno != 0 {
+            Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get an array of strings from the list.
+    pub fn get_string_array<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<Vec<String>> {
+        let c_name = name.into_c_str()?;
+        let mut ptr = null_mut();
+        let mut len = 0;
+        let errno = unsafe {
+            sys::nvlist_lookup_string_array(self.as_ptr(), c_name.as_ptr(), &mut ptr, &mut len)
+        };
+        if errno != 0 {
+            Err(self.lookup_error(&c_name, DataType::StringArray, errno))
+        } else {
+            let raw = unsafe { raw_slice(ptr, len) };
+            raw.iter()
+                .map(|v| {
+                    unsafe { CStr::from_ptr(*v) }.to_str().map(str::to_owned).map_err(NvError::from)
+                })
+                .collect()
+        }
+    }
+
     /// Add a `&str` to the list.
     pub fn insert_string<'a, 'b, N: IntoCStr<'a>, V: IntoCStr<'b>>(
         &mut self,
@@ -435,9 +1091,10 @@ impl NvList {
     ) -> NvResult<()> {
         let c_name = name.into_c_str()?;
         let c_value = value.into_c_str()?;
-        let errno = unsafe { sys::nvlist_add_string(self.ptr, c_name.as_ptr(), c_value.as_ptr()) };
+        let errno =
+            unsafe { sys::nvlist_add_string(self.as_ptr(), c_name.as_ptr(), c_value.as_ptr()) };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
         } else {
             Ok(())
         }
@@ -446,9 +1103,9 @@ impl NvList {
     pub fn get_cstr<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<&CStr> {
         let c_name = name.into_c_str()?;
         let mut ptr = null_mut();
-        let errno = unsafe { sys::nvlist_lookup_string(self.ptr, c_name.as_ptr(), &mut ptr) };
+        let errno = unsafe { sys::nvlist_lookup_string(self.as_ptr(), c_name.as_ptr(), &mut ptr) };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(self.lookup_error(&c_name, DataType::String, errno))
         } else {
             let ret = unsafe { CStr::from_ptr(&*ptr) };
             Ok(ret)
@@ -466,59 +1123,488 @@ impl NvList {
     }
 
     /// Add an NvList to the list.
-    pub fn insert_nvlist<'a, N: IntoCStr<'a>>(&mut self, name: N, value: &NvList) -> NvResult<()> {
+    pub fn insert_nvlist<'a, N: IntoCStr<'a>>(
+        &mut self,
+        name: N,
+        value: &NvListRef,
+    ) -> NvResult<()> {
         let c_name = name.into_c_str()?;
-        let errno = unsafe { sys::nvlist_add_nvlist(self.ptr, c_name.as_ptr(), value.as_ptr()) };
+        let errno =
+            unsafe { sys::nvlist_add_nvlist(self.as_ptr(), c_name.as_ptr(), value.as_ptr()) };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
         } else {
             Ok(())
         }
     }
 
-    /// Get an `NvList` from the list.
+    /// Get a copy of an `NvList` from the list.
     pub fn get_nvlist<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<NvList> {
+        let ptr = self.lookup_nvlist(name)?;
+        unsafe { NvList::dup_raw(ptr) }
+    }
+
+    /// Borrow a nested list without copying it.
+    pub fn get_nvlist_ref<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<&NvListRef> {
+        let ptr = self.lookup_nvlist(name)?;
+        Ok(unsafe { NvListRef::from_ptr(ptr) })
+    }
+
+    /// Mutably borrow a nested list without copying it. Changes made through the view are made to
+    /// the list stored in this one.
+    ///
+    /// ```
+    /// use libnv::nvpair::NvList;
+    ///
+    /// let mut config = NvList::default();
+    /// config.insert("vdev_tree", &NvList::default()).unwrap();
+    ///
+    /// let mut tree = config.get_nvlist_mut("vdev_tree").unwrap();
+    /// tree.insert("ashift", 12u64).unwrap();
+    /// assert_eq!(12, config.get_nvlist("vdev_tree").unwrap().get_u64("ashift").unwrap());
+    /// ```
+    pub fn get_nvlist_mut<'a, N: IntoCStr<'a>>(&mut self, name: N) -> NvResult<NvListMut<'_>> {
+        let ptr = self.lookup_nvlist(name)?;
+        Ok(unsafe { NvListRef::from_ptr_mut(ptr) })
+    }
+
+    /// Call `f` with a list nested under `path` and return its result. Every element of `path`
+    /// is a name of a nested list inside the previous one, an empty path means this list.
+    ///
+    /// ```
+    /// use libnv::nvpair::NvList;
+    ///
+    /// let mut tree = NvList::default();
+    /// tree.insert("ashift", 9u64).unwrap();
+    /// let mut config = NvList::default();
+    /// config.insert("vdev_tree", &tree).unwrap();
+    ///
+    /// config.update_nested(&["vdev_tree"], |tree| tree.insert("ashift", 12u64)).unwrap();
+    /// assert_eq!(12, config.get_nvlist("vdev_tree").unwrap().get_u64("ashift").unwrap());
+    /// ```
+    pub fn update_nested<R, F: FnOnce(&mut NvListRef) -> NvResult<R>>(
+        &mut self,
+        path: &[&str],
+        f: F,
+    ) -> NvResult<R> {
+        let mut list = self;
+        for name in path {
+            list = list.get_nvlist_mut(*name)?;
+        }
+        f(list)
+    }
+
+    /// Look up a nested list. Returned list is owned by this one.
+    fn lookup_nvlist<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<*mut sys::nvlist_t> {
         let c_name = name.into_c_str()?;
         let mut ptr = null_mut();
-        let errno = unsafe { sys::nvlist_lookup_nvlist(self.ptr, c_name.as_ptr(), &mut ptr) };
+        let errno = unsafe { sys::nvlist_lookup_nvlist(self.as_ptr(), c_name.as_ptr(), &mut ptr) };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(self.lookup_error(&c_name, DataType::NvList, errno))
         } else {
-            let ret = unsafe { NvList::from_ptr(ptr) };
-            Ok(ret)
+            Ok(ptr)
         }
     }
 
-    /// Turn NvPair into json representation. This method uses libnvpair to do so.
-    pub fn save_as_json<F: AsRawFd>(&self, output: F) -> NvResult<()> {
-        let mode = c"w";
-        let file = unsafe { libc::fdopen(output.as_raw_fd(), mode.as_ptr()) };
-        let errno = unsafe { nvlist_print_json(file, self.ptr) };
-        if errno != 0 {
-            Err(NvError::from_errno(errno))
-        } else {
-            unsafe {
-                libc::fflush(file);
-            }
-            Ok(())
-        }
+    /// Get a detached copy of the pair with given name.
+    pub fn get_pair<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<NvPair> {
+        let c_name = name.into_c_str()?;
+        let pair = self.find_pair(&c_name).ok_or_else(|| NvError::not_found(&c_name))?;
+        NvPair::copy_into(&pair, self.new_like_with(NvFlag::None)?)
     }
 
-    /// Attempt to pack this [`NvList`] into a serialized form.
-    ///
-    /// When created with [`NvEncoding::Xdr`], it can be sent to a host with differing
-    /// endianness.
-    pub fn pack(&self, encoding: NvEncoding) -> NvResult<PackedNvList> {
-        let mut siz: u64 = 0;
+    /// Add a copy of the pair to the list. The pair keeps its name.
+    pub fn insert_pair(&mut self, pair: &NvPair) -> NvResult<()> {
+        self.add_nvpair(&pair.as_pair_ref())
+    }
+
+    /// Copy the pair with given name from `other` list into this one.
+    pub fn copy_pair<'a, N: IntoCStr<'a>>(&mut self, other: &NvListRef, name: N) -> NvResult<()> {
+        let c_name = name.into_c_str()?;
+        let pair = other.find_pair(&c_name).ok_or_else(|| NvError::not_found(&c_name))?;
+        self.add_nvpair(&pair)
+    }
+
+    /// Copy pairs with given names from `other` list into this one. Stops at the first name
+    /// missing from `other`.
+    ///
+    /// ```
+    /// use libnv::nvpair::NvList;
+    ///
+    /// let mut config = NvList::default();
+    /// config.insert("name", "tank").unwrap();
+    /// config.insert("guid", 42u64).unwrap();
+    /// config.insert("txg", 7u64).unwrap();
+    ///
+    /// let mut label = NvList::default();
+    /// label.extend_from(&config, ["name", "guid"]).unwrap();
+    /// assert!(!label.exists("txg").unwrap());
+    /// ```
+    pub fn extend_from<'a, N: IntoCStr<'a>, I: IntoIterator<Item = N>>(
+        &mut self,
+        other: &NvListRef,
+        names: I,
+    ) -> NvResult<()> {
+        for name in names {
+            self.copy_pair(other, name)?;
+        }
+        Ok(())
+    }
+
+    /// Create a new list with only given top level names. Order and flags are preserved.
+    ///
+    /// ```
+    /// use libnv::nvpair::NvList;
+    ///
+    /// let mut config = NvList::default();
+    /// config.insert("name", "tank").unwrap();
+    /// config.insert("hostname", "secret").unwrap();
+    ///
+    /// let public = config.select(&["name"]).unwrap();
+    /// assert!(public.exists("name").unwrap());
+    /// assert!(!public.exists("hostname").unwrap());
+    /// ```
+    pub fn select(&self, names: &[&str]) -> NvResult<NvList> {
+        self.filter(|pair| names.iter().any(|name| pair.key().to_bytes() == name.as_bytes()))
+    }
+
+    /// Create a new list without given top level names. Order and flags are preserved.
+    pub fn without(&self, names: &[&str]) -> NvResult<NvList> {
+        self.filter(|pair| names.iter().all(|name| pair.key().to_bytes() != name.as_bytes()))
+    }
+
+    /// Create a new list with top level pairs for which `predicate` returns `true`. Order and
+    /// flags are preserved.
+    pub fn filter<F: FnMut(&NvPairRef) -> bool>(&self, mut predicate: F) -> NvResult<NvList> {
+        let mut ret = self.new_like()?;
+        for pair in self.iter().filter(|pair| predicate(pair)) {
+            ret.add_nvpair(&pair)?;
+        }
+        Ok(ret)
+    }
+
+    /// Create a new list with only given key paths. Paths are names of nested lists joined with
+    /// `.`, i.e. `vdev_tree.guid`. Nested lists on the way to a selected path are kept with only
+    /// selected pairs, selected nested lists are kept whole. Array indexes are not part of a path,
+    /// so `vdev_tree.children.guid` matches `guid` in every element of `children`.
+    pub fn select_paths(&self, paths: &[&str]) -> NvResult<NvList> {
+        self.filter_recursive(|path, _| {
+            paths.iter().any(|selected| {
+                selected == &path
+                    || is_path_prefix(selected, path)
+                    || is_path_prefix(path, selected)
+            })
+        })
+    }
+
+    /// Create a new list without given key paths. See [`NvListRef::select_paths`] for path syntax.
+    pub fn without_paths(&self, paths: &[&str]) -> NvResult<NvList> {
+        self.filter_recursive(|path, _| !paths.contains(&path))
+    }
+
+    /// Like [`NvListRef::filter`], but applied to nested lists and elements of nvlist arrays too.
+    /// `predicate` gets a key path of the pair. See [`NvListRef::select_paths`] for path syntax. If
+    /// `predicate` returns `false` for a nested list, the whole list is skipped.
+    pub fn filter_recursive<F: FnMut(&str, &NvPairRef) -> bool>(
+        &self,
+        mut predicate: F,
+    ) -> NvResult<NvList> {
+        self.filter_recursive_at(None, &mut predicate)
+    }
+
+    fn filter_recursive_at<F: FnMut(&str, &NvPairRef) -> bool>(
+        &self,
+        prefix: Option<&str>,
+        predicate: &mut F,
+    ) -> NvResult<NvList> {
+        let mut ret = self.new_like()?;
+        for pair in self.iter() {
+            let key = pair.key().to_string_lossy();
+            let path = match prefix {
+                Some(prefix) => format!("{}.{}", prefix, key),
+                None => key.into_owned(),
+            };
+            if !predicate(&path, &pair) {
+                continue;
+            }
+            if let Some(nested) = pair.borrow_nvlist() {
+                let filtered = nested.filter_recursive_at(Some(&path), predicate)?;
+                ret.insert_nvlist(pair.key(), &filtered)?;
+            } else if let Some(nested) = pair.borrow_nvlist_array() {
+                let filtered = nested
+                    .iter()
+                    .map(|list| list.filter_recursive_at(Some(&path), predicate))
+                    .collect::<NvResult<Vec<_>>>()?;
+                ret.insert_nvlist_array(pair.key(), &filtered)?;
+            } else {
+                ret.add_nvpair(&pair)?;
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Deep copy of the list, allocated with the allocator of this list, see
+    /// [`NvList::new_in`]. Unlike [`Clone`], reports running out of memory instead of panicking.
+    pub fn try_clone(&self) -> NvResult<NvList> { unsafe { NvList::dup_raw(self.as_ptr()) } }
+
+    /// Create an empty list with the same flags and allocator as this one.
+    fn new_like(&self) -> NvResult<NvList> {
+        self.new_like_raw(unsafe { sys::nvlist_nvflag(self.as_ptr()) })
+    }
+
+    /// Create an empty list with `flags` and the allocator of this list, for copies made from
+    /// it.
+    pub(crate) fn new_like_with(&self, flags: NvFlag) -> NvResult<NvList> {
+        self.new_like_raw(flags as u32)
+    }
+
+    fn new_like_raw(&self, flags: u32) -> NvResult<NvList> {
+        let mut raw_list = null_mut();
+        let errno = unsafe {
+            sys::nvlist_xalloc(&mut raw_list, flags, sys::nvlist_lookup_nv_alloc(self.as_ptr()))
+        };
+        if errno != 0 {
+            Err(NvError::from_errno(errno))
+        } else {
+            Ok(NvList { ptr: raw_list })
+        }
+    }
+
+    /// Add a copy of a pair from any list.
+    fn add_nvpair(&mut self, pair: &NvPairRef) -> NvResult<()> {
+        let errno = unsafe { sys::nvlist_add_nvpair(self.as_ptr(), pair.as_ptr()) };
+        if errno != 0 {
+            Err(NvError::failed(Operation::Insert, Some(pair.key()), errno))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Add value of `pair` to the list under a different name.
+    fn insert_pair_as(&mut self, name: &CStr, pair: &NvPairRef) -> NvResult<()> {
+        macro_rules! copy_value {
+            ($type_:ty, $value:ident, $add:ident) => {{
+                let mut value = MaybeUninit::<$type_>::uninit();
+                match sys::$value(pair.as_ptr(), value.as_mut_ptr()) {
+                    0 => sys::$add(self.as_ptr(), name.as_ptr(), value.assume_init()),
+                    errno => errno,
+                }
+            }};
+        }
+        macro_rules! copy_array {
+            ($value:ident, $add:ident) => {{
+                let mut ptr = null_mut();
+                let mut len = 0;
+                match sys::$value(pair.as_ptr(), &mut ptr, &mut len) {
+                    0 => sys::$add(self.as_ptr(), name.as_ptr(), ptr, len),
+                    errno => errno,
+                }
+            }};
+        }
+        let errno = unsafe {
+            match pair.data_type() {
+                DataType::Boolean => sys::nvlist_add_boolean(self.as_ptr(), name.as_ptr()),
+                DataType::BooleanValue => copy_value!(
+                    sys::boolean_t::Type,
+                    nvpair_value_boolean_value,
+                    nvlist_add_boolean_value
+                ),
+                DataType::Byte => copy_value!(sys::uchar_t, nvpair_value_byte, nvlist_add_byte),
+                DataType::Int8 => copy_value!(i8, nvpair_value_int8, nvlist_add_int8),
+                DataType::Uint8 => copy_value!(u8, nvpair_value_uint8, nvlist_add_uint8),
+                DataType::Int16 => copy_value!(i16, nvpair_value_int16, nvlist_add_int16),
+                DataType::Uint16 => copy_value!(u16, nvpair_value_uint16, nvlist_add_uint16),
+                DataType::Int32 => copy_value!(i32, nvpair_value_int32, nvlist_add_int32),
+                DataType::Uint32 => copy_value!(u32, nvpair_value_uint32, nvlist_add_uint32),
+                DataType::Int64 => copy_value!(i64, nvpair_value_int64, nvlist_add_int64),
+                DataType::Uint64 => copy_value!(u64, nvpair_value_uint64, nvlist_add_uint64),
+                DataType::Hrtime => {
+                    copy_value!(sys::hrtime_t, nvpair_value_hrtime, nvlist_add_hrtime)
+                },
+                DataType::Double => copy_value!(f64, nvpair_value_double, nvlist_add_double),
+                DataType::String => {
+                    let mut ptr = null_mut();
+                    match sys::nvpair_value_string(pair.as_ptr(), &mut ptr) {
+                        0 => sys::nvlist_add_string(self.as_ptr(), name.as_ptr(), ptr),
+                        errno => errno,
+                    }
+                },
+                DataType::NvList => {
+                    let mut ptr = null_mut();
+                    match sys::nvpair_value_nvlist(pair.as_ptr(), &mut ptr) {
+                        0 => sys::nvlist_add_nvlist(self.as_ptr(), name.as_ptr(), ptr),
+                        errno => errno,
+                    }
+                },
+                DataType::BooleanArray => {
+                    copy_array!(nvpair_value_boolean_array, nvlist_add_boolean_array)
+                },
+                DataType::ByteArray => copy_array!(nvpair_value_byte_array, nvlist_add_byte_array),
+                DataType::Int8Array => copy_array!(nvpair_value_int8_array, nvlist_add_int8_array),
+                DataType::Uint8Array => {
+                    copy_array!(nvpair_value_uint8_array, nvlist_add_uint8_array)
+                },
+                DataType::Int16Array => {
+                    copy_array!(nvpair_value_int16_array, nvlist_add_int16_array)
+                },
+                DataType::Uint16Array => {
+                    copy_array!(nvpair_value_uint16_array, nvlist_add_uint16_array)
+                },
+                DataType::Int32Array => {
+                    copy_array!(nvpair_value_int32_array, nvlist_add_int32_array)
+                },
+                DataType::Uint32Array => {
+                    copy_array!(nvpair_value_uint32_array, nvlist_add_uint32_array)
+                },
+                DataType::Int64Array => {
+                    copy_array!(nvpair_value_int64_array, nvlist_add_int64_array)
+                },
+                DataType::Uint64Array => {
+                    copy_array!(nvpair_value_uint64_array, nvlist_add_uint64_array)
+                },
+                DataType::StringArray => {
+                    copy_array!(nvpair_value_string_array, nvlist_add_string_array)
+                },
+                DataType::NvListArray => {
+                    copy_array!(nvpair_value_nvlist_array, nvlist_add_nvlist_array)
+                },
+                DataType::DontCare | DataType::Unknown => libc::EINVAL,
+            }
+        };
+        if errno != 0 {
+            Err(NvError::failed(Operation::Insert, Some(name), errno))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Add an array of `NvList`s to the list. Lists are copied.
+    pub fn insert_nvlist_array<'a, N: IntoCStr<'a>>(
+        &mut self,
+        name: N,
+        value: &[NvList],
+    ) -> NvResult<()> {
+        let c_name = name.into_c_str()?;
+        let mut lists: Vec<*mut sys::nvlist_t> = value.iter().map(|list| list.as_ptr()).collect();
+        let errno = unsafe {
+            sys::nvlist_add_nvlist_array(
+                self.as_ptr(),
+                c_name.as_ptr(),
+                lists.as_mut_ptr(),
+                lists.len() as u32,
+            )
+        };
+        if errno != 0 {
+            Err(NvError::failed(Operation::Insert, Some(&c_name), errno))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Iterate over elements of an nvlist array without copying them.
+    pub fn iter_nvlist_array<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<NvListArrayIter<'_>> {
+        let c_name = name.into_c_str()?;
+        let mut ptr = null_mut();
+        let mut len = 0;
+        let errno = unsafe {
+            sys::nvlist_lookup_nvlist_array(self.as_ptr(), c_name.as_ptr(), &mut ptr, &mut len)
+        };
+        if errno != 0 {
+            Err(self.lookup_error(&c_name, DataType::NvListArray, errno))
+        } else {
+            Ok(NvListArrayIter { lists: unsafe { raw_slice(ptr, len) }.iter() })
+        }
+    }
+
+    /// Get a copy of an array of `NvList`s from the list.
+    pub fn get_nvlist_array<'a, N: IntoCStr<'a>>(&self, name: N) -> NvResult<Vec<NvList>> {
+        self.iter_nvlist_array(name)?
+            .map(|list| unsafe { NvList::dup_raw(list.as_ptr()) })
+            .collect()
+    }
+
+    /// Turn NvPair into json representation. This method uses libnvpair to do so, see
+    /// [`NvListRef::write_json`] for a writer that doesn't need a file descriptor.
+    ///
+    /// `output` stays open.
+    pub fn save_as_json<F: AsRawFd>(&self, output: F) -> NvResult<()> {
+        let mode = c"w";
+        // `fclose` closes the descriptor the stream was opened with, so give it a copy.
+        let fd = unsafe { libc::dup(output.as_raw_fd()) };
+        if fd < 0 {
+            return Err(NvError::Io(std::io::Error::last_os_error()));
+        }
+        let file = unsafe { libc::fdopen(fd, mode.as_ptr()) };
+        if file.is_null() {
+            let err = std::io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(NvError::Io(err));
+        }
+        let errno = unsafe { nvlist_print_json(file, self.as_ptr()) };
+        let closed = unsafe { libc::fclose(file) };
+        if errno != 0 {
+            Err(NvError::from_errno(errno))
+        } else if closed != 0 {
+            Err(NvError::Io(std::io::Error::last_os_error()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Attempt to pack this [`NvList`] into a serialized form.
+    ///
+    /// When created with [`NvEncoding::Xdr`], it can be sent to a host with differing
+    /// endianness. The buffer is allocated with the allocator of this list.
+    pub fn pack(&self, encoding: NvEncoding) -> NvResult<PackedNvList> {
+        let mut siz: u64 = 0;
         let mut buf = null_mut();
+        let nva = unsafe { sys::nvlist_lookup_nv_alloc(self.as_ptr()) };
         let errno = unsafe {
-            sys::nvlist_pack(self.as_ptr(), &raw mut buf, &raw mut siz, encoding as i32, 0)
+            sys::nvlist_xpack(self.as_ptr(), &raw mut buf, &raw mut siz, encoding as i32, nva)
         };
         if errno != 0 {
-            return Err(NvError::from_errno(errno));
+            return Err(NvError::failed(Operation::Pack, None, errno));
         }
 
-        Ok(PackedNvList { buf: buf.cast::<c_void>(), buflen: siz })
+        Ok(PackedNvList { buf: buf.cast::<c_void>(), buflen: siz, nva })
+    }
+
+    /// Size of the list once packed with given encoding.
+    pub fn encoded_size(&self, encoding: NvEncoding) -> NvResult<usize> {
+        let mut size: sys::size_t = 0;
+        let errno = unsafe { sys::nvlist_size(self.as_ptr(), &mut size, encoding as i32) };
+        if errno != 0 {
+            Err(NvError::failed(Operation::Pack, None, errno))
+        } else {
+            Ok(size as usize)
+        }
+    }
+
+    /// Pack the list into the beginning of `buf` and return the number of bytes written. Fails
+    /// with [`NvError::BufferTooSmall`] if the list doesn't fit, see
+    /// [`NvListRef::encoded_size`].
+    pub fn pack_into(&self, buf: &mut [u8], encoding: NvEncoding) -> NvResult<usize> {
+        let required = self.encoded_size(encoding)?;
+        if buf.len() < required {
+            return Err(NvError::BufferTooSmall { required, available: buf.len() });
+        }
+        let mut ptr = buf.as_mut_ptr().cast::<c_char>();
+        let mut size = required as sys::size_t;
+        let errno =
+            unsafe { sys::nvlist_pack(self.as_ptr(), &mut ptr, &mut size, encoding as i32, 0) };
+        if errno != 0 {
+            Err(NvError::failed(Operation::Pack, None, errno))
+        } else {
+            Ok(required)
+        }
+    }
+
+    /// Pack the list into a new `Vec`.
+    pub fn pack_to_vec(&self, encoding: NvEncoding) -> NvResult<Vec<u8>> {
+        let mut buf = vec![0; self.encoded_size(encoding)?];
+        let len = self.pack_into(&mut buf, encoding)?;
+        buf.truncate(len);
+        Ok(buf)
     }
 }
 
@@ -533,8 +1619,57 @@ impl_list_op! {i64, insert_i64, false}
 impl_list_op! {u64, insert_u64, false}
 impl_list_op! {&str, insert_string, false}
 impl_list_op! {&NvList, insert_nvlist, false}
+impl_list_op! {&NvListRef, insert_nvlist, false}
+
+impl NvTypeOp for Value {
+    /// Add a value to the `NvList` using the type matching the variant.
+    fn add_to_list<'a, N: IntoCStr<'a>>(&self, list: &mut NvListRef, name: N) -> NvResult<()> {
+        match self {
+            Value::Unknown => {
+                let c_name = name.into_c_str()?;
+                Err(NvError::failed(Operation::Insert, Some(&c_name), libc::EINVAL))
+            },
+            Value::Bool(v) => list.insert_boolean_value(name, *v),
+            Value::Byte(v) => list.insert_byte(name, *v),
+            Value::Int8(v) => list.insert_i8(name, *v),
+            Value::Uint8(v) => list.insert_u8(name, *v),
+            Value::Int16(v) => list.insert_i16(name, *v),
+            Value::Uint16(v) => list.insert_u16(name, *v),
+            Value::Int32(v) => list.insert_i32(name, *v),
+            Value::Uint32(v) => list.insert_u32(name, *v),
+            Value::Int64(v) => list.insert_i64(name, *v),
+            Value::Uint64(v) => list.insert_u64(name, *v),
+            Value::Hrtime(v) => list.insert_hrtime(name, *v),
+            Value::Double(v) => list.insert_double(name, *v),
+            Value::String(v) => list.insert_string(name, v.as_str()),
+            Value::NvList(v) => list.insert_nvlist(name, v),
+            Value::BoolArray(v) => list.insert_boolean_array(name, v),
+            Value::ByteArray(v) => list.insert_byte_array(name, v),
+            Value::Int8Array(v) => list.insert_i8_array(name, v),
+            Value::Uint8Array(v) => list.insert_u8_array(name, v),
+            Value::Int16Array(v) => list.insert_i16_array(name, v),
+            Value::Uint16Array(v) => list.insert_u16_array(name, v),
+            Value::Int32Array(v) => list.insert_i32_array(name, v),
+            Value::Uint32Array(v) => list.insert_u32_array(name, v),
+            Value::Int64Array(v) => list.insert_i64_array(name, v),
+            Value::Uint64Array(v) => list.insert_u64_array(name, v),
+            Value::StringArray(v) => list.insert_string_array(name, v.iter().map(String::as_str)),
+            Value::NvListArray(v) => list.insert_nvlist_array(name, v),
+        }
+    }
+}
+
+impl NvTypeOp for &Value {
+    fn add_to_list<'a, N: IntoCStr<'a>>(&self, list: &mut NvListRef, name: N) -> NvResult<()> {
+        (*self).add_to_list(list, name)
+    }
+}
 
 impl std::fmt::Debug for NvList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { (**self).fmt(f) }
+}
+
+impl std::fmt::Debug for NvListRef {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_map()
             .entries(
@@ -556,83 +1691,86 @@ impl NvPairRef {
 
     pub fn key(&self) -> &CStr { unsafe { CStr::from_ptr(sys::nvpair_name(self.as_ptr())) } }
 
+    /// Borrow nested list without copying it. `None` if value of this pair is not a list.
+    fn borrow_nvlist(&self) -> Option<&NvListRef> {
+        let mut ptr = null_mut();
+        let errno = unsafe { sys::nvpair_value_nvlist(self.as_ptr(), &mut ptr) };
+        if errno != 0 {
+            None
+        } else {
+            Some(unsafe { NvListRef::from_ptr(ptr) })
+        }
+    }
+
+    /// Borrow elements of nvlist array without copying them. `None` if value of this pair is not
+    /// an array of lists.
+    fn borrow_nvlist_array(&self) -> Option<Vec<&NvListRef>> {
+        let mut ptr = null_mut();
+        let mut len = 0;
+        let errno = unsafe { sys::nvpair_value_nvlist_array(self.as_ptr(), &mut ptr, &mut len) };
+        if errno != 0 {
+            None
+        } else {
+            let lists = unsafe { raw_slice(ptr, len) };
+            Some(lists.iter().map(|list| unsafe { NvListRef::from_ptr(*list) }).collect())
+        }
+    }
+
+    /// Type of the value stored in this pair.
+    pub fn data_type(&self) -> DataType {
+        DataType::from(unsafe { sys::nvpair_type(self.as_ptr()) })
+    }
+
+    /// Returns `true` if value of this pair is an array.
+    pub fn is_array(&self) -> bool { unsafe { sys::nvpair_type_is_array(self.as_ptr()) != 0 } }
+
+    /// Number of elements in the array or `None` if value of this pair is not an array.
+    pub fn array_len(&self) -> Option<usize> {
+        if self.is_array() {
+            let len = unsafe { (*self.as_ptr()).nvp_value_elem };
+            Some(len as usize)
+        } else {
+            None
+        }
+    }
+
     pub fn value(&self) -> Value {
-        let data_type = unsafe { sys::nvpair_type(self.as_ptr()) };
-        match data_type {
-            sys::data_type_t::DATA_TYPE_BOOLEAN => Value::Bool(true),
-            sys::data_type_t::DATA_TYPE_BOOLEAN_VALUE => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<sys::boolean_t::Type>::uninit();
-                    sys::nvpair_value_boolean_value(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init() == sys::boolean_t::B_TRUE
-                };
-                Value::Bool(v)
-            },
-            sys::data_type_t::DATA_TYPE_INT8 => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<i8>::uninit();
-                    sys::nvpair_value_int8(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init()
-                };
-                Value::Int8(v)
-            },
-            sys::data_type_t::DATA_TYPE_UINT8 => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<u8>::uninit();
-                    sys::nvpair_value_uint8(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init()
-                };
-                Value::Uint8(v)
-            },
-            sys::data_type_t::DATA_TYPE_INT16 => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<i16>::uninit();
-                    sys::nvpair_value_int16(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init()
-                };
-                Value::Int16(v)
-            },
-            sys::data_type_t::DATA_TYPE_UINT16 => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<u16>::uninit();
-                    sys::nvpair_value_uint16(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init()
-                };
-                Value::Uint16(v)
-            },
-            sys::data_type_t::DATA_TYPE_INT32 => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<i32>::uninit();
-                    sys::nvpair_value_int32(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init()
-                };
-                Value::Int32(v)
-            },
-            sys::data_type_t::DATA_TYPE_UINT32 => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<u32>::uninit();
-                    sys::nvpair_value_uint32(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init()
-                };
-                Value::Uint32(v)
-            },
-            sys::data_type_t::DATA_TYPE_INT64 => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<i64>::uninit();
-                    sys::nvpair_value_int64(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init()
-                };
-                Value::Int64(v)
-            },
-            sys::data_type_t::DATA_TYPE_UINT64 => {
-                let v = unsafe {
-                    let mut ptr = MaybeUninit::<u64>::uninit();
-                    sys::nvpair_value_uint64(self.as_ptr(), ptr.as_mut_ptr());
-                    ptr.assume_init()
-                };
-                Value::Uint64(v)
+        macro_rules! value {
+            ($type_:ty, $method:ident) => {{
+                let mut value = MaybeUninit::<$type_>::uninit();
+                unsafe {
+                    sys::$method(self.as_ptr(), value.as_mut_ptr());
+                    value.assume_init()
+                }
+            }};
+        }
+        macro_rules! array {
+            ($method:ident) => {{
+                let mut ptr = null_mut();
+                let mut len = 0;
+                unsafe {
+                    sys::$method(self.as_ptr(), &mut ptr, &mut len);
+                    raw_slice(ptr, len)
+                }
+            }};
+        }
+        match self.data_type() {
+            DataType::Boolean => Value::Bool(true),
+            DataType::BooleanValue => {
+                Value::Bool(value!(sys::boolean_t::Type, nvpair_value_boolean_value) != 0)
             },
-            sys::data_type_t::DATA_TYPE_STRING => {
+            DataType::Byte => Value::Byte(value!(sys::uchar_t, nvpair_value_byte)),
+            DataType::Int8 => Value::Int8(value!(i8, nvpair_value_int8)),
+            DataType::Uint8 => Value::Uint8(value!(u8, nvpair_value_uint8)),
+            DataType::Int16 => Value::Int16(value!(i16, nvpair_value_int16)),
+            DataType::Uint16 => Value::Uint16(value!(u16, nvpair_value_uint16)),
+            DataType::Int32 => Value::Int32(value!(i32, nvpair_value_int32)),
+            DataType::Uint32 => Value::Uint32(value!(u32, nvpair_value_uint32)),
+            DataType::Int64 => Value::Int64(value!(i64, nvpair_value_int64)),
+            DataType::Uint64 => Value::Uint64(value!(u64, nvpair_value_uint64)),
+            DataType::Hrtime => Value::Hrtime(value!(sys::hrtime_t, nvpair_value_hrtime)),
+            DataType::Double => Value::Double(value!(f64, nvpair_value_double)),
+            DataType::String => {
                 let v = unsafe {
                     let mut ptr = null_mut();
                     sys::nvpair_value_string(self.as_ptr(), &mut ptr);
@@ -641,16 +1779,39 @@ impl NvPairRef {
 
                 Value::String(v.to_string_lossy().to_string())
             },
-            sys::data_type_t::DATA_TYPE_NVLIST => {
-                let v = unsafe {
-                    let mut ptr = null_mut();
-                    sys::nvpair_value_nvlist(self.as_ptr(), &mut ptr);
-                    NvList::from_ptr(ptr)
-                };
-
-                Value::NvList(v)
-            },
-            _ => Value::Unknown,
+            DataType::NvList => self
+                .borrow_nvlist()
+                .and_then(|list| unsafe { NvList::dup_raw(list.as_ptr()) }.ok())
+                .map_or(Value::Unknown, Value::NvList),
+            DataType::BooleanArray => Value::BoolArray(
+                array!(nvpair_value_boolean_array).iter().map(|v| *v != 0).collect(),
+            ),
+            DataType::ByteArray => Value::ByteArray(array!(nvpair_value_byte_array).to_vec()),
+            DataType::Int8Array => Value::Int8Array(array!(nvpair_value_int8_array).to_vec()),
+            DataType::Uint8Array => Value::Uint8Array(array!(nvpair_value_uint8_array).to_vec()),
+            DataType::Int16Array => Value::Int16Array(array!(nvpair_value_int16_array).to_vec()),
+            DataType::Uint16Array => Value::Uint16Array(array!(nvpair_value_uint16_array).to_vec()),
+            DataType::Int32Array => Value::Int32Array(array!(nvpair_value_int32_array).to_vec()),
+            DataType::Uint32Array => Value::Uint32Array(array!(nvpair_value_uint32_array).to_vec()),
+            DataType::Int64Array => Value::Int64Array(array!(nvpair_value_int64_array).to_vec()),
+            DataType::Uint64Array => Value::Uint64Array(array!(nvpair_value_uint64_array).to_vec()),
+            DataType::StringArray => Value::StringArray(
+                array!(nvpair_value_string_array)
+                    .iter()
+                    .map(|ptr| unsafe { CStr::from_ptr(*ptr) }.to_string_lossy().into_owned())
+                    .collect(),
+            ),
+            DataType::NvListArray => self
+                .borrow_nvlist_array()
+                .and_then(|lists| {
+                    lists
+                        .iter()
+                        .map(|list| unsafe { NvList::dup_raw(list.as_ptr()) })
+                        .collect::<NvResult<Vec<_>>>()
+                        .ok()
+                })
+                .map_or(Value::Unknown, Value::NvListArray),
+            DataType::DontCare | DataType::Unknown => Value::Unknown,
         }
     }
 }
@@ -660,33 +1821,346 @@ impl std::fmt::Debug for NvPairRef {
     }
 }
 
+/// A name/value pair that doesn't belong to any list.
+///
+/// The pair is stored in a private single-pair list, so it can be moved between lists with
+/// `nvlist_add_nvpair` without decoding it into a [`Value`].
+#[derive(Clone)]
+pub struct NvPair {
+    list: NvList,
+}
+
+impl NvPair {
+    /// Create a new pair from a name and a value.
+    pub fn new<'a, N: IntoCStr<'a>, T: NvTypeOp>(name: N, value: T) -> NvResult<Self> {
+        NvPair::with_list(name, value, NvList::new(NvFlag::None)?)
+    }
+
+    /// Insert the pair into the empty `list`, which then holds it.
+    fn with_list<'a, N: IntoCStr<'a>, T: NvTypeOp>(
+        name: N,
+        value: T,
+        mut list: NvList,
+    ) -> NvResult<Self> {
+        list.insert(name, value)?;
+        Ok(NvPair { list })
+    }
+
+    /// Make a detached copy of a pair that belongs to some list. The copy uses the default
+    /// allocator, [`NvListRef::get_pair`] copies with the allocator of the list.
+    pub fn from_ref(pair: &NvPairRef) -> NvResult<Self> {
+        NvPair::copy_into(pair, NvList::new(NvFlag::None)?)
+    }
+
+    /// Copy `pair` into the empty `list`, which then holds the detached pair.
+    fn copy_into(pair: &NvPairRef, list: NvList) -> NvResult<Self> {
+        let errno = unsafe { sys::nvlist_add_nvpair(list.as_ptr(), pair.as_ptr()) };
+        if errno != 0 {
+            Err(NvError::failed(Operation::Insert, Some(pair.key()), errno))
+        } else {
+            Ok(NvPair { list })
+        }
+    }
+
+    /// Borrow the pair to inspect it. The returned handle points into `self.list`, so it must not
+    /// outlive `self`.
+    pub(crate) fn as_pair_ref(&self) -> NvPairRef {
+        let ptr = unsafe { sys::nvlist_next_nvpair(self.list.as_ptr(), null_mut()) };
+        unsafe { NvPairRef::from_ptr(ptr) }
+    }
+
+    /// Name of the pair.
+    pub fn key(&self) -> &CStr {
+        unsafe { CStr::from_ptr(sys::nvpair_name(self.as_pair_ref().as_ptr())) }
+    }
+
+    /// Type of the value stored in the pair.
+    pub fn data_type(&self) -> DataType { self.as_pair_ref().data_type() }
+
+    /// Decode value of the pair.
+    pub fn value(&self) -> Value { self.as_pair_ref().value() }
+
+    /// Change name of the pair. Value is copied as is, without decoding it.
+    pub fn rename<'a, N: IntoCStr<'a>>(&mut self, name: N) -> NvResult<()> {
+        let c_name = name.into_c_str()?;
+        let mut list = self.list.new_like_with(NvFlag::None)?;
+        list.insert_pair_as(&c_name, &self.as_pair_ref())?;
+        self.list = list;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for NvPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { self.as_pair_ref().fmt(f) }
+}
+
+/// Iterator over pairs of a list. Created with [`NvListRef::iter`] or [`Cursor::iter_from`].
 pub struct NvListIter<'a> {
-    list:     &'a NvList,
-    position: *mut sys::nvpair_t,
+    list:      &'a NvListRef,
+    /// Last pair returned from the front, null before the first call to `next`.
+    front:     *mut sys::nvpair_t,
+    /// Last pair returned from the back, null before the first call to `next_back`.
+    back:      *mut sys::nvpair_t,
+    /// Set once the front and the back meet.
+    finished:  bool,
+    /// Number of pairs left, only counted when asked for since that walks the list.
+    remaining: Cell<Option<usize>>,
+}
+
+impl<'a> NvListIter<'a> {
+    /// Iterate over pairs of `list` that follow `front`, or over all of them if it's null.
+    fn after(list: &'a NvListRef, front: *mut sys::nvpair_t) -> Self {
+        NvListIter { list, front, back: null_mut(), finished: false, remaining: Cell::new(None) }
+    }
+
+    /// Turn `pair` into the next item, finishing the iteration if it's the end of the list or a
+    /// pair already returned from the other side.
+    fn step(&mut self, pair: *mut sys::nvpair_t, other: *mut sys::nvpair_t) -> Option<NvPairRef> {
+        if pair.is_null() || pair == other {
+            self.finished = true;
+            self.remaining.set(Some(0));
+            return None;
+        }
+        if let Some(remaining) = self.remaining.get() {
+            self.remaining.set(Some(remaining.saturating_sub(1)));
+        }
+        Some(unsafe { NvPairRef::from_ptr(pair) })
+    }
 }
 
 impl Iterator for NvListIter<'_> {
     type Item = NvPairRef;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next = unsafe { sys::nvlist_next_nvpair(self.list.as_ptr(), self.position) };
-        self.position = next;
-        if next.is_null() {
+        if self.finished {
+            return None;
+        }
+        let next = unsafe { sys::nvlist_next_nvpair(self.list.as_ptr(), self.front) };
+        let item = self.step(next, self.back);
+        if item.is_some() {
+            self.front = next;
+        }
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining.get().unwrap_or_else(|| {
+            let mut remaining = 0;
+            let mut pair = self.front;
+            loop {
+                pair = unsafe { sys::nvlist_next_nvpair(self.list.as_ptr(), pair) };
+                if pair.is_null() || pair == self.back {
+                    break;
+                }
+                remaining += 1;
+            }
+            self.remaining.set(Some(remaining));
+            remaining
+        });
+        (remaining, Some(remaining))
+    }
+}
+
+impl DoubleEndedIterator for NvListIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let prev = unsafe { sys::nvlist_prev_nvpair(self.list.as_ptr(), self.back) };
+        let item = self.step(prev, self.front);
+        if item.is_some() {
+            self.back = prev;
+        }
+        item
+    }
+}
+
+impl ExactSizeIterator for NvListIter<'_> {}
+
+impl FusedIterator for NvListIter<'_> {}
+
+impl<'a> IntoIterator for &'a NvListRef {
+    type IntoIter = NvListIter<'a>;
+    type Item = NvPairRef;
+
+    fn into_iter(self) -> NvListIter<'a> { self.iter() }
+}
+
+impl<'a> IntoIterator for &'a NvList {
+    type IntoIter = NvListIter<'a>;
+    type Item = NvPairRef;
+
+    fn into_iter(self) -> NvListIter<'a> { self.iter() }
+}
+
+/// A position in a list that can move in both directions. Created with [`NvListRef::cursor`].
+///
+/// Besides pairs of the list, the cursor can point at a "ghost" position that sits between the
+/// last and the first pair. A new cursor starts there.
+///
+/// ```
+/// use libnv::nvpair::NvList;
+///
+/// let mut history = NvList::new(libnv::nvpair::NvFlag::None).unwrap();
+/// history.insert("txg", 1u64).unwrap();
+/// history.insert("snapshot", "tank@a").unwrap();
+/// history.insert("txg", 2u64).unwrap();
+///
+/// let mut cursor = history.cursor();
+/// cursor.seek("snapshot").unwrap();
+/// let after: Vec<_> = cursor.iter_from().skip(1).map(|pair| pair.value()).collect();
+/// assert_eq!(vec![2u64.into()], after);
+/// ```
+pub struct Cursor<'a> {
+    list:    &'a NvListRef,
+    current: *mut sys::nvpair_t,
+}
+
+impl<'a> Cursor<'a> {
+    /// Pair the cursor points at or `None` at the ghost position.
+    pub fn current(&self) -> Option<NvPairRef> { self.pair(self.current) }
+
+    /// Pair after the current one. At the ghost position this is the first pair.
+    pub fn peek_next(&self) -> Option<NvPairRef> { self.pair(self.next_ptr()) }
+
+    /// Pair before the current one. At the ghost position this is the last pair.
+    pub fn peek_prev(&self) -> Option<NvPairRef> { self.pair(self.prev_ptr()) }
+
+    /// Move to the next pair. Moves from the last pair to the ghost position and from the ghost
+    /// position to the first pair.
+    pub fn move_next(&mut self) { self.current = self.next_ptr(); }
+
+    /// Move to the previous pair. Moves from the first pair to the ghost position and from the
+    /// ghost position to the last pair.
+    pub fn move_prev(&mut self) { self.current = self.prev_ptr(); }
+
+    /// Move to the first pair with given name. The cursor doesn't move if there is no such pair.
+    pub fn seek<'b, N: IntoCStr<'b>>(&mut self, name: N) -> NvResult<()> {
+        let c_name = name.into_c_str()?;
+        let pair = self.list.find_pair(&c_name).ok_or_else(|| NvError::not_found(&c_name))?;
+        self.current = pair.as_ptr();
+        Ok(())
+    }
+
+    /// Move to the last pair with given name. The cursor doesn't move if there is no such pair.
+    pub fn seek_last<'b, N: IntoCStr<'b>>(&mut self, name: N) -> NvResult<()> {
+        let c_name = name.into_c_str()?;
+        let pair = self.list.iter().rfind(|pair| pair.key() == c_name.as_ref());
+        self.current = pair.ok_or_else(|| NvError::not_found(&c_name))?.as_ptr();
+        Ok(())
+    }
+
+    /// Iterate from the current pair, inclusive, to the end of the list. At the ghost position
+    /// this iterates over the whole list.
+    pub fn iter_from(&self) -> NvListIter<'a> {
+        if self.current.is_null() {
+            return self.list.iter();
+        }
+        NvListIter::after(self.list, self.prev_ptr())
+    }
+
+    fn next_ptr(&self) -> *mut sys::nvpair_t {
+        unsafe { sys::nvlist_next_nvpair(self.list.as_ptr(), self.current) }
+    }
+
+    fn prev_ptr(&self) -> *mut sys::nvpair_t {
+        unsafe { sys::nvlist_prev_nvpair(self.list.as_ptr(), self.current) }
+    }
+
+    fn pair(&self, ptr: *mut sys::nvpair_t) -> Option<NvPairRef> {
+        if ptr.is_null() {
             None
         } else {
-            Some(unsafe { NvPairRef::from_ptr(next) })
+            Some(unsafe { NvPairRef::from_ptr(ptr) })
         }
     }
 }
 
+/// Iterator over elements of an nvlist array that borrows them without copying. Created with
+/// [`NvListRef::iter_nvlist_array`].
+pub struct NvListArrayIter<'a> {
+    lists: std::slice::Iter<'a, *mut sys::nvlist_t>,
+}
+
+impl<'a> Iterator for NvListArrayIter<'a> {
+    type Item = &'a NvListRef;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lists.next().map(|list| unsafe { NvListRef::from_ptr(*list) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) { self.lists.size_hint() }
+}
+
+impl DoubleEndedIterator for NvListArrayIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.lists.next_back().map(|list| unsafe { NvListRef::from_ptr(*list) })
+    }
+}
+
+impl ExactSizeIterator for NvListArrayIter<'_> {}
+
+impl FusedIterator for NvListArrayIter<'_> {}
+
 /// A packed [`NvList`]
 ///
 /// This buffer holds an NvList that has been packed into a form suitable for serialization. When
 /// constructed with the [`NvEncoding::Xdr`] flag, it can even be sent to a host with a different
 /// endianness.
+#[derive(Debug)]
 pub struct PackedNvList {
     buf:    *mut c_void,
     buflen: u64,
+    /// Allocator `buf` comes from, null if it was allocated with `malloc`.
+    nva:    *mut sys::nv_alloc_t,
+}
+
+// The buffer is owned and only written through `&mut self`. Allocators of lists can be used from
+// any thread.
+unsafe impl Send for PackedNvList {}
+unsafe impl Sync for PackedNvList {}
+
+/// Like [`PackedNvList`], but it doesn't own the memory. Use it to unpack lists read from disk or
+/// received over a socket.
+#[derive(Debug, Clone, Copy)]
+pub struct BorrowedPackedNvList<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> BorrowedPackedNvList<'a> {
+    /// Create a borrowed packed NvList from a Rust buffer.
+    pub fn from_raw(buf: &'a [u8]) -> Self { BorrowedPackedNvList { buf } }
+
+    /// Get a pointer to the packed buffer, for use with FFI functions.
+    pub fn as_ptr(&self) -> *const c_void { self.buf.as_ptr().cast() }
+
+    /// Get the packed buffer.
+    pub fn as_bytes(&self) -> &'a [u8] { self.buf }
+
+    /// Get the size of the packed buffer.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize { self.buf.len() }
+
+    /// Attempt to unpack the given buffer into an [`NvList`].
+    ///
+    /// libnvpair stores flags of the list in the buffer. `flags` should be the ones the list was
+    /// created with, unpacking a list with other flags fails with [`NvError::FlagsMismatch`].
+    pub fn unpack(&self, flags: NvFlag) -> NvResult<NvList> {
+        let list = NvList::try_from(self.buf)?;
+        if list.flags() != flags {
+            Err(flags_mismatch(flags, list.flags()))
+        } else {
+            Ok(list)
+        }
+    }
+}
+
+impl<'a> From<&'a [u8]> for BorrowedPackedNvList<'a> {
+    fn from(buf: &'a [u8]) -> Self { BorrowedPackedNvList::from_raw(buf) }
+}
+
+impl<'a> From<&'a PackedNvList> for BorrowedPackedNvList<'a> {
+    fn from(packed: &'a PackedNvList) -> Self { BorrowedPackedNvList::from_raw(packed.as_bytes()) }
 }
 
 impl PackedNvList {
@@ -694,39 +2168,133 @@ impl PackedNvList {
     ///
     /// This effectively takes ownership of the buffer, which will be freed when
     /// [`PackedNvList::drop`] is called.
-    pub fn from_raw(buf: *mut c_void, buflen: u64) -> Self { PackedNvList { buf, buflen } }
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be allocated with `malloc` and hold at least `buflen` bytes. Nothing else may
+    /// free it.
+    pub unsafe fn from_raw(buf: *mut c_void, buflen: u64) -> Self {
+        PackedNvList { buf, buflen, nva: null_mut() }
+    }
 
     /// Get a pointer to the packed buffer, for use with FFI functions.
     pub fn as_ptr(&self) -> *const c_void { self.buf }
 
     /// Get a mutable pointer to the packed buffer, for use with FFI functions.
-    pub fn as_mut_ptr(&self) -> *const c_void { self.buf }
+    pub fn as_mut_ptr(&mut self) -> *mut c_void { self.buf }
+
+    /// Get the packed buffer.
+    pub fn as_bytes(&self) -> &[u8] { unsafe { raw_bytes(self.buf.cast(), self.len()) } }
+
+    /// Copy the packed buffer into a `Vec`.
+    pub fn into_vec(self) -> Vec<u8> { self.as_bytes().to_vec() }
 
     /// Get the size of the packed buffer.
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> usize { self.buflen as usize }
 
     /// Attempt to unpack the given buffer into an [`NvList`].
-    pub fn unpack(&mut self) -> NvResult<NvList> {
-        let mut nvl = null_mut();
-        let errno =
-            unsafe { sys::nvlist_unpack(self.buf.cast::<c_char>(), self.buflen, &raw mut nvl, 0) };
+    ///
+    /// See [`BorrowedPackedNvList::unpack`].
+    pub fn unpack(&self, flags: NvFlag) -> NvResult<NvList> {
+        BorrowedPackedNvList::from(self).unpack(flags)
+    }
+}
+
+impl AsRef<[u8]> for PackedNvList {
+    fn as_ref(&self) -> &[u8] { self.as_bytes() }
+}
+
+impl AsRef<[u8]> for BorrowedPackedNvList<'_> {
+    fn as_ref(&self) -> &[u8] { self.buf }
+}
+
+impl Drop for PackedNvList {
+    fn drop(&mut self) {
+        if self.nva.is_null() {
+            unsafe { libc::free(self.buf.cast::<c_void>()) };
+            return;
+        }
+        // Same as `nv_mem_free`, which libnvpair doesn't export.
+        unsafe {
+            let free = (*(*self.nva).nva_ops).nv_ao_free.expect("allocator frees memory");
+            free(self.nva, self.buf, self.buflen as sys::size_t);
+        }
+    }
+}
+
+/// Unpack a list with whatever flags are stored in the buffer.
+impl TryFrom<&[u8]> for NvList {
+    type Error = NvError;
 
+    fn try_from(buf: &[u8]) -> NvResult<Self> {
+        let mut nvl = null_mut();
+        // libnvpair doesn't write to the buffer when unpacking.
+        let errno = unsafe {
+            sys::nvlist_unpack(buf.as_ptr() as *mut c_char, buf.len() as _, &raw mut nvl, 0)
+        };
         if errno != 0 {
-            Err(NvError::from_errno(errno))
+            Err(NvError::failed(Operation::Unpack, None, errno))
         } else {
             Ok(unsafe { NvList::from_ptr(nvl) })
         }
     }
 }
 
-impl Drop for PackedNvList {
-    fn drop(&mut self) { unsafe { libc::free(self.buf.cast::<c_void>()) }; }
+/// Like [`raw_slice`], but for buffers with a `usize` length.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to `len` initialized bytes that outlive `'a`.
+unsafe fn raw_bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(ptr, len) }
+    }
+}
+
+/// Turn an array returned by libnvpair into a slice. Empty arrays may be returned as null.
+///
+/// # Safety
+///
+/// `ptr` must be null or point to `len` initialized elements that outlive `'a`.
+unsafe fn raw_slice<'a, T>(ptr: *const T, len: sys::uint_t) -> &'a [T] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(ptr, len as usize) }
+    }
+}
+
+/// Error for input that can't be turned into a list.
+fn invalid_input(msg: String) -> NvError {
+    NvError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, msg))
+}
+
+/// Error for a packed list with other flags than `expected`.
+fn flags_mismatch(expected: NvFlag, found: NvFlag) -> NvError {
+    NvError::FlagsMismatch { expected: expected as u32, found: found as u32 }
+}
+
+/// Error for text that can't be parsed, with line and column of `offset` in `src`. Both start
+/// at 1.
+fn parse_error(src: &str, offset: usize, message: String) -> NvError {
+    let before = &src[..offset.min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    NvError::Parse { line, column, message }
+}
+
+/// Returns `true` if `path` is a nested path of `prefix`, i.e. `a.b` is a nested path of `a`.
+fn is_path_prefix(prefix: &str, path: &str) -> bool {
+    path.len() > prefix.len() && path.starts_with(prefix) && path.as_bytes()[prefix.len()] == b'.'
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::NvKey;
     use std::ffi::CString;
 
     #[test]
@@ -997,6 +2565,290 @@ mod test {
         assert_eq!(6, ret);
     }
 
+    #[test]
+    fn data_type() {
+        let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
+        list.insert_u64("u64", 42).unwrap();
+        list.insert_boolean("flag").unwrap();
+        list.insert_u32_array("array", &[1, 2, 3]).unwrap();
+
+        assert_eq!(DataType::Uint64, list.type_of("u64").unwrap());
+        assert_eq!(DataType::Boolean, list.type_of("flag").unwrap());
+        assert_eq!(DataType::Uint32Array, list.type_of("array").unwrap());
+        assert_eq!(Some("missing"), list.type_of("missing").unwrap_err().key());
+
+        assert!(list.exists_with_type("u64", DataType::Uint64).unwrap());
+        assert!(!list.exists_with_type("u64", DataType::Uint32).unwrap());
+
+        let pairs: Vec<_> = list.iter().map(|pair| (pair.is_array(), pair.array_len())).collect();
+        assert_eq!(vec![(false, None), (false, None), (true, Some(3))], pairs);
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
+        list.insert_string("string", "not a number").unwrap();
+
+        let err = list.get_u64("string").unwrap_err();
+        assert!(matches!(
+            err,
+            NvError::TypeMismatch { ref key, expected: "uint64", found: "string" } if key == "string"
+        ));
+        assert_eq!("\"string\": expected value of type uint64, found string", err.to_string());
+        assert_eq!(Some(Operation::Lookup), err.operation());
+        let err = list.get_u64("missing").unwrap_err();
+        assert!(matches!(err, NvError::NotFound { ref key } if key == "missing"));
+        assert_eq!("\"missing\": no such pair", err.to_string());
+        assert_eq!(Some(libc::ENOENT), err.raw_os_error());
+        let err = std::io::Error::from(err);
+        assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+        assert_eq!("\"missing\": no such pair", err.to_string());
+
+        let err = Value::from(1u32).as_bool().unwrap_err();
+        assert!(matches!(
+            err,
+            NvError::TypeMismatch { expected: "boolean_value", found: "uint32", .. }
+        ));
+        assert_eq!(None, err.key());
+        assert_eq!("expected value of type boolean_value, found uint32", err.to_string());
+    }
+
+    #[test]
+    fn nested_value_is_a_copy() {
+        let mut inner = NvList::new(NvFlag::UniqueNameType).unwrap();
+        inner.insert_u64("guid", 42).unwrap();
+        let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
+        list.insert_nvlist("inner", &inner).unwrap();
+
+        drop(list.iter().next().unwrap().value());
+        drop(list.get_nvlist("inner").unwrap());
+
+        assert_eq!(42, list.get_nvlist("inner").unwrap().get_u64("guid").unwrap());
+    }
+
+    #[test]
+    fn detached_pair() {
+        let mut src = NvList::new(NvFlag::UniqueNameType).unwrap();
+        src.insert_u64("guid", 42).unwrap();
+        src.insert_u32_array("array", &[1, 2, 3]).unwrap();
+
+        let mut pair = src.get_pair("guid").unwrap();
+        assert_eq!(c"guid", pair.key());
+        assert_eq!(DataType::Uint64, pair.data_type());
+        assert_eq!(Value::Uint64(42), pair.value());
+
+        pair.rename("pool_guid").unwrap();
+        assert_eq!(c"pool_guid", pair.key());
+        assert_eq!(Value::Uint64(42), pair.value());
+
+        let mut array = src.get_pair("array").unwrap();
+        array.rename("renamed").unwrap();
+
+        let mut dst = NvList::new(NvFlag::UniqueNameType).unwrap();
+        dst.insert_pair(&pair).unwrap();
+        dst.insert_pair(&array).unwrap();
+        dst.insert_pair(&NvPair::new("new", "pair").unwrap()).unwrap();
+        assert_eq!(42, dst.get_u64("pool_guid").unwrap());
+        assert_eq!(&[1, 2, 3], dst.get_u32_array("renamed").unwrap());
+        assert_eq!("pair", dst.get_str("new").unwrap());
+        assert!(!dst.exists("guid").unwrap());
+    }
+
+    #[test]
+    fn extend_from() {
+        let mut src = NvList::new(NvFlag::UniqueNameType).unwrap();
+        src.insert_u64("guid", 42).unwrap();
+        src.insert_string("name", "tank").unwrap();
+        src.insert_boolean("flag").unwrap();
+
+        let mut dst = NvList::new(NvFlag::UniqueNameType).unwrap();
+        dst.extend_from(&src, ["guid", "flag"]).unwrap();
+        assert_eq!(42, dst.get_u64("guid").unwrap());
+        assert!(dst.get_boolean("flag").unwrap());
+        assert!(!dst.exists("name").unwrap());
+
+        dst.copy_pair(&src, "name").unwrap();
+        assert_eq!("tank", dst.get_str("name").unwrap());
+
+        assert!(matches!(
+            dst.extend_from(&src, ["missing"]).unwrap_err(),
+            NvError::NotFound { .. }
+        ));
+    }
+
+    fn keys(list: &NvList) -> Vec<String> {
+        list.iter().map(|pair| pair.key().to_string_lossy().into_owned()).collect()
+    }
+
+    #[test]
+    fn select_without_filter() {
+        let mut list = NvList::new(NvFlag::UniqueName).unwrap();
+        list.insert_u64("c", 3).unwrap();
+        list.insert_u64("a", 1).unwrap();
+        list.insert_string("b", "two").unwrap();
+
+        let selected = list.select(&["a", "c", "missing"]).unwrap();
+        assert_eq!(vec!["c", "a"], keys(&selected));
+        assert_eq!(NvFlag::UniqueName, selected.flags());
+
+        let without = list.without(&["a"]).unwrap();
+        assert_eq!(vec!["c", "b"], keys(&without));
+
+        let numbers = list.filter(|pair| pair.data_type() == DataType::Uint64).unwrap();
+        assert_eq!(vec!["c", "a"], keys(&numbers));
+    }
+
+    #[test]
+    fn filter_paths() {
+        let mut child = NvList::new(NvFlag::UniqueNameType).unwrap();
+        child.insert_u64("guid", 1).unwrap();
+        child.insert_string("path", "/dev/da0").unwrap();
+        let mut tree = NvList::new(NvFlag::UniqueNameType).unwrap();
+        tree.insert_u64("guid", 2).unwrap();
+        tree.insert_nvlist_array("children", &[child.clone(), child]).unwrap();
+        let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
+        list.insert_string("name", "tank").unwrap();
+        list.insert_nvlist("vdev_tree", &tree).unwrap();
+
+        let selected = list.select_paths(&["vdev_tree.children.guid"]).unwrap();
+        assert_eq!(vec!["vdev_tree"], keys(&selected));
+        let tree = selected.get_nvlist("vdev_tree").unwrap();
+        assert_eq!(vec!["children"], keys(&tree));
+        let children = tree.get_nvlist_array("children").unwrap();
+        assert_eq!(2, children.len());
+        assert_eq!(vec!["guid"], keys(&children[1]));
+
+        let without = list.without_paths(&["vdev_tree.children.path", "name"]).unwrap();
+        assert_eq!(vec!["vdev_tree"], keys(&without));
+        let tree = without.get_nvlist("vdev_tree").unwrap();
+        assert_eq!(vec!["guid", "children"], keys(&tree));
+        assert_eq!(vec!["guid"], keys(&tree.get_nvlist_array("children").unwrap()[0]));
+
+        let whole = list.select_paths(&["vdev_tree"]).unwrap();
+        assert_eq!(
+            vec!["guid", "path"],
+            keys(&whole.get_nvlist("vdev_tree").unwrap().get_nvlist_array("children").unwrap()[0])
+        );
+    }
+
+    fn every_value() -> Vec<Value> {
+        let mut nested = NvList::new(NvFlag::UniqueNameType).unwrap();
+        nested.insert("inner", 1u8).unwrap();
+        vec![
+            Value::Bool(true),
+            Value::Byte(1),
+            Value::Int8(-2),
+            Value::Uint8(3),
+            Value::Int16(-4),
+            Value::Uint16(5),
+            Value::Int32(-6),
+            Value::Uint32(7),
+            Value::Int64(-8),
+            Value::Uint64(9),
+            Value::Hrtime(10),
+            Value::Double(11.5),
+            Value::String(String::from("twelve")),
+            Value::NvList(nested.clone()),
+            Value::NvList(NvList::new(NvFlag::UniqueNameType).unwrap()),
+            Value::BoolArray(vec![true, false]),
+            Value::ByteArray(vec![1, 2]),
+            Value::Int8Array(vec![-1, 2]),
+            Value::Uint8Array(vec![1, 2]),
+            Value::Int16Array(vec![-1, 2]),
+            Value::Uint16Array(vec![1, 2]),
+            Value::Int32Array(vec![-1, 2]),
+            Value::Uint32Array(vec![1, 2]),
+            Value::Int64Array(vec![-1, 2]),
+            Value::Uint64Array(vec![1, 2]),
+            Value::StringArray(vec![String::from("a"), String::from("b")]),
+            Value::NvListArray(vec![nested.clone(), NvList::new(NvFlag::UniqueNameType).unwrap()]),
+            Value::NvListArray(vec![]),
+        ]
+    }
+
+    #[test]
+    fn every_value_type() {
+        let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
+        for (idx, value) in every_value().iter().enumerate() {
+            list.insert(format!("v{}", idx), value).unwrap();
+        }
+        for (pair, value) in list.iter().zip(every_value()) {
+            assert_eq!(value.data_type(), pair.data_type());
+            assert_eq!(value, pair.value());
+        }
+        let err = list.insert("unknown", Value::Unknown).unwrap_err();
+        assert!(matches!(err, NvError::Failed { op: Operation::Insert, .. }));
+        assert_eq!(Some("unknown"), err.key());
+        assert_eq!(Some(libc::EINVAL), err.raw_os_error());
+        assert!(matches!(err.root_cause(), NvError::InvalidArgument));
+        let source = std::error::Error::source(&err).unwrap();
+        assert!(source.downcast_ref::<NvError>().is_some());
+        assert!(err.to_string().starts_with("insert failed for \"unknown\": "));
+    }
+
+    #[test]
+    fn flatten_unflatten() {
+        let mut leaf = NvList::new(NvFlag::UniqueNameType).unwrap();
+        for (idx, value) in every_value().iter().enumerate() {
+            leaf.insert(format!("v{}", idx), value).unwrap();
+        }
+        let mut tree = NvList::new(NvFlag::UniqueNameType).unwrap();
+        tree.insert_u64("guid", 1).unwrap();
+        tree.insert_nvlist_array("children", &[leaf.clone(), leaf]).unwrap();
+        let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
+        list.insert_string("name", "tank").unwrap();
+        list.insert_nvlist("vdev_tree", &tree).unwrap();
+
+        let flat = list.flatten("/");
+        assert_eq!((String::from("name"), Value::from("tank")), flat[0]);
+        assert_eq!((String::from("vdev_tree/guid"), Value::Uint64(1)), flat[1]);
+        assert_eq!((String::from("vdev_tree/children/0/v0"), Value::Bool(true)), flat[2]);
+        // Non-empty nvlist array is flattened into two records, everything else into one.
+        assert_eq!(2 + 2 * (every_value().len() + 1), flat.len());
+
+        assert_eq!(list, NvList::unflatten(flat, "/").unwrap());
+    }
+
+    #[test]
+    fn flatten_escapes() {
+        let mut features = NvList::new(NvFlag::UniqueNameType).unwrap();
+        features.insert_boolean("com.delphix:hole_birth").unwrap();
+        features.insert_boolean_value("org.openzfs:blake3", true).unwrap();
+        // Looks like an nvlist array, but is a list with a pair named `0`.
+        let mut by_index = NvList::new(NvFlag::UniqueNameType).unwrap();
+        by_index.insert_u64("0", 7).unwrap();
+        let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
+        list.insert_nvlist("features_for_read", &features).unwrap();
+        list.insert_nvlist("by_index", &by_index).unwrap();
+        list.insert_string(r"C:\tank", "path").unwrap();
+
+        let flat = list.flatten(".");
+        let paths: Vec<&str> = flat.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            vec![
+                r"features_for_read.com\.delphix:hole_birth\",
+                r"features_for_read.org\.openzfs:blake3",
+                r"by_index.\0",
+                r"C:\\tank",
+            ],
+            paths
+        );
+        assert_eq!(Value::Bool(true), flat[0].1);
+        assert_eq!(list, NvList::unflatten(flat, ".").unwrap());
+
+        let records = vec![(String::from("children.1.guid"), Value::Uint64(1))];
+        assert!(NvList::unflatten(records, ".").is_err());
+        let records = vec![(String::from("a\\"), Value::Uint64(1))];
+        assert!(NvList::unflatten(records, ".").is_err());
+    }
+
+    #[test]
+    fn unflatten_conflict() {
+        let records =
+            vec![(String::from("a"), Value::Uint8(1)), (String::from("a.b"), Value::Uint8(2))];
+        assert!(NvList::unflatten(records, ".").is_err());
+    }
+
     #[test]
     fn debug_list() {
         let mut list = NvList::new(NvFlag::UniqueNameType).unwrap();
@@ -1054,23 +2906,1148 @@ mod test {
         let borrowed = c"borrowed";
         list.insert(borrowed, 2u32).unwrap();
 
+        let name = String::from("string_ref");
+        list.insert(&name, 3u32).unwrap();
+        list.insert(std::borrow::Cow::Borrowed("cow"), 4u32).unwrap();
+        list.insert(std::ffi::OsStr::new("os_str"), 5u32).unwrap();
+        list.insert(&b"bytes"[..], 6u32).unwrap();
+        list.insert(Box::<CStr>::from(c"boxed"), 7u32).unwrap();
+        list.insert(crate::nvkey!("macro"), 8u32).unwrap();
+        let key = NvKey::new("key").unwrap();
+        list.insert(&key, 9u32).unwrap();
+        assert_eq!(9, list.get_u32(key).unwrap());
+        assert!(matches!(list.insert(&b"nul\0"[..], 0u32), Err(NvError::InvalidString(_))));
+
         let mut expected_map = HashMap::with_capacity(3);
         expected_map.insert(String::from("owned"), Value::from(1u32));
         expected_map.insert(String::from("borrowed"), Value::from(2u32));
+        for (idx, name) in
+            ["string_ref", "cow", "os_str", "bytes", "boxed", "macro", "key"].iter().enumerate()
+        {
+            expected_map.insert(name.to_string(), Value::from(idx as u32 + 3));
+        }
 
         assert_eq!(expected_map, list.into_hashmap());
     }
 
+    #[test]
+    fn nv_key() {
+        let short = "a".repeat(63);
+        let key = short.as_str().into_c_str().unwrap();
+        assert!(key.is_inline());
+        assert_eq!(short.as_bytes(), key.to_bytes());
+        let long = "a".repeat(64);
+        let key = long.clone().into_c_str().unwrap();
+        assert!(!key.is_inline());
+        assert_eq!(long.as_bytes(), key.to_bytes());
+        assert!(!c"borrowed".into_c_str().unwrap().is_inline());
+        assert!(c"borrowed".into_c_str().unwrap().into_owned().is_inline());
+        assert!(NvKey::new("nul\0").is_err());
+        assert_eq!(NvKey::new("same").unwrap(), NvKey::from_c_str(c"same"));
+        assert_eq!(c"same", crate::nvkey!("same"));
+        assert_eq!(CString::from(NvKey::new("same").unwrap()).as_c_str(), c"same");
+    }
+
     #[test]
     fn pack_unpack() {
         let mut list = NvList::default();
         list.insert("foo", "bar").unwrap();
         list.insert("baz", "quux").unwrap();
 
-        let mut packed = list.pack(NvEncoding::Native).unwrap();
-        let newlist = packed.unpack().unwrap();
+        let packed = list.pack(NvEncoding::Native).unwrap();
+        let newlist = packed.unpack(NvFlag::UniqueNameType).unwrap();
 
         assert_eq!(list.get_str("foo").unwrap(), newlist.get_str("foo").unwrap());
         assert_eq!(list.get_str("baz").unwrap(), newlist.get_str("baz").unwrap());
     }
+
+    #[test]
+    fn nested_mut() {
+        let mut vdev = NvList::default();
+        vdev.insert("ashift", 9u64).unwrap();
+        let mut tree = NvList::default();
+        tree.insert("vdev", &vdev).unwrap();
+        let mut config = NvList::default();
+        config.insert("vdev_tree", &tree).unwrap();
+        config.insert("name", "tank").unwrap();
+
+        config
+            .get_nvlist_mut("vdev_tree")
+            .unwrap()
+            .get_nvlist_mut("vdev")
+            .unwrap()
+            .insert("ashift", 12u64)
+            .unwrap();
+        let ashift = config.update_nested(&["vdev_tree", "vdev"], |vdev| vdev.get_u64("ashift"));
+        assert_eq!(12, ashift.unwrap());
+
+        config.update_nested(&[], |config| config.insert("txg", 4u64)).unwrap();
+        assert_eq!(4, config.get_u64("txg").unwrap());
+
+        let missing = config.update_nested(&["vdev_tree", "nope"], |_| Ok(()));
+        assert!(matches!(missing, Err(NvError::NotFound { ref key }) if key == "nope"));
+        let not_a_list = config.get_nvlist_mut("name");
+        assert!(matches!(not_a_list, Err(NvError::TypeMismatch { .. })));
+
+        let mut nested = config.get_nvlist_ref("vdev_tree").unwrap().to_owned();
+        nested.insert("children", 0u64).unwrap();
+        assert!(!config.get_nvlist_ref("vdev_tree").unwrap().exists("children").unwrap());
+    }
+
+    #[test]
+    fn iter_both_ends() {
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        for txg in 1..=4u64 {
+            list.insert("txg", txg).unwrap();
+        }
+        assert_eq!(4, list.len());
+
+        let mut iter = list.iter();
+        assert_eq!(4, iter.len());
+        assert_eq!(Value::Uint64(1), iter.next().unwrap().value());
+        assert_eq!(Value::Uint64(4), iter.next_back().unwrap().value());
+        assert_eq!(2, iter.len());
+        assert_eq!(Value::Uint64(3), iter.next_back().unwrap().value());
+        assert_eq!(Value::Uint64(2), iter.next().unwrap().value());
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+
+        let newest: Vec<_> = list.iter().rev().take(2).map(|pair| pair.value()).collect();
+        assert_eq!(vec![Value::Uint64(4), Value::Uint64(3)], newest);
+
+        // Without asking for the length, the ends still stop where they meet.
+        let mut iter = list.iter();
+        assert_eq!(Value::Uint64(4), iter.next_back().unwrap().value());
+        let rest: Vec<_> = iter.by_ref().map(|pair| pair.value()).collect();
+        assert_eq!(vec![Value::Uint64(1), Value::Uint64(2), Value::Uint64(3)], rest);
+        assert!(iter.next_back().is_none());
+        assert_eq!(0, iter.len());
+    }
+
+    #[test]
+    fn cursor() {
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.insert("a", 1u64).unwrap();
+        list.insert("b", 2u64).unwrap();
+        list.insert("a", 3u64).unwrap();
+
+        let mut cursor = list.cursor();
+        assert!(cursor.current().is_none());
+        assert_eq!(Value::Uint64(1), cursor.peek_next().unwrap().value());
+        assert_eq!(Value::Uint64(3), cursor.peek_prev().unwrap().value());
+
+        cursor.seek("b").unwrap();
+        assert_eq!(Value::Uint64(2), cursor.current().unwrap().value());
+        assert_eq!(2, cursor.iter_from().len());
+        cursor.move_next();
+        cursor.move_next();
+        assert!(cursor.current().is_none());
+        cursor.move_prev();
+        assert_eq!(Value::Uint64(3), cursor.current().unwrap().value());
+
+        cursor.seek_last("a").unwrap();
+        assert_eq!(Value::Uint64(3), cursor.current().unwrap().value());
+        assert!(matches!(cursor.seek("c"), Err(NvError::NotFound { .. })));
+        assert_eq!(Value::Uint64(3), cursor.current().unwrap().value());
+    }
+
+    #[test]
+    fn iter_nvlist_array() {
+        let lists: Vec<_> = (0..3u64)
+            .map(|guid| {
+                let mut child = NvList::default();
+                child.insert("guid", guid).unwrap();
+                child
+            })
+            .collect();
+        let mut tree = NvList::default();
+        tree.insert_nvlist_array("children", &lists).unwrap();
+
+        let children = tree.iter_nvlist_array("children").unwrap();
+        assert_eq!(3, children.len());
+        let guids: Vec<_> = children.rev().map(|child| child.get_u64("guid").unwrap()).collect();
+        assert_eq!(vec![2, 1, 0], guids);
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+        skip:   Option<&'static str>,
+        stop:   Option<&'static str>,
+    }
+
+    impl Recorder {
+        fn flow(&self, path: &KeyPath) -> Flow {
+            if self.stop.is_some_and(|stop| path.to_string() == stop) {
+                Flow::Stop
+            } else if self.skip.is_some_and(|skip| path.to_string() == skip) {
+                Flow::Skip
+            } else {
+                Flow::Continue
+            }
+        }
+    }
+
+    impl Visitor for Recorder {
+        fn visit_value(&mut self, path: &KeyPath, value: Value) -> Flow {
+            self.events.push(format!("{} = {:?}", path, value));
+            self.flow(path)
+        }
+
+        fn visit_string(&mut self, path: &KeyPath, value: &str) -> Flow {
+            self.events.push(format!("{} = {}", path, value));
+            self.flow(path)
+        }
+
+        fn enter_list(&mut self, path: &KeyPath) -> Flow {
+            self.events.push(format!("enter {}", path));
+            self.flow(path)
+        }
+
+        fn leave_list(&mut self, path: &KeyPath) -> Flow {
+            self.events.push(format!("leave {}", path));
+            Flow::Continue
+        }
+
+        fn enter_array(&mut self, path: &KeyPath, data_type: DataType, len: usize) -> Flow {
+            self.events.push(format!("enter {} {} {}", path, data_type, len));
+            self.flow(path)
+        }
+
+        fn leave_array(&mut self, path: &KeyPath) -> Flow {
+            self.events.push(format!("leave {}", path));
+            Flow::Continue
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let mut child = NvList::default();
+        child.insert("guid", 2u64).unwrap();
+        let mut tree = NvList::default();
+        tree.insert("type", "root").unwrap();
+        tree.insert_nvlist_array("children", &[child]).unwrap();
+        let mut config = NvList::default();
+        config.insert("vdev_tree", &tree).unwrap();
+        config.insert_u16_array("features", &[1, 2]).unwrap();
+        config.insert_boolean("import").unwrap();
+
+        let mut recorder = Recorder::default();
+        assert!(config.accept(&mut recorder));
+        let expected = [
+            "enter vdev_tree",
+            "vdev_tree.type = root",
+            "enter vdev_tree.children nvlist_array 1",
+            "enter vdev_tree.children[0]",
+            "vdev_tree.children[0].guid = Uint64(2)",
+            "leave vdev_tree.children[0]",
+            "leave vdev_tree.children",
+            "leave vdev_tree",
+            "enter features uint16_array 2",
+            "features[0] = Uint16(1)",
+            "features[1] = Uint16(2)",
+            "leave features",
+            "import = Bool(true)",
+        ];
+        assert_eq!(&expected[..], &recorder.events[..]);
+
+        let mut recorder = Recorder { skip: Some("vdev_tree.children"), ..Default::default() };
+        assert!(config.accept(&mut recorder));
+        assert_eq!(&expected[..3], &recorder.events[..3]);
+        assert_eq!("leave vdev_tree", recorder.events[3]);
+
+        let mut recorder = Recorder { stop: Some("features[0]"), ..Default::default() };
+        assert!(!config.accept(&mut recorder));
+        assert_eq!(&expected[..10], &recorder.events[..]);
+    }
+
+    #[test]
+    fn visitor_deep_nesting() {
+        struct Depth(usize);
+        impl Visitor for Depth {
+            fn visit_u64(&mut self, path: &KeyPath, _: u64) -> Flow {
+                self.0 = path.depth();
+                Flow::Continue
+            }
+        }
+
+        let mut list = NvList::default();
+        list.insert("leaf", 0u64).unwrap();
+        for _ in 0..1000 {
+            let mut parent = NvList::default();
+            parent.insert("nested", &list).unwrap();
+            list = parent;
+        }
+        let mut depth = Depth(0);
+        assert!(list.accept(&mut depth));
+        assert_eq!(1001, depth.0);
+    }
+
+    #[test]
+    fn packed_bytes() {
+        let mut list = NvList::new(NvFlag::UniqueName).unwrap();
+        list.insert("foo", "bar").unwrap();
+
+        let packed = list.pack(NvEncoding::Xdr).unwrap();
+        let bytes = packed.as_bytes().to_vec();
+        assert_eq!(packed.len(), bytes.len());
+        assert_eq!(bytes, packed.into_vec());
+
+        assert_eq!(list, NvList::try_from(&bytes[..]).unwrap());
+        let borrowed = BorrowedPackedNvList::from(&bytes[..]);
+        assert_eq!(list, borrowed.unpack(NvFlag::UniqueName).unwrap());
+        let err = borrowed.unpack(NvFlag::UniqueNameType).unwrap_err();
+        assert_eq!(Some(Operation::Unpack), err.operation());
+        assert!(matches!(err, NvError::FlagsMismatch { expected: 0b010, found: 0b001 }));
+
+        let err = NvList::try_from(&bytes[..bytes.len() / 2]).unwrap_err();
+        assert!(matches!(err.root_cause(), NvError::CorruptBuffer));
+        let err = NvList::try_from(&[][..]).unwrap_err();
+        assert!(matches!(err.root_cause(), NvError::CorruptBuffer));
+    }
+
+    #[test]
+    fn pack_into() {
+        let mut list = NvList::default();
+        list.insert("foo", "bar").unwrap();
+        list.insert_u64_array("txgs", &[1, 2, 3]).unwrap();
+
+        for encoding in [NvEncoding::Native, NvEncoding::Xdr] {
+            let size = list.encoded_size(encoding).unwrap();
+            let mut buf = vec![0xff; size + 16];
+            assert_eq!(size, list.pack_into(&mut buf, encoding).unwrap());
+            assert_eq!(list, NvList::try_from(&buf[..size]).unwrap());
+
+            let packed = list.pack_to_vec(encoding).unwrap();
+            assert_eq!(size, packed.len());
+            assert_eq!(list, NvList::try_from(&packed[..]).unwrap());
+
+            let err = list.pack_into(&mut buf[..size - 1], encoding).unwrap_err();
+            assert!(matches!(
+                err,
+                NvError::BufferTooSmall { required, available } if required == size && available == size - 1
+            ));
+        }
+    }
+
+    #[test]
+    fn read_write_stream() {
+        let mut child = NvList::default();
+        child.insert("guid", 2u64).unwrap();
+        let mut tree = NvList::default();
+        tree.insert("type", "mirror").unwrap();
+        tree.insert_nvlist_array("children", &[child.clone(), NvList::default(), child]).unwrap();
+        let mut config = NvList::default();
+        config.insert("vdev_tree", &tree).unwrap();
+        config.insert("txg", 4u64).unwrap();
+
+        for encoding in [NvEncoding::Native, NvEncoding::Xdr] {
+            let mut stream = Vec::new();
+            config.write_to(&mut stream, encoding).unwrap();
+            tree.write_to(&mut stream, encoding).unwrap();
+
+            let mut reader = &stream[..];
+            assert_eq!(config, NvList::read_from(&mut reader).unwrap());
+            assert_eq!(tree, NvList::read_from(&mut reader).unwrap());
+            assert!(reader.is_empty());
+
+            let lists: Vec<_> =
+                PackedNvListReader::new(&stream[..]).collect::<NvResult<_>>().unwrap();
+            assert_eq!(vec![config.clone(), tree.clone()], lists);
+
+            let truncated = &stream[..stream.len() - 1];
+            let mut reader = PackedNvListReader::new(truncated);
+            assert!(reader.next().unwrap().is_ok());
+            assert!(matches!(reader.next(), Some(Err(NvError::Io(_)))));
+            assert!(reader.next().is_none());
+        }
+
+        assert!(NvList::read_from(&[][..]).is_err());
+        assert!(NvList::read_from(&[7, 1, 0, 0, 0][..]).is_err());
+    }
+
+    #[test]
+    fn json() {
+        let mut child = NvList::default();
+        child.insert("guid", 2u64).unwrap();
+        let mut list = NvList::new(NvFlag::None).unwrap();
+        list.insert_boolean("flag").unwrap();
+        list.insert("bool", false).unwrap();
+        list.insert("i8", -1i8).unwrap();
+        list.insert_hrtime("hrtime", -1).unwrap();
+        list.insert_double("double", 1.5).unwrap();
+        list.insert("escaped", "\"q\"\n\u{1}\u{e9}\u{1f600}/").unwrap();
+        list.insert("nested", &child).unwrap();
+        list.insert("empty", &NvList::default()).unwrap();
+        list.insert_nvlist_array("children", &[child.clone(), child]).unwrap();
+        list.insert_string_array("names", ["a", "b"]).unwrap();
+        list.insert_boolean_array("bools", &[true]).unwrap();
+        list.insert_u8_array("none", &[]).unwrap();
+
+        let expected = concat!(
+            r#"{"flag":true,"bool":false,"i8":-1,"hrtime":18446744073709551615,"#,
+            r#""double":1.500000,"escaped":"\"q\"\n\u0001\u00e9😀/","nested":{"guid":2},"#,
+            r#""empty":{},"children":[{"guid":2},{"guid":2}],"names":["a","b"],"#,
+            r#""bools":[true],"none":[]}"#,
+        );
+        assert_eq!(expected, list.to_json_string().unwrap());
+
+        let mut out = Vec::new();
+        list.write_json(&mut out).unwrap();
+        assert_eq!(expected.as_bytes(), &out[..]);
+
+        let list = list.select(&["i8", "nested", "empty", "names"]).unwrap();
+        let expected = r#"{
+  "i8": -1,
+  "nested": {
+    "guid": 2
+  },
+  "empty": {},
+  "names": [
+    "a",
+    "b"
+  ]
+}"#;
+        assert_eq!(expected, list.to_json_string_pretty().unwrap());
+        assert_eq!("{}", NvList::default().to_json_string_pretty().unwrap());
+    }
+
+    #[test]
+    fn from_json() {
+        let json = r#"{
+            "name": "tank",
+            "vdev_tree": {"ashift": 12, "children": [{"guid": 1}, {"guid": 2}]},
+            "readonly": null,
+            "offset": -4,
+            "ratio": 1.5e0,
+            "txgs": [1, -2],
+            "paths": ["a", "\u00e9\ud83d\ude00"],
+            "empty": []
+        }"#;
+        let hints = TypeHints::new()
+            .hint("vdev_tree.ashift", DataType::Uint32)
+            .hint("vdev_tree.children.guid", DataType::Hrtime)
+            .hint("readonly", DataType::Boolean);
+        let list = NvList::from_json(json, &hints).unwrap();
+        let types: Vec<_> = list
+            .iter()
+            .map(|pair| (pair.key().to_str().unwrap().to_owned(), pair.data_type()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("name".to_owned(), DataType::String),
+                ("vdev_tree".to_owned(), DataType::NvList),
+                ("readonly".to_owned(), DataType::Boolean),
+                ("offset".to_owned(), DataType::Int64),
+                ("ratio".to_owned(), DataType::Double),
+                ("txgs".to_owned(), DataType::Int64Array),
+                ("paths".to_owned(), DataType::StringArray),
+                ("empty".to_owned(), DataType::Uint64Array),
+            ],
+            types
+        );
+        let vdev_tree = list.get_nvlist("vdev_tree").unwrap();
+        assert_eq!(12, vdev_tree.get_u32("ashift").unwrap());
+        let children = vdev_tree.get_nvlist_array("children").unwrap();
+        assert_eq!(Value::Hrtime(2), children[1].get_pair("guid").unwrap().value());
+        assert_eq!(vec!["a", "\u{e9}\u{1f600}"], list.get_string_array("paths").unwrap());
+
+        let err = |json: &str, hints: &TypeHints| match NvList::from_json(json, hints) {
+            Err(NvError::Parse { line, column, .. }) => (line, column),
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!((2, 8), err("{\"a\": 1,\n  \"b\": tru}", &TypeHints::new()));
+        assert_eq!((1, 7), err(r#"{"a": null}"#, &TypeHints::new()));
+        assert_eq!((1, 11), err(r#"{"a": [1, "b"]}"#, &TypeHints::new()));
+        assert_eq!((1, 7), err(r#"{"a": 256}"#, &TypeHints::new().hint("a", DataType::Uint8)));
+        assert_eq!((1, 1), err("[]", &TypeHints::new()));
+        assert_eq!((1, 4), err("{} {}", &TypeHints::new()));
+        assert_eq!((1, 258), err(&"[".repeat(2000), &TypeHints::new()));
+    }
+
+    #[test]
+    fn typed_json() {
+        let mut child = NvList::default();
+        child.insert("guid", 2u64).unwrap();
+        let mut list = NvList::default();
+        list.insert_boolean("flag").unwrap();
+        list.insert("byte", Value::Byte(255)).unwrap();
+        list.insert("i8", -1i8).unwrap();
+        list.insert("u16", 65535u16).unwrap();
+        list.insert_hrtime("hrtime", -1).unwrap();
+        list.insert_double("double", 0.1).unwrap();
+        list.insert_double("inf", f64::NEG_INFINITY).unwrap();
+        list.insert("escaped", "\"q\"\n\u{1}\u{e9}\u{1f600}").unwrap();
+        list.insert("nested", &child).unwrap();
+        list.insert_nvlist_array("children", &[child.clone(), child]).unwrap();
+        list.insert_string_array("names", ["a", "b"]).unwrap();
+        list.insert_i32_array("none", &[]).unwrap();
+
+        let options = JsonOptions { typed: true, ..Default::default() };
+        let json = list.to_json_string_with(options).unwrap();
+        assert!(json.starts_with(r#"{"flag":{"boolean":true},"byte":{"byte":255},"#));
+        assert_eq!(list, NvList::from_typed_json(&json).unwrap());
+        let pretty = list.to_json_string_with(JsonOptions { pretty: true, typed: true }).unwrap();
+        assert_eq!(list, NvList::from_typed_json(&pretty).unwrap());
+
+        assert!(NvList::from_typed_json(r#"{"a": 1}"#).is_err());
+        assert!(NvList::from_typed_json(r#"{"a": {"uint65": 1}}"#).is_err());
+    }
+
+    #[test]
+    fn text() {
+        let mut child = NvList::default();
+        child.insert("guid", 1u64).unwrap();
+        let mut list = NvList::default();
+        list.insert_boolean("flag").unwrap();
+        list.insert("bool", false).unwrap();
+        list.insert("byte", Value::Byte(255)).unwrap();
+        list.insert("i8", -128i8).unwrap();
+        list.insert("u8", 255u8).unwrap();
+        list.insert("i16", -1i16).unwrap();
+        list.insert("u16", 65535u16).unwrap();
+        list.insert("i32", i32::MIN).unwrap();
+        list.insert("u32", u32::MAX).unwrap();
+        list.insert("i64", i64::MIN).unwrap();
+        list.insert("u64", u64::MAX).unwrap();
+        list.insert_hrtime("hrtime", -1).unwrap();
+        list.insert_double("double", 0.1).unwrap();
+        list.insert_double("inf", f64::NEG_INFINITY).unwrap();
+        list.insert("string", "\"q\"\n\u{1}\u{e9}").unwrap();
+        list.insert("with space", "").unwrap();
+        list.insert("nested", &child).unwrap();
+        list.insert("empty", &NvList::default()).unwrap();
+        list.insert_boolean_array("bools", &[true, false]).unwrap();
+        list.insert_byte_array("bytes", &[1, 2]).unwrap();
+        list.insert_i8_array("i8s", &[-1]).unwrap();
+        list.insert_u8_array("u8s", &[]).unwrap();
+        list.insert_i16_array("i16s", &[-1]).unwrap();
+        list.insert_u16_array("u16s", &[1]).unwrap();
+        list.insert_i32_array("i32s", &[-1]).unwrap();
+        list.insert_u32_array("u32s", &[1]).unwrap();
+        list.insert_i64_array("i64s", &[-1]).unwrap();
+        list.insert_u64_array("u64s", &[1, 2]).unwrap();
+        list.insert_string_array("strings", ["a", "b"]).unwrap();
+        list.insert_nvlist_array("children", &[child.clone(), child]).unwrap();
+        list.insert_nvlist_array("no_children", &[]).unwrap();
+
+        let expected = r#"flag: boolean
+bool: boolean_value = false
+byte: byte = 255
+i8: int8 = -128
+u8: uint8 = 255
+i16: int16 = -1
+u16: uint16 = 65535
+i32: int32 = -2147483648
+u32: uint32 = 4294967295
+i64: int64 = -9223372036854775808
+u64: uint64 = 18446744073709551615
+hrtime: hrtime = -1
+double: double = 0.1
+inf: double = -inf
+string: string = "\"q\"\n\u{1}é"
+"with space": string = ""
+nested: nvlist = {
+  guid: uint64 = 1
+}
+empty: nvlist = {}
+bools: boolean_value[] = [true, false]
+bytes: byte[] = [1, 2]
+i8s: int8[] = [-1]
+u8s: uint8[] = []
+i16s: int16[] = [-1]
+u16s: uint16[] = [1]
+i32s: int32[] = [-1]
+u32s: uint32[] = [1]
+i64s: int64[] = [-1]
+u64s: uint64[] = [1, 2]
+strings: string[] = ["a", "b"]
+children: nvlist[] = [
+  {
+    guid: uint64 = 1
+  },
+  {
+    guid: uint64 = 1
+  }
+]
+no_children: nvlist[] = []
+"#;
+        assert_eq!(expected, text::to_string(&list).unwrap());
+        assert_eq!(list, text::from_str(expected).unwrap());
+        assert_eq!("", text::to_string(&NvList::default()).unwrap());
+
+        // Bytes that aren't valid UTF-8 are escaped and read back as they were.
+        let mut raw = NvList::default();
+        raw.insert_string(&b"name\xff"[..], &b"caf\xe9 \xf0\x9f"[..]).unwrap();
+        raw.insert_string_array("paths", [&b"/dev/\xfe"[..], &b"ok"[..]]).unwrap();
+        let expected = r#""name\xff": string = "caf\xe9 \xf0\x9f"
+paths: string[] = ["/dev/\xfe", "ok"]
+"#;
+        assert_eq!(expected, text::to_string(&raw).unwrap());
+        let parsed = text::from_str(expected).unwrap();
+        assert_eq!(c"caf\xe9 \xf0\x9f", parsed.get_cstr(&b"name\xff"[..]).unwrap());
+        assert_eq!(raw, parsed);
+
+        let edited = r#"
+            # Hand-written, with comments.
+            guid: uint64 = 0xff # trailing comment
+            offset:int64=-0x10
+            nested: nvlist = { a: uint8 = 1 b: boolean }
+            names: string[] = [
+                "a",
+                "b", # trailing comma
+            ]
+        "#;
+        let parsed = text::from_str(edited).unwrap();
+        assert_eq!(255, parsed.get_u64("guid").unwrap());
+        assert_eq!(-16, parsed.get_i64("offset").unwrap());
+        assert_eq!(DataType::Boolean, parsed.get_nvlist("nested").unwrap().type_of("b").unwrap());
+        assert_eq!(vec!["a", "b"], parsed.get_string_array("names").unwrap());
+
+        let err = |text: &str| match text::from_str(text) {
+            Err(NvError::Parse { line, column, .. }) => (line, column),
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!((2, 12), err("a: uint8 = 1\nb: uint8 = 256"));
+        assert_eq!((1, 4), err("a: uint65 = 1"));
+        assert_eq!((1, 4), err("a: boolean[] = []"));
+        assert_eq!((2, 1), err("a: boolean\na: boolean"));
+        assert_eq!((1, 15), err("a: string = \"b"));
+        assert_eq!((1, 14), err(r#"a: string = "\x00""#));
+        assert_eq!((1, 14), err(r#"a: string = "\xg0""#));
+        assert_eq!((1, 14), err("a: nvlist = {"));
+        assert_eq!((1, 18), err("a: uint8[] = [1 2]"));
+        assert_eq!((1, 11), err("a: int8 = --1"));
+        assert_eq!((1, 12), err("a: int64 = 0x-5"));
+        assert_eq!((1, 12), err("a: int64 = 0x+5"));
+        assert_eq!((1, 12), err("a: int64 = -0x-5"));
+        assert_eq!((1, 12), err("a: int64 = +5"));
+    }
+
+    #[test]
+    fn display() {
+        let mut child = NvList::default();
+        child.insert("guid", 10u64).unwrap();
+        let mut tree = NvList::default();
+        tree.insert("type", "root").unwrap();
+        tree.insert_nvlist_array("children", &[child.clone(), child]).unwrap();
+        let mut list = NvList::default();
+        list.insert_boolean("flag").unwrap();
+        list.insert("readonly", false).unwrap();
+        list.insert("byte", Value::Byte(7)).unwrap();
+        list.insert("i8", -1i8).unwrap();
+        list.insert("u32", 255u32).unwrap();
+        list.insert_hrtime("hrtime", 16).unwrap();
+        list.insert("name", "tank").unwrap();
+        list.insert("vdev_tree", &tree).unwrap();
+        list.insert_boolean_array("bools", &[true, false]).unwrap();
+        list.insert_u64_array("txgs", &[1, 255]).unwrap();
+        list.insert_string_array("names", ["a", "b"]).unwrap();
+
+        let expected = "\
+flag
+readonly: false
+byte: 7
+i8: -1
+u32: 255
+bad config type 18 for hrtime
+name: 'tank'
+vdev_tree:
+    type: 'root'
+    children[0]:
+        guid: 10
+    children[1]:
+        guid: 10
+bools: [true, false]
+txgs[0]: 1
+txgs[1]: 255
+names[0]: 'a'
+names[1]: 'b'
+";
+        assert_eq!(expected, list.to_string());
+        assert_eq!(expected, format!("{}", &*list));
+
+        let options = DisplayOptions { indent: 2, step: 1, tabs: true, ..DisplayOptions::dump() };
+        let expected = "\t\ttype: 'root'\n\t\tchildren[0]:\n\t\t\tguid: 10\n\t\tchildren[1]:\n\t\t\tguid: 10\n";
+        assert_eq!(expected, tree.display_with(options).to_string());
+
+        let expected = "\
+nvlist version: 0
+\tflag = 1
+\treadonly = 0
+\tbyte = 0x07
+\ti8 = -1
+\tu32 = 0xff
+\thrtime = 0x10
+\tname = tank
+\tvdev_tree = (embedded nvlist)
+\tnvlist version: 0
+\t\ttype = root
+\t\tchildren = (array of embedded nvlists)
+\t\t(start children[0])
+\t\tnvlist version: 0
+\t\t\tguid = 0xa
+\t\t(end children[0])
+\t\t(start children[1])
+\t\tnvlist version: 0
+\t\t\tguid = 0xa
+\t\t(end children[1])
+
+\t(end vdev_tree)
+
+\tbools = 1 0
+\ttxgs = 0x1 0xff
+\tnames = a b
+";
+        assert_eq!(expected, list.display_with(DisplayOptions::print()).to_string());
+
+        let options = DisplayOptions { tabs: false, step: 4, ..DisplayOptions::print() };
+        let expected =
+            "nvlist version: 0\n    type = root\n    children = (array of embedded nvlists)\n";
+        let empty_children = {
+            let mut tree = tree.clone();
+            tree.insert_nvlist_array("children", &[]).unwrap();
+            tree
+        };
+        assert_eq!(format!("{}\n", expected), empty_children.display_with(options).to_string());
+    }
+
+    #[test]
+    fn printer() {
+        let mut vdev = NvList::default();
+        vdev.insert("guid", 0xabcdu64).unwrap();
+        vdev.insert_u64_array("vdev_stats", &[1, 20, 300]).unwrap();
+        let mut list = NvList::default();
+        list.insert("guid", 255u64).unwrap();
+        list.insert("txg", 4u64).unwrap();
+        list.insert("vdev_tree", &vdev).unwrap();
+        list.insert("features", &NvList::default()).unwrap();
+
+        let printer = Printer::new()
+            .indent(1, 2)
+            .tabs(false)
+            .on_key("guid", Printer::hex)
+            .on_type(DataType::Uint64, |f, _, value| write!(f, "#{:?}", value))
+            .on_key("vdev_stats", |f, _, value| {
+                if let Value::Uint64Array(stats) = value {
+                    for (label, stat) in ["ops", "bytes", "errors"].iter().zip(stats) {
+                        write!(f, "\n{:<6} {}", label, stat)?;
+                    }
+                }
+                Ok(())
+            })
+            .on_key("features", |f, _, _| f.write_str("(hidden)"));
+        let expected = "\
+ nvlist version: 0
+   guid = 0xff
+   txg = #Uint64(4)
+   vdev_tree = (embedded nvlist)
+   nvlist version: 0
+     guid = 0xabcd
+     vdev_stats = \n     ops    1
+     bytes  20
+     errors 300
+
+   (end vdev_tree)
+
+   features = (hidden)
+";
+        assert_eq!(expected, printer.render_to_string(&list));
+
+        let printer = Printer::with_options(DisplayOptions::dump())
+            .on_key("guid", Printer::hex)
+            .on_type(DataType::Uint64Array, Printer::hex);
+        let mut out = String::new();
+        printer.render(&vdev, &mut out).unwrap();
+        assert_eq!("guid: 0xabcd\nvdev_stats: [0x1, 0x14, 0x12c]\n", out);
+    }
+
+    #[test]
+    fn canonical() {
+        let mut disk = NvList::default();
+        disk.insert("path", "/dev/da0").unwrap();
+        disk.insert("guid", 1u64).unwrap();
+        let mut left = NvList::default();
+        left.insert("txg", 7u64).unwrap();
+        left.insert("name", "tank").unwrap();
+        left.insert("txg", 7u32).unwrap();
+        left.insert_nvlist_array("children", &[disk.clone()]).unwrap();
+        left.insert("vdev_tree", &disk).unwrap();
+
+        let mut reversed = NvList::new(NvFlag::UniqueName).unwrap();
+        reversed.insert("guid", 1u64).unwrap();
+        reversed.insert("path", "/dev/da0").unwrap();
+        let mut right = NvList::new(NvFlag::None).unwrap();
+        right.insert("vdev_tree", &reversed).unwrap();
+        right.insert_nvlist_array("children", &[reversed]).unwrap();
+        right.insert("txg", 7u32).unwrap();
+        right.insert("name", "tank").unwrap();
+        right.insert("txg", 7u64).unwrap();
+        assert_ne!(left, right);
+
+        let bytes = left.canonical_bytes().unwrap();
+        assert_eq!(bytes, right.canonical_bytes().unwrap());
+        assert_eq!(1, bytes[1]);
+        assert_eq!(left.content_hash().unwrap(), right.content_hash().unwrap());
+        let unpacked = NvList::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(NvFlag::None, unpacked.flags());
+
+        left.canonicalize().unwrap();
+        right.canonicalize().unwrap();
+        assert_eq!(left, right);
+        assert_eq!(left, unpacked);
+        assert_eq!(NvFlag::None, right.flags());
+        assert_eq!(NvFlag::UniqueName, right.get_nvlist_ref("vdev_tree").unwrap().flags());
+        let names: Vec<_> =
+            left.iter().map(|pair| pair.key().to_str().unwrap().to_owned()).collect();
+        assert_eq!(["children", "name", "txg", "txg", "vdev_tree"], names.as_slice());
+        assert_eq!(DataType::Uint32, left.iter().nth(2).unwrap().data_type());
+        let nested = left.get_nvlist("vdev_tree").unwrap();
+        assert_eq!("guid", nested.iter().next().unwrap().key().to_str().unwrap());
+
+        let mut left = NvList::new(NvFlag::None).unwrap();
+        left.insert("guid", 2u64).unwrap();
+        left.insert("guid", 1u64).unwrap();
+        let mut right = NvList::new(NvFlag::None).unwrap();
+        right.insert("guid", 1u64).unwrap();
+        right.insert("guid", 2u64).unwrap();
+        assert_eq!(left.canonical_bytes().unwrap(), right.canonical_bytes().unwrap());
+
+        right.insert("guid", 3u64).unwrap();
+        assert_ne!(left.content_hash().unwrap(), right.content_hash().unwrap());
+    }
+
+    /// Run `op` with `allocator` failing after 0, 1, 2... allocations until it succeeds, checking
+    /// that every failure is reported as running out of memory.
+    fn exhaust<T>(allocator: &FailingAllocator, mut op: impl FnMut() -> NvResult<T>) -> T {
+        for count in 0.. {
+            allocator.fail_after(count);
+            match op() {
+                Ok(ret) => {
+                    allocator.disarm();
+                    assert!(count > 0, "no allocation to fail");
+                    return ret;
+                },
+                Err(err) => assert!(matches!(err.root_cause(), NvError::OutOfMemory), "{}", err),
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn out_of_memory() {
+        let allocator = FailingAllocator::new();
+        allocator.fail_after(0);
+        let err = NvList::new_in(NvFlag::UniqueNameType, allocator.clone()).unwrap_err();
+        assert!(matches!(err, NvError::OutOfMemory));
+        let mut list =
+            exhaust(&allocator, || NvList::new_in(NvFlag::UniqueNameType, allocator.clone()));
+        for (idx, value) in every_value().iter().enumerate() {
+            let name = format!("v{}", idx);
+            exhaust(&allocator, || list.insert(name.as_str(), value));
+            assert_eq!(*value, list.iter().last().unwrap().value());
+        }
+        exhaust(&allocator, || list.insert_string("name", "tank"));
+        exhaust(&allocator, || list.insert_u64_array("txgs", &[1, 2, 3]));
+
+        // Copies use the allocator of the list they are made from.
+        let copy = exhaust(&allocator, || list.try_clone());
+        assert_eq!(list, copy);
+        let nested = exhaust(&allocator, || copy.get_nvlist("v13"));
+        assert_eq!(1, nested.get_u8("inner").unwrap());
+        let selected = exhaust(&allocator, || list.select(&["name", "v13"]));
+        assert_eq!(2, selected.len());
+        let mut pair = exhaust(&allocator, || list.get_pair("name"));
+        exhaust(&allocator, || pair.rename("pool"));
+        assert_eq!(c"pool", pair.key());
+        let single = exhaust(&allocator, || NvPair::new_in("guid", 1u64, allocator.clone()));
+        assert_eq!(Value::Uint64(1), single.value());
+        let packed = exhaust(&allocator, || list.pack(NvEncoding::Native));
+        assert_eq!(list, packed.unpack(NvFlag::UniqueNameType).unwrap());
+        let canonical = exhaust(&allocator, || list.canonical_bytes());
+        assert_eq!(list.canonical_bytes().unwrap(), canonical);
+        let redacted = exhaust(&allocator, || list.redact(&Redactor::new().path("name")));
+        assert_eq!("<redacted>", redacted.get_str("name").unwrap());
+        // Values of the records are copied with another allocator, so cloning them doesn't fail.
+        let flat = list.try_clone_in(FailingAllocator::new()).unwrap().flatten(".");
+        let rebuilt =
+            exhaust(&allocator, || NvList::unflatten_in(flat.clone(), ".", allocator.clone()));
+        assert_eq!(list, rebuilt);
+
+        allocator.fail_after(0);
+        let cloned = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| list.clone()));
+        assert!(cloned.is_err());
+        allocator.disarm();
+        assert!(allocator.failures() > 0);
+
+        drop((list, copy, nested, selected, pair, single, packed, redacted, rebuilt));
+        assert_eq!(0, allocator.live());
+    }
+
+    #[test]
+    fn fixed_allocator() {
+        let arena = std::sync::Arc::new(FixedAllocator::new(1 << 16));
+        let mut list = NvList::new_in(NvFlag::UniqueNameType, arena.clone()).unwrap();
+        for (idx, value) in every_value().iter().enumerate() {
+            list.insert(format!("v{}", idx), value).unwrap();
+        }
+        let used = arena.used();
+        assert!(used > 0);
+        let nested = list.get_nvlist("v13").unwrap();
+        assert!(arena.used() > used);
+
+        let copy = list.try_clone_in(FailingAllocator::new()).unwrap();
+        assert_eq!(list, copy);
+        let packed = copy.pack(NvEncoding::Native).unwrap();
+        let used = arena.used();
+        let unpacked = BorrowedPackedNvList::from(&packed)
+            .unpack_in(NvFlag::UniqueNameType, arena.clone())
+            .unwrap();
+        assert_eq!(list, unpacked);
+        assert!(arena.used() > used);
+        let err =
+            BorrowedPackedNvList::from(&packed).unpack_in(NvFlag::None, arena.clone()).unwrap_err();
+        assert!(matches!(err, NvError::FlagsMismatch { expected: 0b000, found: 0b010 }));
+
+        let err = list.insert_u8_array("big", &[0; 1 << 16]).unwrap_err();
+        assert!(matches!(err.root_cause(), NvError::OutOfMemory));
+        assert!(arena.used() <= arena.capacity());
+        drop((list, nested, unpacked));
+        assert_eq!(1, std::sync::Arc::strong_count(&arena));
+    }
+
+    #[test]
+    fn threads() {
+        fn send<T: Send>() {}
+        fn sync<T: Send + Sync>() {}
+        send::<NvList>();
+        send::<NvPair>();
+        send::<Value>();
+        send::<SecretNvList>();
+        sync::<PackedNvList>();
+        sync::<FrozenNvList>();
+        sync::<SharedNvList>();
+
+        let mut list = NvList::default();
+        list.insert("txg", 7u64).unwrap();
+        let list = std::thread::spawn(move || {
+            list.insert("name", "tank").unwrap();
+            list
+        })
+        .join()
+        .unwrap();
+
+        let shared = SharedNvList::new(list);
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        let list = shared.read();
+                        assert_eq!(2, list.iter().count());
+                        assert_eq!("tank", list.get_str("name").unwrap());
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let mut updated = shared.clone();
+        assert!(updated.ptr_eq(&shared));
+        updated.make_mut().insert("txg", 8u64).unwrap();
+        assert!(!updated.ptr_eq(&shared));
+        assert_eq!(7, shared.read().get_u64("txg").unwrap());
+        let guard = shared.read();
+        assert_eq!(r#"{"txg": Uint64(7), "name": String("tank")}"#, format!("{:?}", guard));
+        let frozen = guard.to_frozen();
+        drop(guard);
+        assert_eq!(7, frozen.read().get_u64("txg").unwrap());
+        let before = updated.read().get_u64("txg").unwrap();
+        updated.make_mut().insert("txg", 9u64).unwrap();
+        assert_eq!((8, 9), (before, updated.read().get_u64("txg").unwrap()));
+        assert_eq!(9, updated.into_inner().get_u64("txg").unwrap());
+        assert_eq!(7, shared.into_inner().get_u64("txg").unwrap());
+    }
+
+    #[test]
+    fn redactor() {
+        let redactor = Redactor::new()
+            .path("**.hostname")
+            .path("vdev_tree.children.pa*")
+            .path("secrets")
+            .data_type(DataType::Uint8Array);
+        assert!(redactor.hides(&["hostname"], DataType::String));
+        assert!(redactor.hides(&["a", "b", "hostname"], DataType::String));
+        assert!(redactor.hides(&["vdev_tree", "children", "path"], DataType::String));
+        assert!(redactor.hides(&["secrets", "key", "data"], DataType::Uint64));
+        assert!(redactor.hides(&["secrets"], DataType::NvList));
+        assert!(redactor.hides(&["anything"], DataType::Uint8Array));
+        assert!(!redactor.hides(&["vdev_tree", "path"], DataType::String));
+        assert!(!redactor.hides(&["hostnames"], DataType::String));
+        assert!(!redactor.hides(&["a.hostname"], DataType::String));
+
+        let dotted = Redactor::new().path(r"features_for_read.com\.delphix:*").path(r"a\*");
+        assert!(dotted.hides(&["features_for_read", "com.delphix:hole_birth"], DataType::Boolean));
+        assert!(!dotted.hides(&["features_for_read", "comxdelphix:hole_birth"], DataType::Boolean));
+        assert!(dotted.hides(&["a*"], DataType::Uint64));
+        assert!(!dotted.hides(&["ab"], DataType::Uint64));
+
+        let mut child = NvList::default();
+        child.insert("path", "/dev/da0").unwrap();
+        child.insert("guid", 1u64).unwrap();
+        let mut tree = NvList::default();
+        tree.insert_nvlist_array("children", &[child.clone(), child]).unwrap();
+        tree.insert("path", "/").unwrap();
+        let mut secrets = NvList::default();
+        secrets.insert("token", 7u64).unwrap();
+        let mut list = NvList::default();
+        list.insert("hostname", "backup01").unwrap();
+        list.insert("vdev_tree", &tree).unwrap();
+        list.insert("secrets", &secrets).unwrap();
+        list.insert_u8_array("wkeydata", &[1, 2]).unwrap();
+        list.insert_boolean("readonly").unwrap();
+
+        assert_eq!(
+            concat!(
+                r#"{"hostname":"<redacted>","vdev_tree":{"children":[{"path":"<redacted>","#,
+                r#""guid":1},{"path":"<redacted>","guid":1}],"path":"/"},"#,
+                r#""secrets":"<redacted>","wkeydata":"<redacted>","readonly":true}"#
+            ),
+            list.to_json_string_redacted(&redactor).unwrap()
+        );
+        let hidden = Redactor::new().path("hostname").placeholder("***");
+        let mut buf = Vec::new();
+        let typed = JsonOptions { typed: true, ..Default::default() };
+        list.select(&["hostname"]).unwrap().write_json_redacted(&mut buf, typed, &hidden).unwrap();
+        assert_eq!(r#"{"hostname":{"string":"***"}}"#, String::from_utf8(buf).unwrap());
+
+        assert_eq!(
+            concat!(
+                r#"{"hostname": ***, "secrets": NvList({"token": Uint64(7)}), "#,
+                r#""readonly": Bool(true)}"#
+            ),
+            format!("{:?}", list.without(&["vdev_tree", "wkeydata"]).unwrap().redacted(&hidden))
+        );
+        let debug = format!("{:?}", list.redacted(&redactor));
+        assert!(
+            debug.contains(r#""children": NvListArray([{"path": <redacted>, "guid": Uint64(1)}"#)
+        );
+        assert!(debug.contains(r#""secrets": <redacted>"#));
+        assert!(!debug.contains("backup01") && !debug.contains("/dev/da0"));
+        let flags = Redactor::new().path("readonly").path("vdev_tree.children");
+        assert_eq!(
+            concat!(
+                r#"{"hostname":"backup01","vdev_tree":{"children":"<redacted>","path":"/"},"#,
+                r#""secrets":{"token":7},"wkeydata":[1,2],"readonly":"<redacted>"}"#
+            ),
+            list.to_json_string_redacted(&flags).unwrap()
+        );
+        let mut buf = Vec::new();
+        text::write_redacted(&list.select(&["readonly"]).unwrap(), &mut buf, &flags).unwrap();
+        assert_eq!("readonly: boolean = <redacted>\n", String::from_utf8(buf).unwrap());
+
+        let redacted = list.redact(&redactor).unwrap();
+        assert_eq!(NvFlag::None, redacted.flags());
+        assert_eq!("<redacted>", redacted.get_str("wkeydata").unwrap());
+        let nested = redacted.get_nvlist_ref("vdev_tree").unwrap();
+        assert_eq!("/", nested.get_str("path").unwrap());
+        assert_eq!(2, nested.get_nvlist_array("children").unwrap().len());
+        assert_eq!("<redacted>", redacted.get_str("secrets").unwrap());
+        let map = list.into_hashmap_redacted(&redactor).unwrap();
+        assert_eq!(Value::from("<redacted>"), map["hostname"]);
+    }
+
+    #[test]
+    fn secret() {
+        let mut key = NvList::default();
+        key.insert_u8_array("wkeydata", &[0x5a; 32]).unwrap();
+        key.insert("keyformat", "raw").unwrap();
+        let mut list = SecretNvList::new(NvFlag::UniqueName).unwrap();
+        list.expose_mut().insert("crypto", &key).unwrap();
+        list.expose_mut().insert("hidden", true).unwrap();
+        list.expose_mut().insert_boolean("encrypted").unwrap();
+
+        // Debug, JSON and text all show top level names only.
+        assert_eq!(
+            r#"{"crypto":"<redacted>","hidden":"<redacted>","encrypted":"<redacted>"}"#,
+            list.to_json_string().unwrap()
+        );
+        let typed = JsonOptions { typed: true, ..Default::default() };
+        let mut buf = Vec::new();
+        list.write_json_with(&mut buf, typed).unwrap();
+        let json = String::from_utf8(buf).unwrap();
+        assert!(json.contains(r#""crypto":{"nvlist":"<redacted>"}"#), "{}", json);
+        assert!(!json.contains("wkeydata"), "{}", json);
+        assert_eq!(
+            concat!(
+                "crypto: nvlist = <redacted>\n",
+                "hidden: boolean_value = <redacted>\n",
+                "encrypted: boolean = <redacted>\n",
+            ),
+            list.to_text().unwrap()
+        );
+        assert_eq!(
+            r#"{"crypto": <redacted>, "hidden": <redacted>, "encrypted": <redacted>}"#,
+            format!("{:?}", list)
+        );
+
+        let copy = list.clone();
+        assert_eq!(list.expose(), copy.expose());
+        // Memory of the copy is zeroed too.
+        let zeroed = secret::ZEROED.with(|zeroed| zeroed.get());
+        drop(copy);
+        assert!(secret::ZEROED.with(|zeroed| zeroed.get()) > zeroed);
+        let packed = list.pack(NvEncoding::Xdr).unwrap();
+        assert_eq!(format!("SecretBytes {{ len: {}, .. }}", packed.len()), format!("{:?}", packed));
+        let unpacked = SecretNvList::unpack(&packed, NvFlag::UniqueName).unwrap();
+        assert_eq!(list.expose(), unpacked.expose());
+        let crypto = unpacked.expose().get_nvlist_ref("crypto").unwrap();
+        assert_eq!(&[0x5a; 32][..], crypto.get_u8_array("wkeydata").unwrap());
+        let wrapped = SecretNvList::from_list(&key).unwrap();
+        assert_eq!(&*key, wrapped.expose());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let mut child = NvList::default();
+        child.insert("guid", 2u64).unwrap();
+        let mut list = NvList::default();
+        list.insert_boolean("flag").unwrap();
+        list.insert("bool", true).unwrap();
+        list.insert("byte", Value::Byte(7)).unwrap();
+        list.insert("u32", 3u32).unwrap();
+        list.insert_hrtime("hrtime", -1).unwrap();
+        list.insert_double("double", 0.5).unwrap();
+        list.insert("nested", &child).unwrap();
+        list.insert_nvlist_array("children", &[child.clone(), child]).unwrap();
+        list.insert_u8_array("bytes", &[1, 2]).unwrap();
+        list.insert_string_array("names", ["a"]).unwrap();
+
+        let json = serde_json::to_string(&list).unwrap();
+        let expected = concat!(
+            r#"{"flag":"boolean","bool":{"boolean_value":true},"byte":{"byte":7},"#,
+            r#""u32":{"uint32":3},"hrtime":{"hrtime":-1},"double":{"double":0.5},"#,
+            r#""nested":{"nvlist":{"guid":{"uint64":2}}},"#,
+            r#""children":{"nvlist_array":[{"guid":{"uint64":2}},{"guid":{"uint64":2}}]},"#,
+            r#""bytes":{"uint8_array":[1,2]},"names":{"string_array":["a"]}}"#,
+        );
+        assert_eq!(expected, json);
+        assert_eq!(list, serde_json::from_str::<NvList>(&json).unwrap());
+
+        let pairs = list.clone().into_vec();
+        assert_eq!(("u32".to_owned(), Value::Uint32(3)), pairs[3]);
+        let json = serde_json::to_string(&pairs).unwrap();
+        assert_eq!(pairs, serde_json::from_str::<Vec<(String, Value)>>(&json).unwrap());
+
+        let map = list.into_hashmap();
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(map, serde_json::from_str(&json).unwrap());
+
+        assert!(serde_json::from_str::<Value>(r#"{"uint8":256}"#).is_err());
+        assert!(serde_json::from_str::<Value>(r#"{"dontcare":1}"#).is_err());
+        assert!(serde_json::from_str::<NvList>(r#"{"a":{"unknown":null}}"#).is_err());
+    }
 }
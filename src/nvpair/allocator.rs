@@ -0,0 +1,349 @@
+//! Allocators backing the storage of lists, passed to libnvpair as `nv_alloc_t`.
+
+use super::{flags_mismatch, BorrowedPackedNvList, NvFlag, NvList, NvListRef, NvPair, NvTypeOp};
+use crate::{IntoCStr, NvError, NvResult, Operation};
+use nvpair_sys as sys;
+use std::{alloc::Layout,
+          cell::UnsafeCell,
+          os::raw::{c_char, c_void},
+          ptr::{null_mut, NonNull},
+          sync::{atomic::{AtomicUsize, Ordering},
+                 Arc}};
+
+/// Alignment of allocations on the Rust heap, the one `malloc` guarantees.
+const ALIGN: usize = 16;
+
+/// Alignment libnvpair needs for the memory of lists.
+const NV_ALIGN: usize = 8;
+
+/// Memory for lists created with [`NvList::new_in`] and the lists copied from them, including
+/// nested lists. The allocator lives until the last list using it is dropped.
+///
+/// ```
+/// use libnv::nvpair::{NvAllocator, NvFlag, NvList};
+/// use std::{alloc::Layout, ptr::NonNull};
+///
+/// struct Heap;
+///
+/// unsafe impl NvAllocator for Heap {
+///     fn allocate(&self, size: usize) -> Option<NonNull<u8>> {
+///         NonNull::new(unsafe { std::alloc::alloc(Layout::from_size_align(size, 8).ok()?) })
+///     }
+///
+///     unsafe fn deallocate(&self, ptr: NonNull<u8>, size: usize) {
+///         unsafe { std::alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(size, 8).unwrap()) }
+///     }
+/// }
+///
+/// let mut list = NvList::new_in(NvFlag::UniqueNameType, Heap).unwrap();
+/// list.insert("guid", 1u64).unwrap();
+/// ```
+///
+/// # Safety
+///
+/// `allocate` must return `None` or a pointer to `size` bytes aligned to 8 bytes, which nothing
+/// else uses until it's passed to `deallocate`.
+pub unsafe trait NvAllocator: Send + Sync + 'static {
+    /// Allocate `size` bytes, or return `None` if there is no memory left. libnvpair never asks
+    /// for 0 bytes.
+    fn allocate(&self, size: usize) -> Option<NonNull<u8>>;
+
+    /// Free memory returned by [`NvAllocator::allocate`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `allocate` of this allocator for `size` bytes and not
+    /// be used anymore.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, size: usize);
+}
+
+/// Shares an allocator with the caller, e.g. to look at [`FixedAllocator::used`].
+unsafe impl<A: NvAllocator> NvAllocator for Arc<A> {
+    fn allocate(&self, size: usize) -> Option<NonNull<u8>> { (**self).allocate(size) }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, size: usize) {
+        unsafe { (**self).deallocate(ptr, size) }
+    }
+}
+
+/// Allocator carving lists out of a buffer allocated once, like `nv_fixed_ops` of libnvpair.
+/// Freed memory isn't reused, and the buffer is released with the last list using it.
+///
+/// ```
+/// use libnv::{nvpair::{FixedAllocator, NvFlag, NvList}, NvError};
+/// use std::sync::Arc;
+///
+/// let arena = Arc::new(FixedAllocator::new(4096));
+/// let mut list = NvList::new_in(NvFlag::UniqueNameType, arena.clone()).unwrap();
+/// list.insert("guid", 1u64).unwrap();
+/// assert!(arena.used() > 0);
+/// let err = list.insert_u64_array("txgs", &[0; 1024]).unwrap_err();
+/// assert!(matches!(err.root_cause(), NvError::OutOfMemory));
+/// ```
+#[derive(Debug)]
+pub struct FixedAllocator {
+    buf:      NonNull<u8>,
+    capacity: usize,
+    used:     AtomicUsize,
+}
+
+// The buffer is only handed out in disjoint pieces.
+unsafe impl Send for FixedAllocator {}
+unsafe impl Sync for FixedAllocator {}
+
+impl FixedAllocator {
+    /// Allocator with a buffer of `capacity` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer can't be allocated.
+    pub fn new(capacity: usize) -> Self {
+        let layout = fixed_layout(capacity);
+        let buf = NonNull::new(unsafe { std::alloc::alloc(layout) })
+            .unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+        FixedAllocator { buf, capacity, used: AtomicUsize::new(0) }
+    }
+
+    /// Size of the buffer.
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    /// Number of bytes handed out so far, including padding.
+    pub fn used(&self) -> usize { self.used.load(Ordering::SeqCst) }
+}
+
+unsafe impl NvAllocator for FixedAllocator {
+    fn allocate(&self, size: usize) -> Option<NonNull<u8>> {
+        let size = size.checked_next_multiple_of(NV_ALIGN)?;
+        let offset = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(size).filter(|end| *end <= self.capacity)
+            })
+            .ok()?;
+        NonNull::new(unsafe { self.buf.as_ptr().add(offset) })
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _size: usize) {}
+}
+
+impl Drop for FixedAllocator {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.buf.as_ptr(), fixed_layout(self.capacity)) }
+    }
+}
+
+fn fixed_layout(capacity: usize) -> Layout {
+    Layout::from_size_align(capacity.max(1), NV_ALIGN).expect("buffer too large")
+}
+
+/// Allocator that fails on demand, to test how code copes with lists running out of memory.
+/// Clones share the same state.
+///
+/// ```
+/// use libnv::{nvpair::{FailingAllocator, NvFlag, NvList}, NvError};
+///
+/// let allocator = FailingAllocator::new();
+/// let mut list = NvList::new_in(NvFlag::UniqueNameType, allocator.clone()).unwrap();
+/// allocator.fail_after(0);
+/// let err = list.insert("guid", 1u64).unwrap_err();
+/// assert!(matches!(err.root_cause(), NvError::OutOfMemory));
+/// allocator.disarm();
+/// list.insert("guid", 1u64).unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FailingAllocator {
+    state: Arc<FailingState>,
+}
+
+#[derive(Debug)]
+struct FailingState {
+    /// Number of allocations that may still succeed.
+    remaining:   AtomicUsize,
+    allocations: AtomicUsize,
+    failures:    AtomicUsize,
+    live:        AtomicUsize,
+}
+
+impl Default for FailingState {
+    fn default() -> Self {
+        FailingState {
+            remaining:   AtomicUsize::new(usize::MAX),
+            allocations: AtomicUsize::new(0),
+            failures:    AtomicUsize::new(0),
+            live:        AtomicUsize::new(0),
+        }
+    }
+}
+
+impl FailingAllocator {
+    /// Allocator that doesn't fail until [`FailingAllocator::fail_after`] is called.
+    pub fn new() -> Self { FailingAllocator::default() }
+
+    /// Let `count` more allocations succeed and fail every one after them.
+    pub fn fail_after(&self, count: usize) { self.state.remaining.store(count, Ordering::SeqCst); }
+
+    /// Stop failing allocations.
+    pub fn disarm(&self) { self.fail_after(usize::MAX); }
+
+    /// Number of allocations that succeeded so far.
+    pub fn allocations(&self) -> usize { self.state.allocations.load(Ordering::SeqCst) }
+
+    /// Number of allocations that failed so far.
+    pub fn failures(&self) -> usize { self.state.failures.load(Ordering::SeqCst) }
+
+    /// Number of allocations that weren't freed yet.
+    pub fn live(&self) -> usize { self.state.live.load(Ordering::SeqCst) }
+}
+
+unsafe impl NvAllocator for FailingAllocator {
+    fn allocate(&self, size: usize) -> Option<NonNull<u8>> {
+        let state = &self.state;
+        let allowed = state
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |remaining| match remaining {
+                0 => None,
+                usize::MAX => Some(usize::MAX),
+                n => Some(n - 1),
+            })
+            .is_ok();
+        if !allowed {
+            state.failures.fetch_add(1, Ordering::SeqCst);
+            return None;
+        }
+        let ptr = NonNull::new(unsafe { std::alloc::alloc(heap_layout(size)) })?;
+        state.allocations.fetch_add(1, Ordering::SeqCst);
+        state.live.fetch_add(1, Ordering::SeqCst);
+        Some(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, size: usize) {
+        unsafe { std::alloc::dealloc(ptr.as_ptr(), heap_layout(size)) };
+        self.state.live.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub(super) fn heap_layout(size: usize) -> Layout {
+    Layout::from_size_align(size.max(1), ALIGN).expect("allocation too large")
+}
+
+impl NvList {
+    /// Create a list whose storage, and the storage of lists copied from it, comes from
+    /// `allocator`.
+    pub fn new_in<A: NvAllocator>(flags: NvFlag, allocator: A) -> NvResult<Self> {
+        let backend = Backend::new(allocator);
+        let mut raw_list = null_mut();
+        let errno = unsafe { sys::nvlist_xalloc(&mut raw_list, flags as u32, backend.nva()) };
+        if errno != 0 {
+            Err(NvError::from_errno(errno))
+        } else {
+            Ok(unsafe { NvList::from_ptr(raw_list) })
+        }
+    }
+}
+
+impl NvPair {
+    /// Like [`NvPair::new`], but the pair is stored with `allocator`.
+    pub fn new_in<'a, N: IntoCStr<'a>, T: NvTypeOp, A: NvAllocator>(
+        name: N,
+        value: T,
+        allocator: A,
+    ) -> NvResult<Self> {
+        NvPair::with_list(name, value, NvList::new_in(NvFlag::None, allocator)?)
+    }
+}
+
+impl NvListRef {
+    /// Like [`NvListRef::try_clone`], but the copy and the lists copied from it use `allocator`.
+    pub fn try_clone_in<A: NvAllocator>(&self, allocator: A) -> NvResult<NvList> {
+        let backend = Backend::new(allocator);
+        let mut new = null_mut();
+        let errno = unsafe { sys::nvlist_xdup(self.as_ptr(), &mut new, backend.nva()) };
+        if errno != 0 {
+            Err(NvError::from_errno(errno))
+        } else {
+            Ok(unsafe { NvList::from_ptr(new) })
+        }
+    }
+}
+
+impl BorrowedPackedNvList<'_> {
+    /// Like [`BorrowedPackedNvList::unpack`], but the list and the lists copied from it use
+    /// `allocator`.
+    pub fn unpack_in<A: NvAllocator>(&self, flags: NvFlag, allocator: A) -> NvResult<NvList> {
+        let backend = Backend::new(allocator);
+        let buf = self.as_bytes();
+        let mut nvl = null_mut();
+        // libnvpair doesn't write to the buffer when unpacking.
+        let errno = unsafe {
+            sys::nvlist_xunpack(
+                buf.as_ptr() as *mut c_char,
+                buf.len() as _,
+                &mut nvl,
+                backend.nva(),
+            )
+        };
+        if errno != 0 {
+            return Err(NvError::failed(Operation::Unpack, None, errno));
+        }
+        let list = unsafe { NvList::from_ptr(nvl) };
+        if list.flags() != flags {
+            Err(flags_mismatch(flags, list.flags()))
+        } else {
+            Ok(list)
+        }
+    }
+}
+
+/// Allocator handed to libnvpair, which keeps a pointer to it in every list. Every allocation
+/// holds a reference to it, so it's freed with the last one.
+#[repr(C)]
+struct Backend {
+    /// First field, so pointers to it are pointers to the whole backend.
+    nva:       UnsafeCell<sys::nv_alloc_t>,
+    allocator: Box<dyn NvAllocator>,
+}
+
+// `nva` is only read after the backend is created.
+unsafe impl Send for Backend {}
+unsafe impl Sync for Backend {}
+
+impl Backend {
+    fn new<A: NvAllocator>(allocator: A) -> Arc<Backend> {
+        Arc::new(Backend {
+            nva:       UnsafeCell::new(sys::nv_alloc_t { nva_ops: &OPS, nva_arg: null_mut() }),
+            allocator: Box::new(allocator),
+        })
+    }
+
+    /// Pointer to pass to libnvpair. It stays valid while the returned `Arc` or memory allocated
+    /// through it lives.
+    fn nva(&self) -> *mut sys::nv_alloc_t { self.nva.get() }
+}
+
+static OPS: sys::nv_alloc_ops_t = sys::nv_alloc_ops_t {
+    nv_ao_init:  None,
+    nv_ao_fini:  None,
+    nv_ao_alloc: Some(alloc),
+    nv_ao_free:  Some(free),
+    nv_ao_reset: None,
+};
+
+unsafe extern "C" fn alloc(nva: *mut sys::nv_alloc_t, size: sys::size_t) -> *mut c_void {
+    let backend = nva as *const Backend;
+    match unsafe { (*backend).allocator.allocate(size as usize) } {
+        Some(ptr) => {
+            unsafe { Arc::increment_strong_count(backend) };
+            ptr.as_ptr().cast()
+        },
+        None => null_mut(),
+    }
+}
+
+unsafe extern "C" fn free(nva: *mut sys::nv_alloc_t, buf: *mut c_void, size: sys::size_t) {
+    let backend = nva as *const Backend;
+    let Some(buf) = NonNull::new(buf.cast()) else { return };
+    unsafe {
+        (*backend).allocator.deallocate(buf, size as usize);
+        Arc::decrement_strong_count(backend);
+    }
+}
@@ -0,0 +1,116 @@
+//! Canonical form of lists, which doesn't depend on the order pairs were added in.
+
+use super::{visit::{nested_list, nested_lists},
+            DataType, NvEncoding, NvFlag, NvList, NvListRef, NvPairRef};
+use crate::NvResult;
+use nvpair_sys as sys;
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+
+/// Offset of the byte of the header of packed lists telling the endianness of the host that
+/// packed them.
+const ENDIAN_OFFSET: usize = 1;
+
+impl NvList {
+    /// Sort pairs by name and then by type, in nested lists too. Pairs with the same name and
+    /// type, which only lists created with [`NvFlag::None`] can hold, are ordered by their
+    /// encoded value. Lists keep their flags.
+    ///
+    /// ```
+    /// use libnv::nvpair::NvList;
+    ///
+    /// let mut list = NvList::default();
+    /// list.insert("txg", 7u64).unwrap();
+    /// list.insert("name", "tank").unwrap();
+    /// list.canonicalize().unwrap();
+    /// let names: Vec<_> = list.iter().map(|pair| pair.key().to_str().unwrap().to_owned()).collect();
+    /// assert_eq!(["name", "txg"], names.as_slice());
+    /// ```
+    pub fn canonicalize(&mut self) -> NvResult<()> {
+        *self = sorted(self, None)?;
+        Ok(())
+    }
+}
+
+impl NvListRef {
+    /// XDR encoding of the canonical form of the list, see [`NvList::canonicalize`]. Equal lists
+    /// give the same bytes, whatever order their pairs were added in, the flags their lists were
+    /// created with and the host they are packed on. The bytes unpack into lists created with
+    /// [`NvFlag::None`].
+    ///
+    /// ```
+    /// use libnv::nvpair::NvList;
+    ///
+    /// let mut left = NvList::default();
+    /// left.insert("name", "tank").unwrap();
+    /// left.insert("txg", 7u64).unwrap();
+    /// let mut right = NvList::default();
+    /// right.insert("txg", 7u64).unwrap();
+    /// right.insert("name", "tank").unwrap();
+    /// assert_eq!(left.canonical_bytes().unwrap(), right.canonical_bytes().unwrap());
+    /// ```
+    pub fn canonical_bytes(&self) -> NvResult<Vec<u8>> {
+        let mut bytes = sorted(self, Some(NvFlag::None))?.pack_to_vec(NvEncoding::Xdr)?;
+        // XDR doesn't depend on endianness, but libnvpair records the one of the host anyway.
+        bytes[ENDIAN_OFFSET] = 1;
+        Ok(bytes)
+    }
+
+    /// SHA-256 of [`NvListRef::canonical_bytes`].
+    pub fn content_hash(&self) -> NvResult<[u8; 32]> {
+        Ok(Sha256::digest(self.canonical_bytes()?).into())
+    }
+}
+
+/// Copy `list` with sorted pairs. Copies are created with `flags` or, if it's `None`, with the
+/// flags of the lists they are copied from.
+fn sorted(list: &NvListRef, flags: Option<NvFlag>) -> NvResult<NvList> {
+    let mut pairs: Vec<(NvPairRef, Vec<u8>)> = list.iter().map(|pair| (pair, Vec::new())).collect();
+    pairs.sort_by(|(left, _), (right, _)| order(left, right));
+    for run in pairs.chunk_by_mut(|(left, _), (right, _)| order(left, right).is_eq()) {
+        if run.len() > 1 {
+            for (pair, bytes) in run.iter_mut() {
+                *bytes = value_bytes(list, pair)?;
+            }
+            run.sort_by(|(_, left), (_, right)| left.cmp(right));
+        }
+    }
+
+    let mut ret = list.new_like_with(flags.unwrap_or_else(|| list.flags()))?;
+    for (pair, _) in &pairs {
+        insert_sorted(&mut ret, pair, flags)?;
+    }
+    Ok(ret)
+}
+
+/// Order of pairs by name and then by type. Raw types are compared, so types unknown to this
+/// library are ordered too.
+fn order(left: &NvPairRef, right: &NvPairRef) -> Ordering {
+    let raw_type = |pair: &NvPairRef| unsafe { sys::nvpair_type(pair.as_ptr()) };
+    left.key().cmp(right.key()).then_with(|| raw_type(left).cmp(&raw_type(right)))
+}
+
+/// Canonical encoding of a list holding only `pair`, which belongs to `owner`.
+fn value_bytes(owner: &NvListRef, pair: &NvPairRef) -> NvResult<Vec<u8>> {
+    let mut list = owner.new_like_with(NvFlag::None)?;
+    insert_sorted(&mut list, pair, Some(NvFlag::None))?;
+    list.pack_to_vec(NvEncoding::Xdr)
+}
+
+/// Copy `pair` into `list`, sorting nested lists.
+fn insert_sorted(list: &mut NvList, pair: &NvPairRef, flags: Option<NvFlag>) -> NvResult<()> {
+    match pair.data_type() {
+        DataType::NvList => {
+            // Nested lists are owned by the list holding `pair`, which outlives this call.
+            let nested = sorted(unsafe { nested_list(pair) }, flags)?;
+            list.insert_nvlist(pair.key(), &nested)
+        },
+        DataType::NvListArray => {
+            let lists = unsafe { nested_lists(pair) }
+                .map(|nested| sorted(nested, flags))
+                .collect::<NvResult<Vec<_>>>()?;
+            list.insert_nvlist_array(pair.key(), &lists)
+        },
+        _ => list.add_nvpair(pair),
+    }
+}
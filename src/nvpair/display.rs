@@ -0,0 +1,538 @@
+//! Text output compatible with `dump_nvlist` and `nvlist_print` from libnvpair, with hooks like
+//! the ones of `nvlist_prtctl`.
+
+use super::{visit::{nested_list, nested_lists},
+            DataType, NvList, NvListArrayIter, NvListIter, NvListRef, NvPairRef, Value};
+use nvpair_sys as sys;
+use std::{collections::HashMap, fmt, iter::Enumerate};
+
+/// Layout used to display a list.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DisplayStyle {
+    /// `name: value` lines of `dump_nvlist`, as printed by `zdb -l`. Nested lists follow their
+    /// name indented, elements of arrays are printed one per line as `name[0]: value`.
+    Dump,
+    /// `name = value` lines of `nvlist_print`. Every list starts with `nvlist version: 0`,
+    /// unsigned integers are printed in hex and arrays on a single line.
+    Print,
+}
+
+/// Options for [`NvListRef::display_with`].
+///
+/// `indent` is the indentation of the list itself and `step` is added for every level of
+/// nesting, like in `dump_nvlist(list, indent)` and `nvlist_prtctl_setindent`. Both count tabs
+/// when `tabs` is set and spaces otherwise.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DisplayOptions {
+    pub style:  DisplayStyle,
+    pub indent: usize,
+    pub step:   usize,
+    pub tabs:   bool,
+}
+
+impl DisplayOptions {
+    /// Options matching `dump_nvlist(list, 0)`.
+    pub fn dump() -> Self {
+        DisplayOptions { style: DisplayStyle::Dump, indent: 0, step: 4, tabs: false }
+    }
+
+    /// Options matching `nvlist_print`.
+    pub fn print() -> Self {
+        DisplayOptions { style: DisplayStyle::Print, indent: 0, step: 1, tabs: true }
+    }
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self { DisplayOptions::dump() }
+}
+
+/// Displays a list with given options. Returned by [`NvListRef::display_with`].
+pub struct DisplayNvList<'a> {
+    list:    &'a NvListRef,
+    options: DisplayOptions,
+}
+
+impl NvListRef {
+    /// Display the list with given options. `Display` of a list uses [`DisplayOptions::dump`].
+    ///
+    /// ```
+    /// use libnv::nvpair::{DisplayOptions, NvList};
+    ///
+    /// let mut list = NvList::default();
+    /// list.insert("name", "tank").unwrap();
+    /// list.insert_u64_array("txgs", &[1, 2]).unwrap();
+    /// assert_eq!("name: 'tank'\ntxgs[0]: 1\ntxgs[1]: 2\n", list.to_string());
+    ///
+    /// let expected = "nvlist version: 0\n\tname = tank\n\ttxgs = 0x1 0x2\n";
+    /// assert_eq!(expected, list.display_with(DisplayOptions::print()).to_string());
+    /// ```
+    pub fn display_with(&self, options: DisplayOptions) -> DisplayNvList<'_> {
+        DisplayNvList { list: self, options }
+    }
+}
+
+impl fmt::Display for DisplayNvList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Printer::with_options(self.options).render(self.list, f)
+    }
+}
+
+impl fmt::Display for NvListRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display_with(DisplayOptions::default()).fmt(f)
+    }
+}
+
+impl fmt::Display for NvList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { NvListRef::fmt(self, f) }
+}
+
+/// Formats the value of a pair, given the output, the name of the pair and its value.
+type Hook<'a> = Box<dyn Fn(&mut dyn fmt::Write, &str, &Value) -> fmt::Result + 'a>;
+
+/// Renders lists with formatting hooks for chosen names and types, like `nvlist_prtctl` does.
+///
+/// Hooks write only the value, indentation and name are written by the printer. Lines after the
+/// first one written by a hook are indented like the pair, so hooks can render tables. A hook
+/// for the name of a pair wins over a hook for its type. Hooks for `nvlist` and `nvlist_array`
+/// replace the whole nested list.
+///
+/// ```
+/// use libnv::nvpair::{DataType, NvList, Printer, Value};
+///
+/// let mut list = NvList::default();
+/// list.insert("guid", 0xdead_beef_u64).unwrap();
+/// list.insert_u64_array("vdev_stats", &[1, 2]).unwrap();
+///
+/// let printer = Printer::new()
+///     .indent(0, 2)
+///     .tabs(false)
+///     .on_key("guid", Printer::hex)
+///     .on_type(DataType::Uint64Array, |f, _, value| {
+///         if let Value::Uint64Array(stats) = value {
+///             f.write_str("\nreads writes")?;
+///             write!(f, "\n{:>5} {:>6}", stats[0], stats[1])?;
+///         }
+///         Ok(())
+///     });
+/// let expected = concat!(
+///     "nvlist version: 0\n",
+///     "  guid = 0xdeadbeef\n",
+///     "  vdev_stats = \n",
+///     "  reads writes\n",
+///     "      1      2\n",
+/// );
+/// assert_eq!(expected, printer.render_to_string(&list));
+/// ```
+pub struct Printer<'a> {
+    options: DisplayOptions,
+    types:   HashMap<DataType, Hook<'a>>,
+    keys:    HashMap<String, Hook<'a>>,
+}
+
+impl Default for Printer<'_> {
+    fn default() -> Self { Printer::new() }
+}
+
+impl<'a> Printer<'a> {
+    /// Printer with the layout of `nvlist_print`.
+    pub fn new() -> Self { Printer::with_options(DisplayOptions::print()) }
+
+    pub fn with_options(options: DisplayOptions) -> Self {
+        Printer { options, types: HashMap::new(), keys: HashMap::new() }
+    }
+
+    /// Indent the list by `indent` and nested lists by `step` more for every level.
+    pub fn indent(mut self, indent: usize, step: usize) -> Self {
+        self.options.indent = indent;
+        self.options.step = step;
+        self
+    }
+
+    /// Indent with tabs instead of spaces.
+    pub fn tabs(mut self, tabs: bool) -> Self {
+        self.options.tabs = tabs;
+        self
+    }
+
+    /// Format values of pairs of type `data_type` with `hook`.
+    pub fn on_type<F>(mut self, data_type: DataType, hook: F) -> Self
+    where
+        F: Fn(&mut dyn fmt::Write, &str, &Value) -> fmt::Result + 'a,
+    {
+        self.types.insert(data_type, Box::new(hook));
+        self
+    }
+
+    /// Format values of pairs named `key` with `hook`, in any nested list.
+    pub fn on_key<K: Into<String>, F>(mut self, key: K, hook: F) -> Self
+    where
+        F: Fn(&mut dyn fmt::Write, &str, &Value) -> fmt::Result + 'a,
+    {
+        self.keys.insert(key.into(), Box::new(hook));
+        self
+    }
+
+    /// Hook writing integers and elements of integer arrays in hex.
+    pub fn hex(f: &mut dyn fmt::Write, _name: &str, value: &Value) -> fmt::Result {
+        write_hex(f, value)
+    }
+
+    /// Render `list` into `out`.
+    pub fn render<W: fmt::Write>(&self, list: &NvListRef, out: &mut W) -> fmt::Result {
+        let mut out = Output { out, options: self.options, indent: self.options.indent };
+        if self.options.style == DisplayStyle::Print {
+            out.header()?;
+        }
+        let mut stack = vec![Frame::List(list.iter(), End::Root)];
+        while let Some(frame) = stack.last_mut() {
+            match frame {
+                Frame::List(pairs, _) => match pairs.next() {
+                    Some(pair) => {
+                        let name = pair.key().to_string_lossy().into_owned();
+                        let data_type = pair.data_type();
+                        if let Some(hook) =
+                            self.keys.get(&name).or_else(|| self.types.get(&data_type))
+                        {
+                            out.custom(&name, &pair.value(), hook)?;
+                            continue;
+                        }
+                        match data_type {
+                            DataType::NvList => {
+                                out.enter_list(&name, None)?;
+                                // Nested list is owned by `list`, which outlives the walk.
+                                let nested = unsafe { nested_list(&pair) };
+                                stack.push(Frame::List(nested.iter(), End::Pair(name)));
+                            },
+                            DataType::NvListArray => {
+                                out.enter_lists(&name)?;
+                                // Elements are owned by `list`, which outlives the walk.
+                                let lists = unsafe { nested_lists(&pair) };
+                                stack.push(Frame::Array(lists.enumerate(), name));
+                            },
+                            _ => out.pair(&name, &pair)?,
+                        }
+                    },
+                    None => {
+                        if let Some(Frame::List(_, end)) = stack.pop() {
+                            out.leave_list(&end)?;
+                        }
+                    },
+                },
+                Frame::Array(lists, name) => match lists.next() {
+                    Some((idx, nested)) => {
+                        let name = name.clone();
+                        out.enter_list(&name, Some(idx))?;
+                        stack.push(Frame::List(nested.iter(), End::Element(name, idx)));
+                    },
+                    None => {
+                        stack.pop();
+                        out.leave_lists()?;
+                    },
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Render `list` into a new string.
+    pub fn render_to_string(&self, list: &NvListRef) -> String {
+        let mut ret = String::new();
+        self.render(list, &mut ret).expect("writing to a String can't fail");
+        ret
+    }
+}
+
+/// Lists that are being rendered. Kept on the heap, so deep nesting can't overflow the stack.
+enum Frame<'a> {
+    List(NvListIter<'a>, End),
+    Array(Enumerate<NvListArrayIter<'a>>, String),
+}
+
+/// What a list being rendered is.
+enum End {
+    Root,
+    Pair(String),
+    Element(String, usize),
+}
+
+/// Write an integer in hex, or an array of them element by element.
+fn write_hex(f: &mut dyn fmt::Write, value: &Value) -> fmt::Result {
+    if let Some(values) = elements(value) {
+        f.write_str("[")?;
+        for (idx, value) in values.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(", ")?;
+            }
+            write_hex(f, value)?;
+        }
+        return f.write_str("]");
+    }
+    match value {
+        Value::Byte(v) | Value::Uint8(v) => write!(f, "0x{:x}", v),
+        Value::Int8(v) => write!(f, "0x{:x}", v),
+        Value::Int16(v) => write!(f, "0x{:x}", v),
+        Value::Uint16(v) => write!(f, "0x{:x}", v),
+        Value::Int32(v) => write!(f, "0x{:x}", v),
+        Value::Uint32(v) => write!(f, "0x{:x}", v),
+        Value::Int64(v) | Value::Hrtime(v) => write!(f, "0x{:x}", v),
+        Value::Uint64(v) => write!(f, "0x{:x}", v),
+        other => write!(f, "{:?}", other),
+    }
+}
+
+/// Elements of an array value, `None` for other values.
+fn elements(value: &Value) -> Option<Vec<Value>> {
+    fn map<T: Copy>(values: &[T], f: fn(T) -> Value) -> Option<Vec<Value>> {
+        Some(values.iter().copied().map(f).collect())
+    }
+    match value {
+        Value::BoolArray(v) => map(v, Value::Bool),
+        Value::ByteArray(v) => map(v, Value::Byte),
+        Value::Int8Array(v) => map(v, Value::Int8),
+        Value::Uint8Array(v) => map(v, Value::Uint8),
+        Value::Int16Array(v) => map(v, Value::Int16),
+        Value::Uint16Array(v) => map(v, Value::Uint16),
+        Value::Int32Array(v) => map(v, Value::Int32),
+        Value::Uint32Array(v) => map(v, Value::Uint32),
+        Value::Int64Array(v) => map(v, Value::Int64),
+        Value::Uint64Array(v) => map(v, Value::Uint64),
+        Value::StringArray(v) => Some(v.iter().cloned().map(Value::String).collect()),
+        _ => None,
+    }
+}
+
+struct Output<'a, W> {
+    out:     &'a mut W,
+    options: DisplayOptions,
+    /// Indentation of the list being rendered.
+    indent:  usize,
+}
+
+impl<W: fmt::Write> Output<'_, W> {
+    /// Indentation of a line. Pairs printed by `nvlist_print` are indented one more level than
+    /// their list.
+    fn padding(&self, pair: bool) -> String {
+        let DisplayOptions { style, step, tabs, .. } = self.options;
+        let width = match style {
+            DisplayStyle::Print if pair && tabs => self.indent + 1,
+            DisplayStyle::Print if pair => self.indent + step,
+            _ => self.indent,
+        };
+        (if tabs { "\t" } else { " " }).repeat(width)
+    }
+
+    fn pad(&mut self, pair: bool) -> fmt::Result {
+        let padding = self.padding(pair);
+        self.out.write_str(&padding)
+    }
+
+    fn header(&mut self) -> fmt::Result {
+        self.pad(false)?;
+        self.out.write_str("nvlist version: 0\n")
+    }
+
+    /// Start a pair: indentation and name.
+    fn name(&mut self, name: &str) -> fmt::Result {
+        self.pad(true)?;
+        match self.options.style {
+            DisplayStyle::Dump => write!(self.out, "{}: ", name),
+            DisplayStyle::Print => write!(self.out, "{} = ", name),
+        }
+    }
+
+    fn custom(&mut self, name: &str, value: &Value, hook: &Hook) -> fmt::Result {
+        self.name(name)?;
+        let prefix = self.padding(true);
+        hook(
+            &mut Indented { out: &mut *self.out, prefix: &prefix, line_start: false },
+            name,
+            value,
+        )?;
+        self.out.write_str("\n")
+    }
+
+    /// Render a pair that isn't a list or an array of lists.
+    fn pair(&mut self, name: &str, pair: &NvPairRef) -> fmt::Result {
+        let data_type = pair.data_type();
+        let value = pair.value();
+        match (self.options.style, data_type) {
+            (DisplayStyle::Dump, DataType::Boolean) => {
+                self.pad(true)?;
+                writeln!(self.out, "{}", name)
+            },
+            (DisplayStyle::Print, DataType::Boolean) => {
+                self.name(name)?;
+                self.out.write_str("1\n")
+            },
+            (DisplayStyle::Dump, DataType::Hrtime | DataType::Double | DataType::Unknown) => {
+                // `dump_nvlist` doesn't know these types.
+                self.pad(true)?;
+                let raw = unsafe { sys::nvpair_type(pair.as_ptr()) };
+                writeln!(self.out, "bad config type {} for {}", raw as i32, name)
+            },
+            (DisplayStyle::Print, DataType::Unknown) => {
+                let raw = unsafe { sys::nvpair_type(pair.as_ptr()) };
+                write!(self.out, " unknown data type ({})", raw as i32)
+            },
+            (DisplayStyle::Dump, DataType::BooleanArray) => {
+                self.name(name)?;
+                self.out.write_str("[")?;
+                for (idx, value) in elements(&value).unwrap_or_default().iter().enumerate() {
+                    if idx > 0 {
+                        self.out.write_str(", ")?;
+                    }
+                    self.dump_value(value)?;
+                }
+                // `dump_nvlist` doesn't close empty boolean arrays, they are closed here to keep
+                // the next pair on its own line.
+                self.out.write_str("]\n")
+            },
+            (DisplayStyle::Dump, _) if data_type.is_array() => {
+                for (idx, value) in elements(&value).unwrap_or_default().iter().enumerate() {
+                    self.pad(true)?;
+                    write!(self.out, "{}[{}]: ", name, idx)?;
+                    self.dump_value(value)?;
+                    self.out.write_str("\n")?;
+                }
+                Ok(())
+            },
+            (DisplayStyle::Print, _) if data_type.is_array() => {
+                for (idx, value) in elements(&value).unwrap_or_default().iter().enumerate() {
+                    if idx == 0 {
+                        self.name(name)?;
+                    } else {
+                        self.out.write_str(" ")?;
+                    }
+                    self.print_value(value)?;
+                }
+                self.out.write_str("\n")
+            },
+            (DisplayStyle::Dump, _) => {
+                self.name(name)?;
+                self.dump_value(&value)?;
+                self.out.write_str("\n")
+            },
+            (DisplayStyle::Print, _) => {
+                self.name(name)?;
+                self.print_value(&value)?;
+                self.out.write_str("\n")
+            },
+        }
+    }
+
+    /// Format a value the way `dump_nvlist` does.
+    fn dump_value(&mut self, value: &Value) -> fmt::Result {
+        match value {
+            Value::Bool(v) => write!(self.out, "{}", v),
+            Value::Byte(v) | Value::Uint8(v) => write!(self.out, "{}", v),
+            Value::Int8(v) => write!(self.out, "{}", v),
+            Value::Int16(v) => write!(self.out, "{}", v),
+            Value::Uint16(v) => write!(self.out, "{}", v),
+            Value::Int32(v) => write!(self.out, "{}", v),
+            Value::Uint32(v) => write!(self.out, "{}", v),
+            Value::Int64(v) => write!(self.out, "{}", v),
+            Value::Uint64(v) => write!(self.out, "{}", v),
+            Value::String(v) => write!(self.out, "'{}'", v),
+            _ => unreachable!("not printed as a value"),
+        }
+    }
+
+    /// Format a value the way `nvlist_print` does.
+    fn print_value(&mut self, value: &Value) -> fmt::Result {
+        match value {
+            Value::Bool(v) => write!(self.out, "{}", *v as i32),
+            Value::Byte(v) => write!(self.out, "0x{:02x}", v),
+            Value::Int8(v) => write!(self.out, "{}", v),
+            Value::Uint8(v) => write!(self.out, "0x{:x}", v),
+            Value::Int16(v) => write!(self.out, "{}", v),
+            Value::Uint16(v) => write!(self.out, "0x{:x}", v),
+            Value::Int32(v) => write!(self.out, "{}", v),
+            Value::Uint32(v) => write!(self.out, "0x{:x}", v),
+            Value::Int64(v) => write!(self.out, "{}", v),
+            Value::Uint64(v) => write!(self.out, "0x{:x}", v),
+            Value::Hrtime(v) => write!(self.out, "0x{:x}", v),
+            // libnvpair uses `0x%f`.
+            Value::Double(v) if v.is_nan() => {
+                self.out.write_str(if v.is_sign_negative() { "0x-nan" } else { "0xnan" })
+            },
+            Value::Double(v) => write!(self.out, "0x{:.6}", v),
+            Value::String(v) => self.out.write_str(v),
+            _ => unreachable!("not printed as a value"),
+        }
+    }
+
+    fn enter_list(&mut self, name: &str, index: Option<usize>) -> fmt::Result {
+        self.pad(true)?;
+        match (self.options.style, index) {
+            (DisplayStyle::Dump, Some(idx)) => writeln!(self.out, "{}[{}]:", name, idx)?,
+            (DisplayStyle::Dump, None) => writeln!(self.out, "{}:", name)?,
+            (DisplayStyle::Print, Some(idx)) => writeln!(self.out, "(start {}[{}])", name, idx)?,
+            (DisplayStyle::Print, None) => writeln!(self.out, "{} = (embedded nvlist)", name)?,
+        }
+        self.indent += self.options.step;
+        if self.options.style == DisplayStyle::Print {
+            self.header()?;
+        }
+        Ok(())
+    }
+
+    fn leave_list(&mut self, end: &End) -> fmt::Result {
+        if let End::Root = end {
+            return Ok(());
+        }
+        self.indent -= self.options.step;
+        if self.options.style == DisplayStyle::Dump {
+            return Ok(());
+        }
+        self.pad(true)?;
+        match end {
+            End::Element(name, idx) => writeln!(self.out, "(end {}[{}])", name, idx),
+            End::Pair(name) => write!(self.out, "(end {})\n\n", name),
+            End::Root => Ok(()),
+        }
+    }
+
+    /// Start an array of lists.
+    fn enter_lists(&mut self, name: &str) -> fmt::Result {
+        match self.options.style {
+            DisplayStyle::Dump => Ok(()),
+            DisplayStyle::Print => {
+                self.name(name)?;
+                self.out.write_str("(array of embedded nvlists)\n")
+            },
+        }
+    }
+
+    fn leave_lists(&mut self) -> fmt::Result {
+        match self.options.style {
+            DisplayStyle::Dump => Ok(()),
+            DisplayStyle::Print => self.out.write_str("\n"),
+        }
+    }
+}
+
+/// Writer that indents every line but the first one.
+struct Indented<'a> {
+    out:        &'a mut dyn fmt::Write,
+    prefix:     &'a str,
+    line_start: bool,
+}
+
+impl fmt::Write for Indented<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (idx, line) in s.split('\n').enumerate() {
+            if idx > 0 {
+                self.out.write_str("\n")?;
+                self.line_start = true;
+            }
+            if !line.is_empty() {
+                if std::mem::replace(&mut self.line_start, false) {
+                    self.out.write_str(self.prefix)?;
+                }
+                self.out.write_str(line)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//! Conversion between nested lists and flat `(path, value)` records.
+
+use super::{invalid_input, DataType, NvAllocator, NvFlag, NvList, NvListRef, Value};
+use crate::NvResult;
+use std::mem;
+
+/// Escapes separators and itself in names, and marks paths of flags when it ends a path.
+const ESCAPE: char = '\\';
+
+/// Part of a path parsed by [`parse`].
+enum Component {
+    Name(String),
+    Index(usize),
+}
+
+/// Node of a tree built from flat records before it is turned back into lists.
+enum Node {
+    Leaf(Value),
+    /// Pair of type `boolean`, which has no value.
+    Flag,
+    List(Vec<(String, Node)>),
+    /// Elements are either `List` or `Leaf` holding an empty list.
+    Array(Vec<Node>),
+}
+
+impl NvListRef {
+    /// Turn the list into flat records. Every value that isn't a nested list gets a path made of
+    /// names of enclosing lists joined with `separator`. Elements of nvlist arrays use their
+    /// index as a name, i.e. `vdev_tree.children.0.guid`. Empty nested lists and empty nvlist
+    /// arrays are kept as values, so nothing is lost.
+    ///
+    /// Names are escaped with `\` so that [`NvList::unflatten`] can split paths again: it's put
+    /// before every `separator` and `\` in a name, and before names made only of digits, which
+    /// would look like array indexes otherwise. Pairs of type `boolean` carry no value, so they
+    /// become [`Value::Bool`] records whose path ends with an unpaired `\`.
+    ///
+    /// ```
+    /// use libnv::nvpair::{NvList, Value};
+    ///
+    /// let mut child = NvList::default();
+    /// child.insert("guid", 42u64).unwrap();
+    /// let mut tree = NvList::default();
+    /// tree.insert_nvlist_array("children", &[child]).unwrap();
+    /// let mut features = NvList::default();
+    /// features.insert_boolean("com.delphix:hole_birth").unwrap();
+    /// let mut config = NvList::default();
+    /// config.insert("vdev_tree", &tree).unwrap();
+    /// config.insert("features_for_read", &features).unwrap();
+    ///
+    /// let flat = config.flatten(".");
+    /// assert_eq!(
+    ///     vec![
+    ///         (String::from("vdev_tree.children.0.guid"), Value::Uint64(42)),
+    ///         (String::from(r"features_for_read.com\.delphix:hole_birth\"), Value::Bool(true)),
+    ///     ],
+    ///     flat
+    /// );
+    /// assert_eq!(config, NvList::unflatten(flat, ".").unwrap());
+    /// ```
+    pub fn flatten(&self, separator: &str) -> Vec<(String, Value)> {
+        let mut ret = Vec::new();
+        self.flatten_into(None, separator, &mut ret);
+        ret
+    }
+
+    fn flatten_into(&self, prefix: Option<&str>, separator: &str, out: &mut Vec<(String, Value)>) {
+        for pair in self.iter() {
+            let mut path = match prefix {
+                Some(prefix) => format!("{}{}", prefix, separator),
+                None => String::new(),
+            };
+            escape(&pair.key().to_string_lossy(), separator, &mut path);
+            match (pair.borrow_nvlist(), pair.borrow_nvlist_array()) {
+                (Some(nested), _) if !nested.is_empty() => {
+                    nested.flatten_into(Some(&path), separator, out)
+                },
+                (_, Some(lists)) if !lists.is_empty() => {
+                    for (idx, nested) in lists.into_iter().enumerate() {
+                        let path = format!("{}{}{}", path, separator, idx);
+                        if nested.is_empty() {
+                            out.push((path, Value::NvList(nested.to_owned())));
+                        } else {
+                            nested.flatten_into(Some(&path), separator, out);
+                        }
+                    }
+                },
+                _ if pair.data_type() == DataType::Boolean => {
+                    path.push(ESCAPE);
+                    out.push((path, Value::Bool(true)));
+                },
+                _ => out.push((path, pair.value())),
+            }
+        }
+    }
+}
+
+impl NvList {
+    /// Build nested lists from records produced by [`NvListRef::flatten`].
+    ///
+    /// A nested list is created for every path component but the last one, and an nvlist array
+    /// for every unescaped index. Indexes of an array have to follow each other starting from
+    /// `0`. Lists are created with [`NvFlag::UniqueNameType`], so flags of the original nested
+    /// lists are not preserved. `separator` can't be empty or contain `\`.
+    pub fn unflatten<I: IntoIterator<Item = (String, Value)>>(
+        records: I,
+        separator: &str,
+    ) -> NvResult<NvList> {
+        let tree = tree(records, separator)?;
+        build(tree, NvList::new(NvFlag::UniqueNameType)?)
+    }
+
+    /// Like [`NvList::unflatten`], but the lists are allocated with `allocator`.
+    pub fn unflatten_in<I: IntoIterator<Item = (String, Value)>, A: NvAllocator>(
+        records: I,
+        separator: &str,
+        allocator: A,
+    ) -> NvResult<NvList> {
+        let tree = tree(records, separator)?;
+        build(tree, NvList::new_in(NvFlag::UniqueNameType, allocator)?)
+    }
+}
+
+/// Arrange `records` into a tree, see [`NvList::unflatten`].
+fn tree<I: IntoIterator<Item = (String, Value)>>(
+    records: I,
+    separator: &str,
+) -> NvResult<Vec<(String, Node)>> {
+    if separator.is_empty() || separator.contains(ESCAPE) {
+        return Err(invalid_input(format!("invalid separator {:?}", separator)));
+    }
+    let mut root = Vec::new();
+    for (path, value) in records {
+        let (components, flag) = parse(&path, separator);
+        let leaf = match (flag, value) {
+            (false, value) => Node::Leaf(value),
+            (true, Value::Bool(true)) => Node::Flag,
+            (true, _) => return Err(invalid_input(format!("{} holds a value", path))),
+        };
+        if place(&mut root, &components, leaf).is_none() {
+            return Err(invalid_input(format!("{} conflicts with an earlier record", path)));
+        }
+    }
+    Ok(root)
+}
+
+/// Append `name` to `out`, escaped as described in [`NvListRef::flatten`].
+fn escape(name: &str, separator: &str, out: &mut String) {
+    if !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) {
+        out.push(ESCAPE);
+    }
+    let mut rest = name;
+    while let Some(c) = rest.chars().next() {
+        if !separator.is_empty() && rest.starts_with(separator) {
+            out.push(ESCAPE);
+            out.push_str(separator);
+            rest = &rest[separator.len()..];
+        } else {
+            if c == ESCAPE {
+                out.push(ESCAPE);
+            }
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+}
+
+/// Split `path` into components, and tell whether it's the path of a flag.
+fn parse(path: &str, separator: &str) -> (Vec<Component>, bool) {
+    let mut components = Vec::new();
+    let mut name = String::new();
+    // Whether some part of `name` was escaped, so it can't be an index.
+    let mut escaped = false;
+    let mut rest = path;
+    loop {
+        if let Some(after) = rest.strip_prefix(ESCAPE) {
+            let len = match after.chars().next() {
+                None => {
+                    components.push(component(name, escaped));
+                    return (components, true);
+                },
+                Some(_) if after.starts_with(separator) => separator.len(),
+                Some(c) => c.len_utf8(),
+            };
+            name.push_str(&after[..len]);
+            escaped = true;
+            rest = &after[len..];
+        } else if let Some(after) = rest.strip_prefix(separator) {
+            components.push(component(mem::take(&mut name), mem::replace(&mut escaped, false)));
+            rest = after;
+        } else if let Some(c) = rest.chars().next() {
+            name.push(c);
+            rest = &rest[c.len_utf8()..];
+        } else {
+            components.push(component(name, escaped));
+            return (components, false);
+        }
+    }
+}
+
+fn component(name: String, escaped: bool) -> Component {
+    if !escaped && !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()) {
+        if let Ok(idx) = name.parse() {
+            return Component::Index(idx);
+        }
+    }
+    Component::Name(name)
+}
+
+/// Put `leaf` at `path` below `list`. `None` if the path doesn't fit nodes already there.
+fn place(list: &mut Vec<(String, Node)>, path: &[Component], leaf: Node) -> Option<()> {
+    let (Component::Name(name), rest) = path.split_first()? else {
+        return None;
+    };
+    let Some(next) = rest.first() else {
+        list.push((name.clone(), leaf));
+        return Some(());
+    };
+    // Records of the same list usually follow each other, so look from the end.
+    let position = match list.iter().rposition(|(n, _)| n == name) {
+        Some(position) => position,
+        None => {
+            let node = match next {
+                Component::Name(_) => Node::List(Vec::new()),
+                Component::Index(_) => Node::Array(Vec::new()),
+            };
+            list.push((name.clone(), node));
+            list.len() - 1
+        },
+    };
+    match &mut list[position].1 {
+        Node::List(children) => place(children, rest, leaf),
+        Node::Array(elements) => place_element(elements, rest, leaf),
+        Node::Leaf(_) | Node::Flag => None,
+    }
+}
+
+/// Like [`place`], for `path` starting with an index into `elements`.
+fn place_element(elements: &mut Vec<Node>, path: &[Component], leaf: Node) -> Option<()> {
+    let (&Component::Index(idx), rest) = path.split_first()? else {
+        return None;
+    };
+    if rest.is_empty() {
+        return match leaf {
+            Node::Leaf(Value::NvList(_)) if idx == elements.len() => {
+                elements.push(leaf);
+                Some(())
+            },
+            _ => None,
+        };
+    }
+    if idx == elements.len() {
+        elements.push(Node::List(Vec::new()));
+    }
+    match elements.get_mut(idx)? {
+        Node::List(children) => place(children, rest, leaf),
+        _ => None,
+    }
+}
+
+/// Fill the empty `list` with `nodes`. Nested lists use the allocator of `list`.
+fn build(nodes: Vec<(String, Node)>, mut list: NvList) -> NvResult<NvList> {
+    for (name, node) in nodes {
+        match node {
+            Node::Leaf(value) => list.insert(name.as_str(), value)?,
+            Node::Flag => list.insert_boolean(name.as_str())?,
+            Node::List(children) => {
+                let nested = build(children, list.new_like_with(NvFlag::UniqueNameType)?)?;
+                list.insert_nvlist(name.as_str(), &nested)?
+            },
+            Node::Array(elements) => {
+                let lists = elements
+                    .into_iter()
+                    .map(|element| match element {
+                        Node::Leaf(Value::NvList(nested)) => Ok(nested),
+                        Node::List(children) => {
+                            build(children, list.new_like_with(NvFlag::UniqueNameType)?)
+                        },
+                        _ => unreachable!("checked by place_element"),
+                    })
+                    .collect::<NvResult<Vec<_>>>()?;
+                list.insert_nvlist_array(name.as_str(), &lists)?;
+            },
+        }
+    }
+    Ok(list)
+}
@@ -0,0 +1,348 @@
+//! JSON output compatible with `nvlist_print_json` from libnvpair.
+
+use super::{DataType, Flow, KeyPath, NvListRef, Redactor, Segment, Value, Visitor};
+use crate::{NvError, NvResult};
+use std::io::{self, Write};
+
+mod parse;
+
+pub use parse::TypeHints;
+
+impl NvListRef {
+    /// Render the list as JSON. The output is the same as the one of `nvlist_print_json`
+    /// running in a UTF-8 locale.
+    ///
+    /// ```
+    /// use libnv::nvpair::NvList;
+    ///
+    /// let mut list = NvList::default();
+    /// list.insert("name", "tank").unwrap();
+    /// list.insert_u64_array("txgs", &[1, 2]).unwrap();
+    /// assert_eq!(r#"{"name":"tank","txgs":[1,2]}"#, list.to_json_string().unwrap());
+    /// ```
+    pub fn to_json_string(&self) -> NvResult<String> {
+        self.to_json_string_with(JsonOptions::default())
+    }
+
+    /// Like [`NvListRef::to_json_string`], but with every pair on its own line.
+    pub fn to_json_string_pretty(&self) -> NvResult<String> {
+        self.to_json_string_with(JsonOptions { pretty: true, ..Default::default() })
+    }
+
+    /// Write the list as JSON to `writer`. See [`NvListRef::to_json_string`].
+    pub fn write_json<W: Write>(&self, writer: W) -> NvResult<()> {
+        self.write_json_with(writer, JsonOptions::default())
+    }
+
+    /// Write the list as pretty-printed JSON to `writer`. See
+    /// [`NvListRef::to_json_string_pretty`].
+    pub fn write_json_pretty<W: Write>(&self, writer: W) -> NvResult<()> {
+        self.write_json_with(writer, JsonOptions { pretty: true, ..Default::default() })
+    }
+
+    /// Render the list as JSON with given options.
+    pub fn to_json_string_with(&self, options: JsonOptions) -> NvResult<String> {
+        let mut buf = Vec::new();
+        self.write_json_with(&mut buf, options)?;
+        Ok(String::from_utf8(buf).expect("JSON output is UTF-8"))
+    }
+
+    /// Write the list as JSON with given options to `writer`.
+    pub fn write_json_with<W: Write>(&self, writer: W, options: JsonOptions) -> NvResult<()> {
+        JsonWriter::new(writer, options).write(self)
+    }
+}
+
+/// Like [`NvListRef::write_json_with`], but values hidden by `redactor` are written as its
+/// placeholder.
+pub(super) fn write_redacted<W: Write>(
+    list: &NvListRef,
+    writer: W,
+    options: JsonOptions,
+    redactor: &Redactor,
+) -> NvResult<()> {
+    JsonWriter { redactor: Some(redactor), ..JsonWriter::new(writer, options) }.write(list)
+}
+
+/// Options for [`NvListRef::write_json_with`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct JsonOptions {
+    /// Put every value on its own line.
+    pub pretty: bool,
+    /// Write every value as an object with a single key naming its type, i.e.
+    /// `{"guid": {"uint64": 123}}`, so [`NvList::from_typed_json`](super::NvList::from_typed_json)
+    /// can read it back without losing anything. Strings are escaped as in regular JSON
+    /// instead of the way libnvpair does it.
+    pub typed:  bool,
+}
+
+/// An object or array being written.
+struct Open {
+    /// Nothing was written to it yet.
+    empty:   bool,
+    /// It is wrapped into an object naming its type.
+    wrapped: bool,
+}
+
+struct JsonWriter<'r, W> {
+    out:      W,
+    options:  JsonOptions,
+    open:     Vec<Open>,
+    /// Hides some values.
+    redactor: Option<&'r Redactor>,
+    error:    Option<NvError>,
+}
+
+impl<W: Write> JsonWriter<'_, W> {
+    fn new(out: W, options: JsonOptions) -> Self {
+        JsonWriter { out, options, open: Vec::new(), redactor: None, error: None }
+    }
+
+    fn write(mut self, list: &NvListRef) -> NvResult<()> {
+        self.open("{", false).map_err(NvError::Io)?;
+        let finished = list.accept(&mut self);
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        debug_assert!(finished);
+        self.close("}").map_err(NvError::Io)
+    }
+
+    /// Start a value inside of the current object or array. Returns `true` if the value was
+    /// wrapped into an object naming its type.
+    fn begin(&mut self, path: &KeyPath, data_type: DataType) -> io::Result<bool> {
+        let open = self.open.last_mut().expect("value outside of the list");
+        if !std::mem::replace(&mut open.empty, false) {
+            self.out.write_all(b",")?;
+        }
+        self.newline(self.open.len())?;
+        let colon: &[u8] = if self.options.pretty { b": " } else { b":" };
+        match path.segments().last() {
+            Some(Segment::Name(name)) => {
+                self.string(name)?;
+                self.out.write_all(colon)?;
+                if self.options.typed {
+                    self.out.write_all(b"{")?;
+                    self.string(data_type.name())?;
+                    self.out.write_all(colon)?;
+                    return Ok(true);
+                }
+                Ok(false)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// Finish a value started with [`JsonWriter::begin`].
+    fn end(&mut self, wrapped: bool) -> io::Result<()> {
+        if wrapped {
+            self.out.write_all(b"}")?;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self, bracket: &str, wrapped: bool) -> io::Result<()> {
+        self.out.write_all(bracket.as_bytes())?;
+        self.open.push(Open { empty: true, wrapped });
+        Ok(())
+    }
+
+    fn close(&mut self, bracket: &str) -> io::Result<()> {
+        let open = self.open.pop().expect("unbalanced close");
+        if !open.empty {
+            self.newline(self.open.len())?;
+        }
+        self.out.write_all(bracket.as_bytes())?;
+        self.end(open.wrapped)
+    }
+
+    fn newline(&mut self, depth: usize) -> io::Result<()> {
+        if self.options.pretty {
+            write!(self.out, "\n{:1$}", "", depth * 2)?;
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, value: &str) -> io::Result<()> {
+        if self.options.typed {
+            write_escaped(&mut self.out, value)
+        } else {
+            write_string(&mut self.out, value)
+        }
+    }
+
+    /// Write the placeholder instead of the value if the redactor hides it. Returns `false` if
+    /// the value should be written.
+    fn redacted(&mut self, path: &KeyPath, data_type: DataType) -> io::Result<bool> {
+        let Some(redactor) = self.redactor.filter(|r| r.hides_key_path(path, data_type)) else {
+            return Ok(false);
+        };
+        let wrapped = self.begin(path, data_type)?;
+        self.string(redactor.placeholder_text())?;
+        self.end(wrapped)?;
+        Ok(true)
+    }
+
+    fn value(&mut self, path: &KeyPath, value: &Value) -> io::Result<()> {
+        if self.redacted(path, value.data_type())? {
+            return Ok(());
+        }
+        let wrapped = self.begin(path, value.data_type())?;
+        match value {
+            Value::Bool(v) => self.out.write_all(if *v { b"true" } else { b"false" })?,
+            Value::Byte(v) | Value::Uint8(v) => write!(self.out, "{}", v)?,
+            Value::Int8(v) => write!(self.out, "{}", v)?,
+            Value::Int16(v) => write!(self.out, "{}", v)?,
+            Value::Uint16(v) => write!(self.out, "{}", v)?,
+            Value::Int32(v) => write!(self.out, "{}", v)?,
+            Value::Uint32(v) => write!(self.out, "{}", v)?,
+            Value::Int64(v) => write!(self.out, "{}", v)?,
+            Value::Uint64(v) => write!(self.out, "{}", v)?,
+            Value::Hrtime(v) if self.options.typed => write!(self.out, "{}", v)?,
+            // libnvpair prints it with `%llu`.
+            Value::Hrtime(v) => write!(self.out, "{}", *v as u64)?,
+            Value::Double(v) if self.options.typed => write_double_exact(&mut self.out, *v)?,
+            Value::Double(v) => write_double(&mut self.out, *v)?,
+            Value::String(v) => self.string(v)?,
+            _ => unreachable!("visited separately"),
+        }
+        self.end(wrapped)
+    }
+
+    /// Turn the result of a write into a flow, keeping the error.
+    fn flow(&mut self, result: io::Result<()>) -> Flow {
+        match result {
+            Ok(()) => Flow::Continue,
+            Err(err) => {
+                self.error = Some(NvError::Io(err));
+                Flow::Stop
+            },
+        }
+    }
+}
+
+impl<W: Write> Visitor for JsonWriter<'_, W> {
+    fn visit_value(&mut self, path: &KeyPath, value: Value) -> Flow {
+        if value.data_type() == DataType::Unknown {
+            // libnvpair fails on types it doesn't know.
+            self.error = Some(NvError::from_errno(libc::EINVAL));
+            return Flow::Stop;
+        }
+        let result = self.value(path, &value);
+        self.flow(result)
+    }
+
+    fn visit_boolean(&mut self, path: &KeyPath) -> Flow {
+        let result = self.redacted(path, DataType::Boolean).and_then(|redacted| {
+            if !redacted {
+                let wrapped = self.begin(path, DataType::Boolean)?;
+                self.out.write_all(b"true")?;
+                self.end(wrapped)?;
+            }
+            Ok(())
+        });
+        self.flow(result)
+    }
+
+    fn visit_string(&mut self, path: &KeyPath, value: &str) -> Flow {
+        let result = self.redacted(path, DataType::String).and_then(|redacted| {
+            if !redacted {
+                let wrapped = self.begin(path, DataType::String)?;
+                self.string(value)?;
+                self.end(wrapped)?;
+            }
+            Ok(())
+        });
+        self.flow(result)
+    }
+
+    fn enter_list(&mut self, path: &KeyPath) -> Flow {
+        match self.redacted(path, DataType::NvList) {
+            Ok(true) => return Flow::Skip,
+            Ok(false) => (),
+            Err(err) => return self.flow(Err(err)),
+        }
+        let result = self.begin(path, DataType::NvList).and_then(|wrapped| self.open("{", wrapped));
+        self.flow(result)
+    }
+
+    fn leave_list(&mut self, _path: &KeyPath) -> Flow {
+        let result = self.close("}");
+        self.flow(result)
+    }
+
+    fn enter_array(&mut self, path: &KeyPath, data_type: DataType, _len: usize) -> Flow {
+        match self.redacted(path, data_type) {
+            Ok(true) => return Flow::Skip,
+            Ok(false) => (),
+            Err(err) => return self.flow(Err(err)),
+        }
+        let result = self.begin(path, data_type).and_then(|wrapped| self.open("[", wrapped));
+        self.flow(result)
+    }
+
+    fn leave_array(&mut self, _path: &KeyPath) -> Flow {
+        let result = self.close("]");
+        self.flow(result)
+    }
+}
+
+/// Write a string the way `nvlist_print_json_string` does: control characters and characters of
+/// the Basic Multilingual Plane are escaped, characters outside of it are written as UTF-8 like
+/// `%lc` does in a UTF-8 locale.
+fn write_string<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\x0c' => out.write_all(b"\\f")?,
+            '\t' => out.write_all(b"\\t")?,
+            '\x08' => out.write_all(b"\\b")?,
+            '\x00'..='\x1f' | '\u{80}'..='\u{ffff}' => write!(out, "\\u{:04x}", c as u32)?,
+            '\x20'..='\x7f' => out.write_all(&[c as u8])?,
+            _ => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Write a double the way `printf("%f")` does.
+fn write_double<W: Write>(out: &mut W, value: f64) -> io::Result<()> {
+    if value.is_nan() {
+        out.write_all(if value.is_sign_negative() { b"-nan" } else { b"nan" })
+    } else {
+        write!(out, "{:.6}", value)
+    }
+}
+
+/// Write a string escaped the way JSON parsers expect it, keeping characters as they are.
+fn write_escaped<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            '\x00'..='\x1f' | '\x7f' => write!(out, "\\u{:04x}", c as u32)?,
+            _ => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+/// Write a double so that parsing it gives back the same value. JSON has no infinities and NaN,
+/// so those are written as strings.
+fn write_double_exact<W: Write>(out: &mut W, value: f64) -> io::Result<()> {
+    if value.is_nan() {
+        out.write_all(b"\"nan\"")
+    } else if value.is_infinite() {
+        out.write_all(if value > 0.0 { b"\"inf\"" } else { b"\"-inf\"" })
+    } else {
+        write!(out, "{:?}", value)
+    }
+}
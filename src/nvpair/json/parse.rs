@@ -0,0 +1,524 @@
+//! Reading lists from JSON.
+
+use super::super::{parse_error, DataType, NvFlag, NvList, Value};
+use crate::{NvError, NvResult};
+use std::collections::HashMap;
+
+/// Nesting deeper than this is rejected, so malicious input can't overflow the stack.
+const MAX_DEPTH: usize = 256;
+
+/// Types to use for values of JSON read with [`NvList::from_json`], by key path.
+///
+/// Paths are names of nested lists joined with `.`, like in [`NvListRef::select_paths`]. Array
+/// indexes are not part of a path, so `vdev_tree.children.guid` is `guid` in every element of
+/// `children`. Values without a hint get a type based on how they look:
+///
+/// - `true` and `false` are `boolean_value`,
+/// - integers are `uint64`, negative ones are `int64`, other numbers are `double`,
+/// - strings are `string` and objects are `nvlist`,
+/// - arrays are arrays of the type their elements would get, integer arrays are `int64_array` if
+///   any element is negative. Empty arrays are `uint64_array`.
+///
+/// `null` needs a hint, it is accepted only for `boolean`, a pair without a value.
+///
+/// [`NvListRef::select_paths`]: super::super::NvListRef::select_paths
+#[derive(Clone, Debug, Default)]
+pub struct TypeHints {
+    types: HashMap<String, DataType>,
+}
+
+impl TypeHints {
+    pub fn new() -> Self { TypeHints::default() }
+
+    /// Use `data_type` for the value at `path`.
+    ///
+    /// ```
+    /// use libnv::nvpair::{DataType, NvList, TypeHints};
+    ///
+    /// let hints = TypeHints::new()
+    ///     .hint("vdev_tree.ashift", DataType::Uint32)
+    ///     .hint("readonly", DataType::Boolean);
+    /// let config =
+    ///     NvList::from_json(r#"{"vdev_tree": {"ashift": 12}, "readonly": true}"#, &hints).unwrap();
+    /// assert_eq!(12, config.get_nvlist("vdev_tree").unwrap().get_u32("ashift").unwrap());
+    /// assert_eq!(DataType::Boolean, config.type_of("readonly").unwrap());
+    /// ```
+    pub fn hint<P: Into<String>>(mut self, path: P, data_type: DataType) -> Self {
+        self.types.insert(path.into(), data_type);
+        self
+    }
+
+    /// Type to use for the value at `path`, if there is a hint for it.
+    pub fn get(&self, path: &str) -> Option<DataType> { self.types.get(path).copied() }
+}
+
+impl NvList {
+    /// Read a list from JSON, i.e. one written by [`NvListRef::write_json`]. JSON doesn't keep
+    /// nvpair types, see [`TypeHints`] for how they are picked. Order of keys is preserved.
+    ///
+    /// [`NvListRef::write_json`]: super::super::NvListRef::write_json
+    pub fn from_json(json: &str, hints: &TypeHints) -> NvResult<NvList> {
+        let root = Parser::new(json).parse()?;
+        Builder { src: json, hints: Some(hints) }.list(&root, "")
+    }
+
+    /// Read a list from typed JSON written with [`JsonOptions::typed`], where every value is an
+    /// object with a single key naming its type. Nothing is lost in a round trip, except for
+    /// strings that are not valid UTF-8.
+    ///
+    /// ```
+    /// use libnv::nvpair::{DataType, NvList};
+    ///
+    /// let config = NvList::from_typed_json(r#"{"guid": {"uint64": 123}}"#).unwrap();
+    /// assert_eq!(DataType::Uint64, config.type_of("guid").unwrap());
+    /// ```
+    ///
+    /// [`JsonOptions::typed`]: super::JsonOptions::typed
+    pub fn from_typed_json(json: &str) -> NvResult<NvList> {
+        let root = Parser::new(json).parse()?;
+        Builder { src: json, hints: None }.list(&root, "")
+    }
+}
+
+/// Parsed JSON. Numbers are kept as text, so they can be parsed as the type they end up with.
+enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Node>),
+    Object(Vec<(String, Node)>),
+}
+
+/// JSON value with offset of its first byte in the input.
+struct Node {
+    offset: usize,
+    json:   Json,
+}
+
+impl Node {
+    fn kind(&self) -> &'static str {
+        match self.json {
+            Json::Null => "null",
+            Json::Bool(_) => "boolean",
+            Json::Number(_) => "number",
+            Json::String(_) => "string",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self { Parser { src, pos: 0 } }
+
+    fn parse(mut self) -> NvResult<Node> {
+        let root = self.value(0)?;
+        self.skip_whitespace();
+        if self.pos != self.src.len() {
+            return Err(self.error("trailing characters after JSON value".into()));
+        }
+        if !matches!(root.json, Json::Object(_)) {
+            return Err(parse_error(self.src, root.offset, "expected an object".into()));
+        }
+        Ok(root)
+    }
+
+    fn error(&self, message: String) -> NvError { parse_error(self.src, self.pos, message) }
+
+    fn peek(&self) -> Option<u8> { self.src.as_bytes().get(self.pos).copied() }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> NvResult<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`", byte as char)))
+        }
+    }
+
+    fn literal(&mut self, literal: &str, json: Json) -> NvResult<Json> {
+        if self.src[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(json)
+        } else {
+            Err(self.error("expected a JSON value".into()))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> NvResult<Node> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting is too deep".into()));
+        }
+        self.skip_whitespace();
+        let offset = self.pos;
+        let json = match self.peek() {
+            Some(b'{') => self.object(depth)?,
+            Some(b'[') => self.array(depth)?,
+            Some(b'"') => Json::String(self.string()?),
+            Some(b'-' | b'0'..=b'9') => Json::Number(self.number()?),
+            Some(b't') => self.literal("true", Json::Bool(true))?,
+            Some(b'f') => self.literal("false", Json::Bool(false))?,
+            Some(b'n') => self.literal("null", Json::Null)?,
+            Some(_) => return Err(self.error("expected a JSON value".into())),
+            None => return Err(self.error("unexpected end of input".into())),
+        };
+        Ok(Node { offset, json })
+    }
+
+    fn object(&mut self, depth: usize) -> NvResult<Json> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a key".into()));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value(depth + 1)?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                },
+                _ => return Err(self.error("expected `,` or `}`".into())),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> NvResult<Json> {
+        self.pos += 1;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(elements));
+        }
+        loop {
+            elements.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(elements));
+                },
+                _ => return Err(self.error("expected `,` or `]`".into())),
+            }
+        }
+    }
+
+    fn number(&mut self) -> NvResult<String> {
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let start = parser.pos;
+            while let Some(b'0'..=b'9') = parser.peek() {
+                parser.pos += 1;
+            }
+            parser.pos > start
+        };
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if !digits(self) {
+            return Err(self.error("expected a digit".into()));
+        }
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            if !digits(self) {
+                return Err(self.error("expected a digit".into()));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.error("expected a digit".into()));
+            }
+        }
+        Ok(self.src[start..self.pos].to_owned())
+    }
+
+    fn string(&mut self) -> NvResult<String> {
+        self.pos += 1;
+        let mut ret = String::new();
+        loop {
+            let rest = &self.src[self.pos..];
+            let c = match rest.chars().next() {
+                Some(c) => c,
+                None => return Err(self.error("unterminated string".into())),
+            };
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok(ret);
+                },
+                '\\' => {
+                    self.pos += 1;
+                    ret.push(self.escape()?);
+                },
+                '\x00'..='\x1f' => return Err(self.error("control character in string".into())),
+                c => {
+                    self.pos += c.len_utf8();
+                    ret.push(c);
+                },
+            }
+        }
+    }
+
+    /// Parse an escape sequence after `\`.
+    fn escape(&mut self) -> NvResult<char> {
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\x08',
+            Some(b'f') => '\x0c',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                let high = self.hex()?;
+                if !(0xd800..0xdc00).contains(&high) {
+                    return char::from_u32(high)
+                        .ok_or_else(|| self.error("invalid unicode escape".into()));
+                }
+                if !self.src[self.pos..].starts_with("\\u") {
+                    return Err(self.error("expected a low surrogate".into()));
+                }
+                self.pos += 2;
+                let low = self.hex()?;
+                if !(0xdc00..0xe000).contains(&low) {
+                    return Err(self.error("expected a low surrogate".into()));
+                }
+                let c = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+                return char::from_u32(c)
+                    .ok_or_else(|| self.error("invalid unicode escape".into()));
+            },
+            _ => return Err(self.error("invalid escape sequence".into())),
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn hex(&mut self) -> NvResult<u32> {
+        let digits = self.src.get(self.pos..self.pos + 4).unwrap_or("");
+        let value = u32::from_str_radix(digits, 16)
+            .ok()
+            .filter(|_| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("expected 4 hex digits".into()))?;
+        self.pos += 4;
+        Ok(value)
+    }
+}
+
+/// Turns parsed JSON into lists. Without hints every value is expected to be typed.
+struct Builder<'a> {
+    src:   &'a str,
+    hints: Option<&'a TypeHints>,
+}
+
+impl Builder<'_> {
+    fn error(&self, node: &Node, message: String) -> NvError {
+        parse_error(self.src, node.offset, message)
+    }
+
+    fn list(&self, node: &Node, path: &str) -> NvResult<NvList> {
+        let members = match &node.json {
+            Json::Object(members) => members,
+            _ => return Err(self.error(node, format!("expected an object, found {}", node.kind()))),
+        };
+        let mut list = NvList::new(NvFlag::UniqueNameType)?;
+        for (key, node) in members {
+            let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+            let (data_type, node) = match self.hints {
+                Some(hints) => match hints.get(&path) {
+                    Some(data_type) => (data_type, node),
+                    None => (self.infer(node)?, node),
+                },
+                None => self.unwrap_typed(node)?,
+            };
+            if data_type == DataType::Boolean {
+                match node.json {
+                    Json::Bool(true) | Json::Null => list.insert_boolean(key.as_str())?,
+                    _ => return Err(self.error(node, "expected `true` or `null`".into())),
+                }
+            } else {
+                list.insert(key.as_str(), self.value(node, data_type, &path)?)?;
+            }
+        }
+        Ok(list)
+    }
+
+    /// Split `{"type": value}` into the type and the value.
+    fn unwrap_typed<'n>(&self, node: &'n Node) -> NvResult<(DataType, &'n Node)> {
+        match &node.json {
+            Json::Object(members) if members.len() == 1 => {
+                let (name, value) = &members[0];
+                let data_type =
+                    name.parse().map_err(|_| self.error(node, format!("unknown type {}", name)))?;
+                Ok((data_type, value))
+            },
+            _ => Err(self.error(node, "expected an object with a single type name".into())),
+        }
+    }
+
+    /// Pick a type for a value without a hint.
+    fn infer(&self, node: &Node) -> NvResult<DataType> {
+        Ok(match &node.json {
+            Json::Null => return Err(self.error(node, "null needs a type hint".into())),
+            Json::Bool(_) => DataType::BooleanValue,
+            Json::Number(n) if n.contains(['.', 'e', 'E']) => DataType::Double,
+            Json::Number(n) if n.starts_with('-') => DataType::Int64,
+            Json::Number(_) => DataType::Uint64,
+            Json::String(_) => DataType::String,
+            Json::Object(_) => DataType::NvList,
+            Json::Array(elements) => {
+                let mut ret = DataType::Uint64Array;
+                for (idx, element) in elements.iter().enumerate() {
+                    let data_type = match self.infer(element)? {
+                        DataType::BooleanValue => DataType::BooleanArray,
+                        DataType::Uint64 if ret == DataType::Int64Array => DataType::Int64Array,
+                        DataType::Uint64 => DataType::Uint64Array,
+                        DataType::Int64 if ret == DataType::Uint64Array => DataType::Int64Array,
+                        DataType::Int64 => DataType::Int64Array,
+                        DataType::String => DataType::StringArray,
+                        DataType::NvList => DataType::NvListArray,
+                        other => {
+                            return Err(self.error(element, format!("no array type for {}", other)))
+                        },
+                    };
+                    let integers = [DataType::Uint64Array, DataType::Int64Array];
+                    if idx > 0
+                        && data_type != ret
+                        && !(integers.contains(&data_type) && integers.contains(&ret))
+                    {
+                        return Err(
+                            self.error(element, "array elements have different types".into())
+                        );
+                    }
+                    ret = data_type;
+                }
+                ret
+            },
+        })
+    }
+
+    fn value(&self, node: &Node, data_type: DataType, path: &str) -> NvResult<Value> {
+        Ok(match data_type {
+            DataType::BooleanValue => Value::Bool(self.bool(node)?),
+            DataType::Byte => Value::Byte(self.int(node, data_type)?),
+            DataType::Int8 => Value::Int8(self.int(node, data_type)?),
+            DataType::Uint8 => Value::Uint8(self.int(node, data_type)?),
+            DataType::Int16 => Value::Int16(self.int(node, data_type)?),
+            DataType::Uint16 => Value::Uint16(self.int(node, data_type)?),
+            DataType::Int32 => Value::Int32(self.int(node, data_type)?),
+            DataType::Uint32 => Value::Uint32(self.int(node, data_type)?),
+            DataType::Int64 => Value::Int64(self.int(node, data_type)?),
+            DataType::Uint64 => Value::Uint64(self.int(node, data_type)?),
+            // `nvlist_print_json` prints it as unsigned.
+            DataType::Hrtime => Value::Hrtime(
+                self.int(node, data_type)
+                    .or_else(|_| self.int::<u64>(node, data_type).map(|v| v as i64))?,
+            ),
+            DataType::Double => Value::Double(self.double(node)?),
+            DataType::String => Value::String(self.string(node)?),
+            DataType::NvList => Value::NvList(self.list(node, path)?),
+            DataType::BooleanArray => Value::BoolArray(self.array(node, |e| self.bool(e))?),
+            DataType::ByteArray => Value::ByteArray(self.array(node, |e| self.int(e, data_type))?),
+            DataType::Int8Array => Value::Int8Array(self.array(node, |e| self.int(e, data_type))?),
+            DataType::Uint8Array => {
+                Value::Uint8Array(self.array(node, |e| self.int(e, data_type))?)
+            },
+            DataType::Int16Array => {
+                Value::Int16Array(self.array(node, |e| self.int(e, data_type))?)
+            },
+            DataType::Uint16Array => {
+                Value::Uint16Array(self.array(node, |e| self.int(e, data_type))?)
+            },
+            DataType::Int32Array => {
+                Value::Int32Array(self.array(node, |e| self.int(e, data_type))?)
+            },
+            DataType::Uint32Array => {
+                Value::Uint32Array(self.array(node, |e| self.int(e, data_type))?)
+            },
+            DataType::Int64Array => {
+                Value::Int64Array(self.array(node, |e| self.int(e, data_type))?)
+            },
+            DataType::Uint64Array => {
+                Value::Uint64Array(self.array(node, |e| self.int(e, data_type))?)
+            },
+            DataType::StringArray => Value::StringArray(self.array(node, |e| self.string(e))?),
+            DataType::NvListArray => Value::NvListArray(self.array(node, |e| self.list(e, path))?),
+            DataType::Boolean | DataType::Unknown | DataType::DontCare => {
+                return Err(self.error(node, format!("can't read a value of type {}", data_type)))
+            },
+        })
+    }
+
+    fn array<T, F: FnMut(&Node) -> NvResult<T>>(&self, node: &Node, f: F) -> NvResult<Vec<T>> {
+        match &node.json {
+            Json::Array(elements) => elements.iter().map(f).collect(),
+            _ => Err(self.error(node, format!("expected an array, found {}", node.kind()))),
+        }
+    }
+
+    fn bool(&self, node: &Node) -> NvResult<bool> {
+        match node.json {
+            Json::Bool(v) => Ok(v),
+            _ => Err(self.error(node, format!("expected a boolean, found {}", node.kind()))),
+        }
+    }
+
+    fn string(&self, node: &Node) -> NvResult<String> {
+        match &node.json {
+            Json::String(v) => Ok(v.clone()),
+            _ => Err(self.error(node, format!("expected a string, found {}", node.kind()))),
+        }
+    }
+
+    fn int<T: std::str::FromStr>(&self, node: &Node, data_type: DataType) -> NvResult<T> {
+        match &node.json {
+            Json::Number(n) => n
+                .parse()
+                .map_err(|_| self.error(node, format!("{} is not a valid {}", n, data_type))),
+            _ => Err(self.error(node, format!("expected a number, found {}", node.kind()))),
+        }
+    }
+
+    fn double(&self, node: &Node) -> NvResult<f64> {
+        match &node.json {
+            Json::Number(n) => {
+                n.parse().map_err(|_| self.error(node, format!("{} is not a valid double", n)))
+            },
+            // Typed JSON writes these as strings.
+            Json::String(s) if s == "nan" => Ok(f64::NAN),
+            Json::String(s) if s == "inf" => Ok(f64::INFINITY),
+            Json::String(s) if s == "-inf" => Ok(f64::NEG_INFINITY),
+            _ => Err(self.error(node, format!("expected a number, found {}", node.kind()))),
+        }
+    }
+}
@@ -0,0 +1,353 @@
+//! Hiding values of lists from logs and exports.
+
+use super::{json,
+            visit::{nested_list, nested_lists},
+            DataType, JsonOptions, KeyPath, NvFlag, NvList, NvListRef, NvPairRef, Segment, Value};
+use crate::NvResult;
+use std::{collections::HashMap,
+          fmt::{self, Debug, Formatter},
+          io::Write,
+          os::unix::io::AsRawFd};
+
+/// Placeholder used unless [`Redactor::placeholder`] sets another one.
+const PLACEHOLDER: &str = "<redacted>";
+
+/// Picks values to replace with a placeholder when printing or exporting a list, by key path or
+/// by type.
+///
+/// Paths are names of nested lists joined with `.` like in [`NvListRef::select_paths`], array
+/// indexes are not part of them. A `*` in a pattern matches any part of a name and `**` matches
+/// any number of names, i.e. `**.hostname` hides `hostname` at any depth. A `\` makes the next
+/// character match itself, so `features_for_read.com\.delphix:hole_birth` hides a pair whose
+/// name contains a dot.
+///
+/// Pairs of any type can be hidden. A hidden nested list or nvlist array is replaced by the
+/// placeholder as a whole, so nothing inside of it is shown. Elements of arrays are hidden along
+/// with their array, never on their own.
+///
+/// ```
+/// use libnv::nvpair::{DataType, NvList, Redactor};
+///
+/// let mut list = NvList::default();
+/// list.insert("hostname", "backup01").unwrap();
+/// list.insert("txg", 7u64).unwrap();
+/// let redactor = Redactor::new().path("hostname").data_type(DataType::Uint8Array);
+/// assert_eq!(
+///     r#"{"hostname":"<redacted>","txg":7}"#,
+///     list.to_json_string_redacted(&redactor).unwrap()
+/// );
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Redactor {
+    paths:       Vec<Vec<Step>>,
+    types:       Vec<DataType>,
+    placeholder: String,
+}
+
+/// Part of a pattern between two `.`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Step {
+    /// `**`, any number of names.
+    AnyNames,
+    Name(Vec<Part>),
+}
+
+/// Part of a pattern of a single name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Part {
+    Literal(String),
+    /// `*`, any part of a name.
+    Any,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Redactor {
+            paths:       Vec::new(),
+            types:       Vec::new(),
+            placeholder: PLACEHOLDER.to_owned(),
+        }
+    }
+}
+
+impl Redactor {
+    /// Redactor that doesn't hide anything yet.
+    pub fn new() -> Self { Redactor::default() }
+
+    /// Hide values at paths matching `pattern`.
+    pub fn path<P: AsRef<str>>(mut self, pattern: P) -> Self {
+        self.paths.push(parse_pattern(pattern.as_ref()));
+        self
+    }
+
+    /// Hide values of `data_type`, wherever they are.
+    pub fn data_type(mut self, data_type: DataType) -> Self {
+        self.types.push(data_type);
+        self
+    }
+
+    /// Write `placeholder` instead of hidden values. Defaults to `<redacted>`.
+    pub fn placeholder<P: Into<String>>(mut self, placeholder: P) -> Self {
+        self.placeholder = placeholder.into();
+        self
+    }
+
+    /// Text written instead of hidden values.
+    pub fn placeholder_text(&self) -> &str { &self.placeholder }
+
+    /// Whether a value of `data_type` is hidden, given the names of the nested lists holding it
+    /// followed by its own name. Names are matched as they are, without treating `.` specially.
+    ///
+    /// ```
+    /// use libnv::nvpair::{DataType, Redactor};
+    ///
+    /// let redactor = Redactor::new().path(r"features_for_read.com\.delphix:*");
+    /// assert!(redactor.hides(&["features_for_read", "com.delphix:hole_birth"], DataType::Boolean));
+    /// assert!(!redactor.hides(&["features_for_read", "org.openzfs:blake3"], DataType::Boolean));
+    /// ```
+    pub fn hides<S: AsRef<str>>(&self, names: &[S], data_type: DataType) -> bool {
+        let names: Vec<&str> = names.iter().map(AsRef::as_ref).collect();
+        self.hides_names(&names, data_type)
+    }
+
+    /// Like [`Redactor::hides`], for paths reported by [`NvListRef::accept`].
+    pub(super) fn hides_key_path(&self, path: &KeyPath, data_type: DataType) -> bool {
+        if let Some(Segment::Index(_)) = path.segments().last() {
+            return false;
+        }
+        let names: Vec<&str> = path
+            .segments()
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Name(name) => Some(name.as_str()),
+                Segment::Index(_) => None,
+            })
+            .collect();
+        self.hides_names(&names, data_type)
+    }
+
+    fn hides_names(&self, names: &[&str], data_type: DataType) -> bool {
+        self.types.contains(&data_type)
+            || self
+                .paths
+                .iter()
+                .any(|pattern| (1..=names.len()).any(|len| matches_path(pattern, &names[..len])))
+    }
+}
+
+/// Split `pattern` into steps, see [`Redactor`].
+fn parse_pattern(pattern: &str) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = pattern.chars();
+    loop {
+        match chars.next() {
+            Some('\\') => literal.push(chars.next().unwrap_or('\\')),
+            Some('*') => {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Any);
+            },
+            Some(c) if c != '.' => literal.push(c),
+            next => {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                let step = match parts.as_slice() {
+                    [Part::Any, Part::Any] => Step::AnyNames,
+                    _ => Step::Name(std::mem::take(&mut parts)),
+                };
+                parts.clear();
+                steps.push(step);
+                if next.is_none() {
+                    return steps;
+                }
+            },
+        }
+    }
+}
+
+/// Whether `path` matches `pattern`.
+fn matches_path(pattern: &[Step], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((Step::AnyNames, rest)) => {
+            (0..=path.len()).any(|skip| matches_path(rest, &path[skip..]))
+        },
+        Some((Step::Name(parts), rest)) => path
+            .split_first()
+            .is_some_and(|(name, path)| matches_name(parts, name) && matches_path(rest, path)),
+    }
+}
+
+/// Whether `name` matches the parts of a pattern.
+fn matches_name(parts: &[Part], name: &str) -> bool {
+    match parts.split_first() {
+        None => name.is_empty(),
+        Some((Part::Literal(literal), rest)) => {
+            name.strip_prefix(literal.as_str()).is_some_and(|name| matches_name(rest, name))
+        },
+        Some((Part::Any, rest)) => (0..=name.len())
+            .filter(|idx| name.is_char_boundary(*idx))
+            .any(|idx| matches_name(rest, &name[idx..])),
+    }
+}
+
+impl NvListRef {
+    /// Borrow the list to print it with `Debug` like the list itself, but with values hidden by
+    /// `redactor` replaced.
+    ///
+    /// ```
+    /// use libnv::nvpair::{NvList, Redactor};
+    ///
+    /// let mut list = NvList::default();
+    /// list.insert("path", "/dev/da0").unwrap();
+    /// let redactor = Redactor::new().path("path");
+    /// assert_eq!(r#"{"path": <redacted>}"#, format!("{:?}", list.redacted(&redactor)));
+    /// ```
+    pub fn redacted<'a>(&'a self, redactor: &'a Redactor) -> RedactedNvList<'a> {
+        RedactedNvList { list: self, redactor, path: Vec::new() }
+    }
+
+    /// Like [`NvListRef::to_json_string`], with values hidden by `redactor` written as the
+    /// placeholder string.
+    pub fn to_json_string_redacted(&self, redactor: &Redactor) -> NvResult<String> {
+        let mut buf = Vec::new();
+        self.write_json_redacted(&mut buf, JsonOptions::default(), redactor)?;
+        Ok(String::from_utf8(buf).expect("JSON output is UTF-8"))
+    }
+
+    /// Like [`NvListRef::write_json_with`], with values hidden by `redactor` written as the
+    /// placeholder string.
+    pub fn write_json_redacted<W: Write>(
+        &self,
+        writer: W,
+        options: JsonOptions,
+        redactor: &Redactor,
+    ) -> NvResult<()> {
+        json::write_redacted(self, writer, options, redactor)
+    }
+
+    /// Copy the list, replacing values hidden by `redactor` with `string` pairs holding the
+    /// placeholder. The copy is created with [`NvFlag::None`], so pairs that only differed by
+    /// their type are all kept, and with the allocator of this list.
+    pub fn redact(&self, redactor: &Redactor) -> NvResult<NvList> {
+        redact_at(self, redactor, &mut Vec::new())
+    }
+
+    /// Like [`NvListRef::save_as_json`], with values hidden by `redactor` written as the
+    /// placeholder string.
+    pub fn save_as_json_redacted<F: AsRawFd>(
+        &self,
+        output: F,
+        redactor: &Redactor,
+    ) -> NvResult<()> {
+        self.redact(redactor)?.save_as_json(output)
+    }
+}
+
+impl NvList {
+    /// Like [`NvList::into_hashmap`], with values hidden by `redactor` replaced with
+    /// [`Value::String`] holding the placeholder.
+    pub fn into_hashmap_redacted(self, redactor: &Redactor) -> NvResult<HashMap<String, Value>> {
+        Ok(self.redact(redactor)?.into_hashmap())
+    }
+}
+
+/// Copy `list` holding pairs at `path` for [`NvListRef::redact`].
+fn redact_at(list: &NvListRef, redactor: &Redactor, path: &mut Vec<String>) -> NvResult<NvList> {
+    let mut ret = list.new_like_with(NvFlag::None)?;
+    for pair in list.iter() {
+        path.push(pair.key().to_string_lossy().into_owned());
+        let result = redact_pair(&mut ret, &pair, redactor, path);
+        path.pop();
+        result?;
+    }
+    Ok(ret)
+}
+
+/// Copy `pair` at `path` into `ret`, see [`redact_at`].
+fn redact_pair(
+    ret: &mut NvList,
+    pair: &NvPairRef,
+    redactor: &Redactor,
+    path: &mut Vec<String>,
+) -> NvResult<()> {
+    match pair.data_type() {
+        data_type if redactor.hides(path, data_type) => {
+            ret.insert_string(pair.key(), redactor.placeholder_text())
+        },
+        DataType::NvList => {
+            // Nested lists are owned by `list`, which outlives this call.
+            let nested = redact_at(unsafe { nested_list(pair) }, redactor, path)?;
+            ret.insert_nvlist(pair.key(), &nested)
+        },
+        DataType::NvListArray => {
+            let lists = unsafe { nested_lists(pair) }
+                .map(|nested| redact_at(nested, redactor, path))
+                .collect::<NvResult<Vec<_>>>()?;
+            ret.insert_nvlist_array(pair.key(), &lists)
+        },
+        _ => ret.add_nvpair(pair),
+    }
+}
+
+/// List printed with `Debug` with some values hidden, see [`NvListRef::redacted`].
+pub struct RedactedNvList<'a> {
+    list:     &'a NvListRef,
+    redactor: &'a Redactor,
+    /// Names of the lists holding this one inside the one being printed.
+    path:     Vec<String>,
+}
+
+impl Debug for RedactedNvList<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for pair in self.list.iter() {
+            let key = pair.key().to_string_lossy();
+            let mut path = self.path.clone();
+            path.push(key.clone().into_owned());
+            match pair.data_type() {
+                data_type if self.redactor.hides(&path, data_type) => {
+                    map.entry(&key, &Placeholder(self.redactor.placeholder_text()));
+                },
+                DataType::NvList => {
+                    let list = unsafe { nested_list(&pair) };
+                    let nested = RedactedNvList { list, redactor: self.redactor, path };
+                    map.entry(&key, &Tuple("NvList", &nested));
+                },
+                DataType::NvListArray => {
+                    let lists: Vec<_> = unsafe { nested_lists(&pair) }
+                        .map(|list| RedactedNvList {
+                            list,
+                            redactor: self.redactor,
+                            path: path.clone(),
+                        })
+                        .collect();
+                    map.entry(&key, &Tuple("NvListArray", &lists));
+                },
+                _ => {
+                    map.entry(&key, &pair.value());
+                },
+            }
+        }
+        map.finish()
+    }
+}
+
+/// Prints like a tuple variant of [`Value`] holding `.1`.
+struct Tuple<'a, T>(&'static str, &'a T);
+
+impl<T: Debug> Debug for Tuple<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_tuple(self.0).field(self.1).finish()
+    }
+}
+
+struct Placeholder<'a>(&'a str);
+
+impl Debug for Placeholder<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(self.0) }
+}
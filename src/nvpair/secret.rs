@@ -0,0 +1,190 @@
+//! Lists holding secret material, such as the wrapping keys of ZFS native encryption.
+
+use super::{allocator::heap_layout, json, text, BorrowedPackedNvList, JsonOptions, NvAllocator,
+            NvEncoding, NvFlag, NvList, NvListRef, Redactor};
+use crate::NvResult;
+use std::{fmt::{self, Debug, Formatter},
+          io::Write,
+          ops::Deref,
+          ptr::NonNull,
+          sync::atomic::{compiler_fence, Ordering}};
+
+/// List whose memory is zeroed when it's freed, including the memory of its copies and of lists
+/// nested in it. Its `Debug`, JSON and text output hide values.
+///
+/// Values are only reachable through [`SecretNvList::expose`] and [`SecretNvList::expose_mut`].
+/// Values copied out of the list, e.g. by [`NvPairRef::value`](super::NvPairRef::value) or
+/// [`PackedNvList::into_vec`](super::PackedNvList::into_vec), aren't zeroed; use
+/// [`SecretNvList::pack`] to pack it.
+///
+/// ```
+/// use libnv::nvpair::{NvFlag, SecretNvList};
+///
+/// let mut crypto = SecretNvList::new(NvFlag::UniqueName).unwrap();
+/// crypto.expose_mut().insert_u8_array("wkeydata", &[0x5a; 32]).unwrap();
+/// assert_eq!(r#"{"wkeydata":"<redacted>"}"#, crypto.to_json_string().unwrap());
+/// assert_eq!("{\"wkeydata\": <redacted>}", format!("{:?}", crypto));
+/// assert_eq!(32, crypto.expose().get_u8_array("wkeydata").unwrap().len());
+/// ```
+pub struct SecretNvList {
+    list: NvList,
+}
+
+impl SecretNvList {
+    /// Create an empty list.
+    pub fn new(flags: NvFlag) -> NvResult<Self> {
+        Ok(SecretNvList { list: NvList::new_in(flags, ZeroingAllocator)? })
+    }
+
+    /// Copy `list`. The memory of `list` itself isn't zeroed when it's freed.
+    pub fn from_list(list: &NvListRef) -> NvResult<Self> {
+        Ok(SecretNvList { list: list.try_clone_in(ZeroingAllocator)? })
+    }
+
+    /// Unpack a list packed with [`SecretNvList::pack`]. See [`BorrowedPackedNvList::unpack`].
+    pub fn unpack(buf: &[u8], flags: NvFlag) -> NvResult<Self> {
+        Ok(SecretNvList {
+            list: BorrowedPackedNvList::from(buf).unpack_in(flags, ZeroingAllocator)?,
+        })
+    }
+
+    /// Borrow the list, revealing its values.
+    pub fn expose(&self) -> &NvListRef { &self.list }
+
+    /// Borrow the list mutably, revealing its values. Lists created with [`NvList::new`] and
+    /// inserted into it are copied into memory that is zeroed.
+    pub fn expose_mut(&mut self) -> &mut NvListRef { &mut self.list }
+
+    /// Like [`SecretNvList`]'s `Clone`, but reports running out of memory instead of panicking.
+    pub fn try_clone(&self) -> NvResult<Self> {
+        Ok(SecretNvList { list: self.list.try_clone_in(ZeroingAllocator)? })
+    }
+
+    /// Pack the list into a buffer that is zeroed when dropped.
+    pub fn pack(&self, encoding: NvEncoding) -> NvResult<SecretBytes> {
+        let mut buf = SecretBytes { buf: vec![0; self.list.encoded_size(encoding)?], len: 0 };
+        buf.len = self.list.pack_into(&mut buf.buf, encoding)?;
+        Ok(buf)
+    }
+
+    /// Render the list as JSON like [`NvListRef::to_json_string`], with the value of every top
+    /// level pair written as `"<redacted>"`. Nested lists and flags are hidden as a whole too.
+    pub fn to_json_string(&self) -> NvResult<String> {
+        let mut buf = Vec::new();
+        self.write_json_with(&mut buf, JsonOptions::default())?;
+        Ok(String::from_utf8(buf).expect("JSON output is UTF-8"))
+    }
+
+    /// Write the list as JSON with given options to `writer`, hiding values like
+    /// [`SecretNvList::to_json_string`].
+    pub fn write_json_with<W: Write>(&self, writer: W, options: JsonOptions) -> NvResult<()> {
+        json::write_redacted(&self.list, writer, options, &hide_all())
+    }
+
+    /// Render the list in the [`text`] format with the value of every top level pair written as
+    /// `<redacted>`, like [`SecretNvList::to_json_string`].
+    pub fn to_text(&self) -> NvResult<String> {
+        let mut buf = Vec::new();
+        self.write_text(&mut buf)?;
+        Ok(String::from_utf8(buf).expect("text output is UTF-8"))
+    }
+
+    /// Write the list to `writer` like [`SecretNvList::to_text`].
+    pub fn write_text<W: Write>(&self, writer: W) -> NvResult<()> {
+        text::write_redacted(&self.list, writer, &hide_all())
+    }
+}
+
+impl Clone for SecretNvList {
+    /// # Panics
+    ///
+    /// Panics if libnvpair runs out of memory, see [`SecretNvList::try_clone`].
+    fn clone(&self) -> Self { self.try_clone().expect("Failed to copy list") }
+}
+
+/// Print names of pairs, but not their values.
+impl Debug for SecretNvList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.list.iter().map(|pair| (pair.key().to_string_lossy().into_owned(), Redacted)),
+            )
+            .finish()
+    }
+}
+
+/// Redactor hiding every pair of the list as a whole, so only top level names are shown, like
+/// in `Debug`.
+fn hide_all() -> Redactor { Redactor::new().path("*") }
+
+/// Packed [`SecretNvList`], zeroed when dropped.
+pub struct SecretBytes {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] { &self.buf[..self.len] }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] { self }
+}
+
+impl Debug for SecretBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretBytes").field("len", &self.len).finish_non_exhaustive()
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        let len = self.buf.len();
+        unsafe { zero(self.buf.as_mut_ptr(), len) }
+    }
+}
+
+struct Redacted;
+
+impl Debug for Redacted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str("<redacted>") }
+}
+
+/// Heap allocator zeroing memory before freeing it.
+struct ZeroingAllocator;
+
+unsafe impl NvAllocator for ZeroingAllocator {
+    fn allocate(&self, size: usize) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { std::alloc::alloc(heap_layout(size)) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, size: usize) {
+        unsafe {
+            zero(ptr.as_ptr(), size);
+            std::alloc::dealloc(ptr.as_ptr(), heap_layout(size));
+        }
+        #[cfg(test)]
+        ZEROED.with(|zeroed| zeroed.set(zeroed.get() + 1));
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of allocations zeroed on this thread, so tests can tell which lists use
+    /// [`ZeroingAllocator`].
+    pub(super) static ZEROED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Zero `len` bytes at `ptr` in a way the compiler can't optimize out.
+///
+/// # Safety
+///
+/// `ptr` must be valid for writes of `len` bytes.
+unsafe fn zero(ptr: *mut u8, len: usize) {
+    for offset in 0..len {
+        unsafe { ptr.add(offset).write_volatile(0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
@@ -0,0 +1,329 @@
+//! `serde` support for values and lists.
+//!
+//! Values are externally tagged with the name of their type, i.e. `{"uint64": 123}` in JSON, so
+//! reading them back gives the same type. Lists are maps of names to tagged values in the order of
+//! pairs. Pairs of type `boolean` have no value and are written as the unit variant `boolean`.
+//!
+//! Byte and `uint8` arrays are written as bytes, which formats like CBOR store compactly. Formats
+//! without NaN and infinities, like JSON, can't round trip such doubles.
+
+use super::{DataType, NvFlag, NvList, NvListRef, Value};
+use serde::{de::{self, Deserialize, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess},
+            ser::{Serialize, SerializeMap, Serializer}};
+use std::fmt;
+
+/// Name of the enum values are written as.
+const ENUM: &str = "Value";
+
+/// Names of variants, indexed by the number of the type in `data_type_t`.
+const VARIANTS: &[&str] = &[
+    "unknown",
+    "boolean",
+    "byte",
+    "int16",
+    "uint16",
+    "int32",
+    "uint32",
+    "int64",
+    "uint64",
+    "string",
+    "byte_array",
+    "int16_array",
+    "uint16_array",
+    "int32_array",
+    "uint32_array",
+    "int64_array",
+    "uint64_array",
+    "string_array",
+    "hrtime",
+    "nvlist",
+    "nvlist_array",
+    "boolean_value",
+    "int8",
+    "uint8",
+    "boolean_array",
+    "int8_array",
+    "uint8_array",
+    "double",
+];
+
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let data_type = self.data_type();
+        let index = data_type as u32;
+        let name = data_type.name();
+        macro_rules! tagged {
+            ($value:expr) => {
+                serializer.serialize_newtype_variant(ENUM, index, name, $value)
+            };
+        }
+        match self {
+            Value::Unknown => serializer.serialize_unit_variant(ENUM, index, name),
+            Value::Bool(v) => tagged!(v),
+            Value::Byte(v) | Value::Uint8(v) => tagged!(v),
+            Value::Int8(v) => tagged!(v),
+            Value::Int16(v) => tagged!(v),
+            Value::Uint16(v) => tagged!(v),
+            Value::Int32(v) => tagged!(v),
+            Value::Uint32(v) => tagged!(v),
+            Value::Int64(v) | Value::Hrtime(v) => tagged!(v),
+            Value::Uint64(v) => tagged!(v),
+            Value::Double(v) => tagged!(v),
+            Value::String(v) => tagged!(v),
+            Value::NvList(v) => tagged!(v),
+            Value::BoolArray(v) => tagged!(v),
+            Value::ByteArray(v) | Value::Uint8Array(v) => tagged!(&Bytes(v)),
+            Value::Int8Array(v) => tagged!(v),
+            Value::Int16Array(v) => tagged!(v),
+            Value::Uint16Array(v) => tagged!(v),
+            Value::Int32Array(v) => tagged!(v),
+            Value::Uint32Array(v) => tagged!(v),
+            Value::Int64Array(v) => tagged!(v),
+            Value::Uint64Array(v) => tagged!(v),
+            Value::StringArray(v) => tagged!(v),
+            Value::NvListArray(v) => tagged!(v),
+        }
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl Serialize for NvListRef {
+    /// Written as a map in the order of pairs. Nested lists are written without copying them.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for pair in self {
+            let key = pair.key().to_string_lossy();
+            let data_type = pair.data_type();
+            let index = data_type as u32;
+            match data_type {
+                DataType::Boolean => map.serialize_entry(&key, &Flag)?,
+                DataType::NvList => {
+                    let nested = pair.borrow_nvlist().expect("pair holds a list");
+                    map.serialize_entry(&key, &Tagged(index, data_type.name(), nested))?
+                },
+                DataType::NvListArray => {
+                    let nested = pair.borrow_nvlist_array().expect("pair holds an array of lists");
+                    map.serialize_entry(&key, &Tagged(index, data_type.name(), &nested))?
+                },
+                _ => map.serialize_entry(&key, &pair.value())?,
+            }
+        }
+        map.end()
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl Serialize for NvList {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        NvListRef::serialize(self, serializer)
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl<'de> Deserialize<'de> for Value {
+    /// A `boolean` without value is read as `Value::Bool(true)`, like [`NvPairRef::value`] does.
+    ///
+    /// [`NvPairRef::value`]: super::NvPairRef::value
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match Entry::deserialize(deserializer)? {
+            Entry::Flag => Value::Bool(true),
+            Entry::Value(value) => value,
+        })
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "serde")))]
+impl<'de> Deserialize<'de> for NvList {
+    /// Read a map written by [`NvListRef::serialize`] into a list created with
+    /// [`NvFlag::UniqueNameType`].
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(ListVisitor)
+    }
+}
+
+/// Value of a `boolean` pair.
+struct Flag;
+
+impl Serialize for Flag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let data_type = DataType::Boolean;
+        serializer.serialize_unit_variant(ENUM, data_type as u32, data_type.name())
+    }
+}
+
+/// Value tagged with the index and name of its type.
+struct Tagged<T>(u32, &'static str, T);
+
+impl<T: Serialize> Serialize for Tagged<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_variant(ENUM, self.0, self.1, &self.2)
+    }
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Bytes read from either a byte string or a sequence of numbers.
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> de::Visitor<'de> for BytesVisitor {
+            type Value = ByteBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("bytes") }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ByteBuf, A::Error> {
+                let mut ret = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+                while let Some(byte) = seq.next_element()? {
+                    ret.push(byte);
+                }
+                Ok(ByteBuf(ret))
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
+/// Name of a variant, i.e. a type.
+struct Tag(DataType);
+
+impl<'de> Deserialize<'de> for Tag {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TagVisitor;
+
+        impl de::Visitor<'_> for TagVisitor {
+            type Value = Tag;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("name of an nvpair type")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Tag, E> {
+                match VARIANTS.get(v as usize) {
+                    Some(name) => self.visit_str(name),
+                    None => Err(E::invalid_value(de::Unexpected::Unsigned(v), &self)),
+                }
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Tag, E> {
+                match v.parse() {
+                    Ok(DataType::DontCare) | Err(_) => Err(E::unknown_variant(v, VARIANTS)),
+                    Ok(data_type) => Ok(Tag(data_type)),
+                }
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Tag, E> {
+                match std::str::from_utf8(v) {
+                    Ok(v) => self.visit_str(v),
+                    Err(_) => Err(E::invalid_value(de::Unexpected::Bytes(v), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_identifier(TagVisitor)
+    }
+}
+
+/// Value of a pair as read from a tagged enum.
+enum Entry {
+    Flag,
+    Value(Value),
+}
+
+impl<'de> Deserialize<'de> for Entry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_enum(ENUM, VARIANTS, EntryVisitor)
+    }
+}
+
+struct EntryVisitor;
+
+impl<'de> de::Visitor<'de> for EntryVisitor {
+    type Value = Entry;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a value tagged with its nvpair type")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Entry, A::Error> {
+        let (Tag(data_type), variant) = data.variant()?;
+        let value = match data_type {
+            DataType::Boolean => {
+                variant.unit_variant()?;
+                return Ok(Entry::Flag);
+            },
+            DataType::Unknown => {
+                variant.unit_variant()?;
+                Value::Unknown
+            },
+            DataType::BooleanValue => Value::Bool(variant.newtype_variant()?),
+            DataType::Byte => Value::Byte(variant.newtype_variant()?),
+            DataType::Int8 => Value::Int8(variant.newtype_variant()?),
+            DataType::Uint8 => Value::Uint8(variant.newtype_variant()?),
+            DataType::Int16 => Value::Int16(variant.newtype_variant()?),
+            DataType::Uint16 => Value::Uint16(variant.newtype_variant()?),
+            DataType::Int32 => Value::Int32(variant.newtype_variant()?),
+            DataType::Uint32 => Value::Uint32(variant.newtype_variant()?),
+            DataType::Int64 => Value::Int64(variant.newtype_variant()?),
+            DataType::Uint64 => Value::Uint64(variant.newtype_variant()?),
+            DataType::Hrtime => Value::Hrtime(variant.newtype_variant()?),
+            DataType::Double => Value::Double(variant.newtype_variant()?),
+            DataType::String => Value::String(variant.newtype_variant()?),
+            DataType::NvList => Value::NvList(variant.newtype_variant()?),
+            DataType::BooleanArray => Value::BoolArray(variant.newtype_variant()?),
+            DataType::ByteArray => Value::ByteArray(variant.newtype_variant::<ByteBuf>()?.0),
+            DataType::Int8Array => Value::Int8Array(variant.newtype_variant()?),
+            DataType::Uint8Array => Value::Uint8Array(variant.newtype_variant::<ByteBuf>()?.0),
+            DataType::Int16Array => Value::Int16Array(variant.newtype_variant()?),
+            DataType::Uint16Array => Value::Uint16Array(variant.newtype_variant()?),
+            DataType::Int32Array => Value::Int32Array(variant.newtype_variant()?),
+            DataType::Uint32Array => Value::Uint32Array(variant.newtype_variant()?),
+            DataType::Int64Array => Value::Int64Array(variant.newtype_variant()?),
+            DataType::Uint64Array => Value::Uint64Array(variant.newtype_variant()?),
+            DataType::StringArray => Value::StringArray(variant.newtype_variant()?),
+            DataType::NvListArray => Value::NvListArray(variant.newtype_variant()?),
+            DataType::DontCare => unreachable!("rejected by Tag"),
+        };
+        Ok(Entry::Value(value))
+    }
+}
+
+struct ListVisitor;
+
+impl<'de> de::Visitor<'de> for ListVisitor {
+    type Value = NvList;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map of names to values tagged with their nvpair type")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<NvList, A::Error> {
+        let mut list = NvList::new(NvFlag::UniqueNameType).map_err(de::Error::custom)?;
+        while let Some((key, entry)) = map.next_entry::<String, Entry>()? {
+            match entry {
+                Entry::Flag => list.insert_boolean(key.as_str()),
+                Entry::Value(value) => list.insert(key.as_str(), value),
+            }
+            .map_err(de::Error::custom)?;
+        }
+        Ok(list)
+    }
+}
@@ -0,0 +1,155 @@
+//! Lists shared between threads.
+
+use super::{NvList, NvListRef};
+use std::{fmt::{self, Debug, Formatter},
+          ops::Deref,
+          sync::{Arc, Mutex, MutexGuard}};
+
+/// List that can't be changed anymore and can be shared between threads.
+///
+/// libnvpair doesn't lock lists, and even reading a list writes to it: walking its pairs records
+/// the last pair visited. Reads therefore don't run concurrently, readers take turns, see
+/// [`FrozenNvList::read`].
+///
+/// ```
+/// use libnv::nvpair::{FrozenNvList, NvList};
+/// use std::{sync::Arc, thread};
+///
+/// let mut list = NvList::default();
+/// list.insert("ashift", 12u8).unwrap();
+/// let frozen = Arc::new(list.freeze());
+/// let reader = Arc::clone(&frozen);
+/// thread::spawn(move || assert_eq!(12, reader.read().get_u8("ashift").unwrap()))
+///     .join()
+///     .unwrap();
+/// assert_eq!(1, frozen.read().len());
+/// ```
+pub struct FrozenNvList {
+    list: NvList,
+    /// Held while the list is read.
+    lock: Mutex<()>,
+}
+
+// Only `read` hands out shared references to the list, and it holds the lock while they live.
+unsafe impl Sync for FrozenNvList {}
+
+impl FrozenNvList {
+    /// Borrow the list. Other threads wait until the returned guard is dropped, so don't call it
+    /// again on the same thread before that. This includes `Clone` and `Debug` of the list,
+    /// which deadlock while a guard is held; clone or print the guard instead.
+    pub fn read(&self) -> FrozenNvListGuard<'_> {
+        // The lock guards no data, so a panic while it was held didn't leave anything broken.
+        let guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        FrozenNvListGuard { list: &self.list, _guard: guard }
+    }
+
+    /// Borrow the list mutably. No lock is needed, since nothing else can read the list.
+    pub fn get_mut(&mut self) -> &mut NvListRef { &mut self.list }
+
+    /// Take the list back.
+    pub fn into_inner(self) -> NvList { self.list }
+}
+
+impl NvList {
+    /// Make the list immutable so it can be shared between threads.
+    pub fn freeze(self) -> FrozenNvList { FrozenNvList { list: self, lock: Mutex::new(()) } }
+}
+
+impl From<NvList> for FrozenNvList {
+    fn from(list: NvList) -> Self { list.freeze() }
+}
+
+/// Reads the list, see [`FrozenNvList::read`]. Use [`FrozenNvListGuard::to_frozen`] while a
+/// guard is held on the same thread.
+impl Clone for FrozenNvList {
+    /// # Panics
+    ///
+    /// Panics if libnvpair runs out of memory, see [`NvListRef::try_clone`].
+    fn clone(&self) -> Self { self.read().to_frozen() }
+}
+
+/// Reads the list, see [`FrozenNvList::read`]. Print the guard instead while one is held on the
+/// same thread.
+impl Debug for FrozenNvList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { self.read().fmt(f) }
+}
+
+/// Shared borrow of a [`FrozenNvList`], returned by [`FrozenNvList::read`].
+pub struct FrozenNvListGuard<'a> {
+    list:   &'a NvList,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl FrozenNvListGuard<'_> {
+    /// Copy the list into a new [`FrozenNvList`], like its `Clone` but without taking the lock
+    /// again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libnvpair runs out of memory, see [`NvListRef::try_clone`].
+    pub fn to_frozen(&self) -> FrozenNvList { self.list.to_owned().freeze() }
+}
+
+impl Deref for FrozenNvListGuard<'_> {
+    type Target = NvListRef;
+
+    fn deref(&self) -> &NvListRef { self.list }
+}
+
+impl Debug for FrozenNvListGuard<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { Debug::fmt(self.list, f) }
+}
+
+/// [`FrozenNvList`] behind an [`Arc`]: clones are cheap and share the same list until one of
+/// them is changed with [`SharedNvList::make_mut`], which copies the list if it's shared.
+///
+/// ```
+/// use libnv::nvpair::{NvList, SharedNvList};
+///
+/// let mut list = NvList::default();
+/// list.insert("txg", 7u64).unwrap();
+/// let config = SharedNvList::new(list);
+/// let mut updated = config.clone();
+/// updated.make_mut().insert("txg", 8u64).unwrap();
+/// assert_eq!(7, config.read().get_u64("txg").unwrap());
+/// assert_eq!(8, updated.read().get_u64("txg").unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct SharedNvList {
+    inner: Arc<FrozenNvList>,
+}
+
+impl SharedNvList {
+    /// Share `list`.
+    pub fn new(list: NvList) -> Self { SharedNvList { inner: Arc::new(list.freeze()) } }
+
+    /// Borrow the list, see [`FrozenNvList::read`].
+    pub fn read(&self) -> FrozenNvListGuard<'_> { self.inner.read() }
+
+    /// Borrow the list mutably, copying it first if other clones share it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libnvpair runs out of memory while copying the list.
+    pub fn make_mut(&mut self) -> &mut NvListRef { Arc::make_mut(&mut self.inner).get_mut() }
+
+    /// Whether `self` and `other` share the same list.
+    pub fn ptr_eq(&self, other: &SharedNvList) -> bool { Arc::ptr_eq(&self.inner, &other.inner) }
+
+    /// Take the list, copying it if other clones share it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if libnvpair runs out of memory while copying the list.
+    pub fn into_inner(self) -> NvList {
+        Arc::try_unwrap(self.inner).unwrap_or_else(|shared| (*shared).clone()).into_inner()
+    }
+}
+
+impl From<NvList> for SharedNvList {
+    fn from(list: NvList) -> Self { SharedNvList::new(list) }
+}
+
+impl From<FrozenNvList> for SharedNvList {
+    fn from(list: FrozenNvList) -> Self { SharedNvList { inner: Arc::new(list) } }
+}
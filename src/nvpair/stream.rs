@@ -0,0 +1,209 @@
+//! Reading and writing packed lists with `std::io`.
+//!
+//! Packed lists don't store their size, only a 4 byte header with the encoding and byte order.
+//! To read a list from a stream without reading past its end, the framing of pairs is followed:
+//!
+//! - XDR: a list starts with 8 bytes of version and flags. Every pair starts with its encoded size
+//!   that covers nested lists too. A list ends with 8 zero bytes.
+//! - Native: a list starts with 8 bytes of version and flags. Every pair starts with its size that
+//!   doesn't cover nested lists, those follow the pair. A list ends with 4 zero bytes.
+
+use super::{DataType, NvEncoding, NvList, NvListRef};
+use crate::{NvError, NvResult};
+use nvpair_sys as sys;
+use std::io::{self, Read, Write};
+
+/// Size of `nvs_header_t`.
+const HEADER_LEN: usize = 4;
+/// Size of version and flags at the start of every list.
+const LIST_HEADER_LEN: usize = 8;
+/// Size of the fixed part of a native `nvpair_t`.
+const NATIVE_PAIR_LEN: usize = 16;
+/// Size of encoded and decoded size, name length, type and number of elements of an XDR pair.
+const XDR_PAIR_LEN: usize = 20;
+
+impl NvListRef {
+    /// Pack the list with given encoding and write it to `writer`.
+    pub fn write_to<W: Write>(&self, mut writer: W, encoding: NvEncoding) -> NvResult<()> {
+        let buf = self.pack_to_vec(encoding)?;
+        writer.write_all(&buf).map_err(NvError::Io)
+    }
+}
+
+impl NvList {
+    /// Read a single packed list from `reader`. Nothing past the end of the list is read, so
+    /// lists written back to back can be read one by one.
+    pub fn read_from<R: Read>(mut reader: R) -> NvResult<NvList> {
+        match read_packed(&mut reader)? {
+            Some(buf) => NvList::try_from(&buf[..]),
+            None => Err(NvError::Io(io::ErrorKind::UnexpectedEof.into())),
+        }
+    }
+}
+
+/// Iterator over packed lists written back to back to a stream, i.e. with
+/// [`NvListRef::write_to`]. Stops at the end of the stream or after the first error.
+///
+/// ```
+/// use libnv::nvpair::{NvEncoding, NvList, PackedNvListReader};
+///
+/// let mut stream = Vec::new();
+/// for txg in 1..=3u64 {
+///     let mut record = NvList::default();
+///     record.insert("txg", txg).unwrap();
+///     record.write_to(&mut stream, NvEncoding::Xdr).unwrap();
+/// }
+///
+/// let txgs: Vec<u64> = PackedNvListReader::new(&stream[..])
+///     .map(|record| record.unwrap().get_u64("txg").unwrap())
+///     .collect();
+/// assert_eq!(vec![1, 2, 3], txgs);
+/// ```
+pub struct PackedNvListReader<R> {
+    reader: R,
+    done:   bool,
+}
+
+impl<R: Read> PackedNvListReader<R> {
+    pub fn new(reader: R) -> Self { PackedNvListReader { reader, done: false } }
+
+    /// Get back the underlying reader.
+    pub fn into_inner(self) -> R { self.reader }
+}
+
+impl<R: Read> Iterator for PackedNvListReader<R> {
+    type Item = NvResult<NvList>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let ret = match read_packed(&mut self.reader) {
+            Ok(Some(buf)) => NvList::try_from(&buf[..]),
+            Ok(None) => {
+                self.done = true;
+                return None;
+            },
+            Err(e) => Err(e),
+        };
+        self.done = ret.is_err();
+        Some(ret)
+    }
+}
+
+impl<R: Read> std::iter::FusedIterator for PackedNvListReader<R> {}
+
+/// Read bytes of one packed list. `None` if the stream ended before the first byte.
+fn read_packed<R: Read>(reader: &mut R) -> NvResult<Option<Vec<u8>>> {
+    let mut buf = Vec::new();
+    reader.by_ref().take(HEADER_LEN as u64).read_to_end(&mut buf).map_err(NvError::Io)?;
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    let mut stream = Stream { reader, buf };
+    stream.fill(HEADER_LEN)?;
+    let little_endian = stream.buf[1] == 1;
+    match i32::from(stream.buf[0]) {
+        sys::NV_ENCODE_XDR => stream.read_xdr()?,
+        sys::NV_ENCODE_NATIVE => stream.read_native(little_endian)?,
+        other => return Err(invalid_data(format!("unknown encoding {}", other))),
+    }
+    Ok(Some(stream.buf))
+}
+
+struct Stream<'a, R> {
+    reader: &'a mut R,
+    buf:    Vec<u8>,
+}
+
+impl<R: Read> Stream<'_, R> {
+    /// Read more bytes until the buffer holds `len` bytes. The buffer grows as data arrives, so
+    /// a bogus size can't make it allocate more than the stream holds.
+    fn fill(&mut self, len: usize) -> NvResult<()> {
+        let missing = len.saturating_sub(self.buf.len());
+        let read = self
+            .reader
+            .by_ref()
+            .take(missing as u64)
+            .read_to_end(&mut self.buf)
+            .map_err(NvError::Io)?;
+        if read < missing {
+            Err(NvError::Io(io::ErrorKind::UnexpectedEof.into()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Read `len` more bytes and return them.
+    fn next(&mut self, len: usize) -> NvResult<&[u8]> {
+        let start = self.buf.len();
+        self.fill(start + len)?;
+        Ok(&self.buf[start..])
+    }
+
+    fn read_xdr(&mut self) -> NvResult<()> {
+        self.next(LIST_HEADER_LEN)?;
+        loop {
+            let sizes = self.next(8)?;
+            let encoded = i32::from_be_bytes(sizes[..4].try_into().unwrap());
+            let decoded = i32::from_be_bytes(sizes[4..].try_into().unwrap());
+            if encoded == 0 && decoded == 0 {
+                return Ok(());
+            }
+            if encoded < XDR_PAIR_LEN as i32 {
+                return Err(invalid_data(format!("invalid pair size {}", encoded)));
+            }
+            self.next(encoded as usize - 8)?;
+        }
+    }
+
+    fn read_native(&mut self, little_endian: bool) -> NvResult<()> {
+        let read_i32 = |bytes: &[u8]| {
+            let bytes = bytes[..4].try_into().unwrap();
+            if little_endian {
+                i32::from_le_bytes(bytes)
+            } else {
+                i32::from_be_bytes(bytes)
+            }
+        };
+        // Number of lists left to read after the current one for every list being read. Nested
+        // lists follow the pair holding them, so this is kept on the heap instead of recursing.
+        let mut siblings = vec![0];
+        self.next(LIST_HEADER_LEN)?;
+        loop {
+            let size = read_i32(self.next(4)?);
+            if size == 0 {
+                match siblings.pop() {
+                    Some(0) if siblings.is_empty() => return Ok(()),
+                    Some(0) => continue,
+                    Some(left) => {
+                        siblings.push(left - 1);
+                        self.next(LIST_HEADER_LEN)?;
+                        continue;
+                    },
+                    None => unreachable!("stack is never empty while reading"),
+                }
+            }
+            if size < NATIVE_PAIR_LEN as i32 {
+                return Err(invalid_data(format!("invalid pair size {}", size)));
+            }
+            let pair = self.next(size as usize - 4)?;
+            let elements = read_i32(&pair[4..]);
+            match DataType::from(read_i32(&pair[8..]) as sys::data_type_t::Type) {
+                DataType::NvList => {
+                    siblings.push(0);
+                    self.next(LIST_HEADER_LEN)?;
+                },
+                DataType::NvListArray if elements > 0 => {
+                    siblings.push(elements as usize - 1);
+                    self.next(LIST_HEADER_LEN)?;
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> NvError {
+    NvError::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
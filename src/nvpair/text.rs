@@ -0,0 +1,649 @@
+//! Human readable text format that keeps the type of every pair.
+//!
+//! Every pair is written as `name: type = value`, pairs of type `boolean` have no value and are
+//! written as `name: type`. Types are the ones returned by [`DataType::name`], arrays are written
+//! as the type of their elements followed by `[]`:
+//!
+//! ```text
+//! # Comments run to the end of the line.
+//! name: string = "tank"
+//! guid: uint64 = 0x5c0ffee
+//! readonly: boolean
+//! txgs: uint64[] = [1, 2, 3]
+//! vdev_tree: nvlist = {
+//!   type: string = "root"
+//!   children: nvlist[] = [
+//!     {
+//!       guid: uint64 = 1
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Names made of letters, digits and `_-./@+` can be written without quotes. Integers may be
+//! written in hex with a `0x` prefix. Doubles are written so that reading them back gives the same
+//! value, including `NaN`, `inf` and `-inf`. Strings use `\"`, `\\`, `\n`, `\r`, `\t` and
+//! `\u{…}` escapes. Bytes of strings and names that are not valid UTF-8 are written as `\xHH`
+//! escapes, so they are read back as they were.
+//!
+//! Output of [`to_string`] depends only on the contents of the list, so it can be checked into
+//! git and compared.
+
+use super::{parse_error, DataType, Flow, KeyPath, NvFlag, NvList, NvListRef, Redactor, Segment,
+            Value, Visitor};
+use crate::{NvError, NvResult};
+use std::{ffi::CStr,
+          io::{self, Write}};
+
+/// Nesting deeper than this is rejected, so malicious input can't overflow the stack.
+const MAX_DEPTH: usize = 256;
+
+/// Render the list as text.
+///
+/// ```
+/// use libnv::nvpair::{text, NvList};
+///
+/// let mut list = NvList::default();
+/// list.insert("name", "tank").unwrap();
+/// list.insert_u64_array("txgs", &[1, 2]).unwrap();
+/// assert_eq!("name: string = \"tank\"\ntxgs: uint64[] = [1, 2]\n", text::to_string(&list).unwrap());
+/// ```
+pub fn to_string(list: &NvListRef) -> NvResult<String> {
+    let mut buf = Vec::new();
+    write(list, &mut buf)?;
+    Ok(String::from_utf8(buf).expect("text output is UTF-8"))
+}
+
+/// Write the list as text to `writer`. See [`to_string`].
+pub fn write<W: Write>(list: &NvListRef, writer: W) -> NvResult<()> {
+    write_with(list, writer, None)
+}
+
+/// Like [`write`], but values hidden by `redactor` are written as its placeholder. The output
+/// can't be read back.
+pub(super) fn write_redacted<W: Write>(
+    list: &NvListRef,
+    writer: W,
+    redactor: &Redactor,
+) -> NvResult<()> {
+    write_with(list, writer, Some(redactor))
+}
+
+fn write_with<W: Write>(list: &NvListRef, writer: W, redactor: Option<&Redactor>) -> NvResult<()> {
+    let mut printer =
+        Printer { out: writer, open: Vec::new(), started: false, redactor, error: None };
+    let finished = list.accept(&mut printer);
+    if let Some(err) = printer.error {
+        return Err(err);
+    }
+    debug_assert!(finished);
+    if printer.started {
+        printer.out.write_all(b"\n").map_err(NvError::Io)?;
+    }
+    Ok(())
+}
+
+/// Read a list written by [`to_string`] or by hand. Errors are reported as [`NvError::Parse`]
+/// with the line and column where parsing failed.
+///
+/// ```
+/// use libnv::nvpair::{text, DataType};
+///
+/// let list = text::from_str("ashift: uint8 = 12 # bytes are 1 << ashift").unwrap();
+/// assert_eq!(DataType::Uint8, list.type_of("ashift").unwrap());
+/// ```
+pub fn from_str(text: &str) -> NvResult<NvList> {
+    let mut parser = Parser { src: text, pos: 0 };
+    parser.list(0, false)
+}
+
+/// Type of elements of an array type.
+fn element_type(array: DataType) -> Option<DataType> {
+    Some(match array {
+        DataType::BooleanArray => DataType::BooleanValue,
+        DataType::ByteArray => DataType::Byte,
+        DataType::Int8Array => DataType::Int8,
+        DataType::Uint8Array => DataType::Uint8,
+        DataType::Int16Array => DataType::Int16,
+        DataType::Uint16Array => DataType::Uint16,
+        DataType::Int32Array => DataType::Int32,
+        DataType::Uint32Array => DataType::Uint32,
+        DataType::Int64Array => DataType::Int64,
+        DataType::Uint64Array => DataType::Uint64,
+        DataType::StringArray => DataType::String,
+        DataType::NvListArray => DataType::NvList,
+        _ => return None,
+    })
+}
+
+/// Type of arrays of `element`.
+fn array_type(element: DataType) -> Option<DataType> {
+    [
+        DataType::BooleanArray,
+        DataType::ByteArray,
+        DataType::Int8Array,
+        DataType::Uint8Array,
+        DataType::Int16Array,
+        DataType::Uint16Array,
+        DataType::Int32Array,
+        DataType::Uint32Array,
+        DataType::Int64Array,
+        DataType::Uint64Array,
+        DataType::StringArray,
+        DataType::NvListArray,
+    ]
+    .into_iter()
+    .find(|array| element_type(*array) == Some(element))
+}
+
+fn is_bare(c: char) -> bool { c.is_ascii_alphanumeric() || "_-./@+".contains(c) }
+
+fn is_bare_key(key: &[u8]) -> bool { !key.is_empty() && key.iter().all(|b| is_bare(*b as char)) }
+
+/// An nvlist or an array being written.
+struct Open {
+    /// Elements are written on one line.
+    inline: bool,
+    /// Nothing was written to it yet.
+    empty:  bool,
+}
+
+struct Printer<'r, W> {
+    out:      W,
+    open:     Vec<Open>,
+    /// Something was written already.
+    started:  bool,
+    /// Hides some values.
+    redactor: Option<&'r Redactor>,
+    error:    Option<NvError>,
+}
+
+impl<W: Write> Printer<'_, W> {
+    /// Start a value: write the name and type of a pair or separate an element from the previous
+    /// one.
+    fn begin(&mut self, path: &KeyPath, data_type: DataType) -> io::Result<()> {
+        let first =
+            self.open.last_mut().is_some_and(|open| std::mem::replace(&mut open.empty, false));
+        match path.segments().last() {
+            Some(Segment::Name(_)) => {
+                self.newline()?;
+                let key = path.key().expect("last name of the path");
+                if is_bare_key(key.to_bytes()) {
+                    self.out.write_all(key.to_bytes())?;
+                } else {
+                    write_string(&mut self.out, key.to_bytes())?;
+                }
+                match element_type(data_type) {
+                    Some(element) => write!(self.out, ": {}[]", element.name())?,
+                    None => write!(self.out, ": {}", data_type.name())?,
+                }
+                if data_type != DataType::Boolean {
+                    self.out.write_all(b" = ")?;
+                }
+            },
+            _ => match self.open.last() {
+                Some(Open { inline: true, .. }) if !first => self.out.write_all(b", ")?,
+                Some(Open { inline: true, .. }) => (),
+                _ => {
+                    if !first {
+                        self.out.write_all(b",")?;
+                    }
+                    self.newline()?;
+                },
+            },
+        }
+        Ok(())
+    }
+
+    fn newline(&mut self) -> io::Result<()> {
+        if std::mem::replace(&mut self.started, true) {
+            write!(self.out, "\n{:1$}", "", self.open.len() * 2)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self, bracket: &[u8]) -> io::Result<()> {
+        let open = self.open.pop().expect("unbalanced close");
+        if !open.inline && !open.empty {
+            self.newline()?;
+        }
+        self.out.write_all(bracket)
+    }
+
+    /// Write the placeholder instead of the value if the redactor hides it. Returns `false` if
+    /// the value should be written.
+    fn redacted(&mut self, path: &KeyPath, data_type: DataType) -> io::Result<bool> {
+        let Some(redactor) = self.redactor.filter(|r| r.hides_key_path(path, data_type)) else {
+            return Ok(false);
+        };
+        self.begin(path, data_type)?;
+        if data_type == DataType::Boolean {
+            // `begin` leaves out the `=` of flags, which have no value.
+            self.out.write_all(b" = ")?;
+        }
+        self.out.write_all(redactor.placeholder_text().as_bytes())?;
+        Ok(true)
+    }
+
+    fn value(&mut self, path: &KeyPath, value: &Value) -> io::Result<()> {
+        if self.redacted(path, value.data_type())? {
+            return Ok(());
+        }
+        self.begin(path, value.data_type())?;
+        match value {
+            Value::Bool(v) => write!(self.out, "{}", v),
+            Value::Byte(v) | Value::Uint8(v) => write!(self.out, "{}", v),
+            Value::Int8(v) => write!(self.out, "{}", v),
+            Value::Int16(v) => write!(self.out, "{}", v),
+            Value::Uint16(v) => write!(self.out, "{}", v),
+            Value::Int32(v) => write!(self.out, "{}", v),
+            Value::Uint32(v) => write!(self.out, "{}", v),
+            Value::Int64(v) | Value::Hrtime(v) => write!(self.out, "{}", v),
+            Value::Uint64(v) => write!(self.out, "{}", v),
+            Value::Double(v) => write!(self.out, "{:?}", v),
+            Value::String(v) => write_string(&mut self.out, v.as_bytes()),
+            _ => unreachable!("visited separately"),
+        }
+    }
+
+    /// Turn the result of a write into a flow, keeping the error.
+    fn flow(&mut self, result: io::Result<()>) -> Flow {
+        match result {
+            Ok(()) => Flow::Continue,
+            Err(err) => {
+                self.error = Some(NvError::Io(err));
+                Flow::Stop
+            },
+        }
+    }
+}
+
+impl<W: Write> Visitor for Printer<'_, W> {
+    fn visit_value(&mut self, path: &KeyPath, value: Value) -> Flow {
+        if value.data_type() == DataType::Unknown {
+            self.error = Some(NvError::from_errno(libc::EINVAL));
+            return Flow::Stop;
+        }
+        let result = self.value(path, &value);
+        self.flow(result)
+    }
+
+    fn visit_boolean(&mut self, path: &KeyPath) -> Flow {
+        let result = self.redacted(path, DataType::Boolean).and_then(|redacted| {
+            if !redacted {
+                self.begin(path, DataType::Boolean)?;
+            }
+            Ok(())
+        });
+        self.flow(result)
+    }
+
+    fn visit_c_str(&mut self, path: &KeyPath, value: &CStr) -> Flow {
+        let result = self.redacted(path, DataType::String).and_then(|redacted| {
+            if !redacted {
+                self.begin(path, DataType::String)?;
+                write_string(&mut self.out, value.to_bytes())?;
+            }
+            Ok(())
+        });
+        self.flow(result)
+    }
+
+    fn enter_list(&mut self, path: &KeyPath) -> Flow {
+        match self.redacted(path, DataType::NvList) {
+            Ok(true) => return Flow::Skip,
+            Ok(false) => (),
+            Err(err) => return self.flow(Err(err)),
+        }
+        let result = self.begin(path, DataType::NvList).and_then(|()| self.out.write_all(b"{"));
+        self.open.push(Open { inline: false, empty: true });
+        self.flow(result)
+    }
+
+    fn leave_list(&mut self, _path: &KeyPath) -> Flow {
+        let result = self.close(b"}");
+        self.flow(result)
+    }
+
+    fn enter_array(&mut self, path: &KeyPath, data_type: DataType, _len: usize) -> Flow {
+        match self.redacted(path, data_type) {
+            Ok(true) => return Flow::Skip,
+            Ok(false) => (),
+            Err(err) => return self.flow(Err(err)),
+        }
+        let result = self.begin(path, data_type).and_then(|()| self.out.write_all(b"["));
+        self.open.push(Open { inline: data_type != DataType::NvListArray, empty: true });
+        self.flow(result)
+    }
+
+    fn leave_array(&mut self, _path: &KeyPath) -> Flow {
+        let result = self.close(b"]");
+        self.flow(result)
+    }
+}
+
+/// Write `value` quoted, with bytes that aren't valid UTF-8 escaped as `\xHH`.
+fn write_string<W: Write>(out: &mut W, mut value: &[u8]) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    loop {
+        match std::str::from_utf8(value) {
+            Ok(valid) => {
+                write_chars(out, valid)?;
+                break;
+            },
+            Err(err) => {
+                let (valid, rest) = value.split_at(err.valid_up_to());
+                write_chars(out, std::str::from_utf8(valid).expect("checked above"))?;
+                let (invalid, rest) = rest.split_at(err.error_len().unwrap_or(rest.len()));
+                for byte in invalid {
+                    write!(out, "\\x{:02x}", byte)?;
+                }
+                value = rest;
+            },
+        }
+    }
+    out.write_all(b"\"")
+}
+
+fn write_chars<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    for c in value.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if c.is_control() => write!(out, "\\u{{{:x}}}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, offset: usize, message: String) -> NvError {
+        parse_error(self.src, offset, message)
+    }
+
+    fn peek(&self) -> Option<char> { self.src[self.pos..].chars().next() }
+
+    /// Skip whitespace and comments.
+    fn skip(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                self.pos += self.src[self.pos..].find('\n').unwrap_or(self.src.len() - self.pos);
+            } else if c.is_whitespace() {
+                self.pos += c.len_utf8();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> NvResult<()> {
+        self.skip();
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(self.pos, format!("expected `{}`", expected)))
+        }
+    }
+
+    /// Read a run of characters allowed in names, numbers and types.
+    fn word(&mut self) -> &'a str {
+        let start = self.pos;
+        while let Some(c) = self.peek().filter(|c| is_bare(*c)) {
+            self.pos += c.len_utf8();
+        }
+        &self.src[start..self.pos]
+    }
+
+    /// Read pairs until the closing `}` of a nested list or the end of input.
+    fn list(&mut self, depth: usize, nested: bool) -> NvResult<NvList> {
+        if depth > MAX_DEPTH {
+            return Err(self.error(self.pos, "nesting is too deep".into()));
+        }
+        let mut list = NvList::new(NvFlag::UniqueNameType)?;
+        loop {
+            self.skip();
+            match self.peek() {
+                Some('}') if nested => {
+                    self.pos += 1;
+                    return Ok(list);
+                },
+                None if nested => return Err(self.error(self.pos, "expected `}`".into())),
+                None => return Ok(list),
+                _ => (),
+            }
+            let offset = self.pos;
+            let key = match self.peek() {
+                Some('"') => self.string()?,
+                _ => self.word().as_bytes().to_vec(),
+            };
+            if key.is_empty() {
+                return Err(self.error(offset, "expected a name".into()));
+            }
+            if list.exists(key.as_slice())? {
+                let key = String::from_utf8_lossy(&key);
+                return Err(self.error(offset, format!("duplicate name {}", key)));
+            }
+            self.expect(':')?;
+            let data_type = self.data_type()?;
+            if data_type == DataType::Boolean {
+                list.insert_boolean(key.as_slice())?;
+                continue;
+            }
+            self.expect('=')?;
+            self.skip();
+            // Strings may not be valid UTF-8, so they don't go through `Value`.
+            match data_type {
+                DataType::String => {
+                    list.insert_string(key.as_slice(), self.string()?.as_slice())?
+                },
+                DataType::StringArray => {
+                    let values = self.array(|p| p.string())?;
+                    list.insert_string_array(key.as_slice(), values.iter().map(Vec::as_slice))?
+                },
+                _ => list.insert(key.as_slice(), self.value(data_type, depth)?)?,
+            }
+        }
+    }
+
+    fn data_type(&mut self) -> NvResult<DataType> {
+        self.skip();
+        let offset = self.pos;
+        let name = self.word();
+        let data_type = match name.parse() {
+            Ok(DataType::DontCare | DataType::Unknown) | Err(_) => None,
+            Ok(data_type) => Some(data_type),
+        };
+        let data_type = if self.src[self.pos..].starts_with("[]") {
+            self.pos += 2;
+            data_type.and_then(array_type)
+        } else {
+            data_type.filter(|data_type| element_type(*data_type).is_none())
+        };
+        data_type.ok_or_else(|| {
+            let name = &self.src[offset..self.pos];
+            self.error(offset, format!("unknown type {}", name))
+        })
+    }
+
+    fn value(&mut self, data_type: DataType, depth: usize) -> NvResult<Value> {
+        Ok(match data_type {
+            DataType::BooleanValue => Value::Bool(self.bool()?),
+            DataType::Byte => Value::Byte(self.int(data_type)?),
+            DataType::Int8 => Value::Int8(self.int(data_type)?),
+            DataType::Uint8 => Value::Uint8(self.int(data_type)?),
+            DataType::Int16 => Value::Int16(self.int(data_type)?),
+            DataType::Uint16 => Value::Uint16(self.int(data_type)?),
+            DataType::Int32 => Value::Int32(self.int(data_type)?),
+            DataType::Uint32 => Value::Uint32(self.int(data_type)?),
+            DataType::Int64 => Value::Int64(self.int(data_type)?),
+            DataType::Uint64 => Value::Uint64(self.int(data_type)?),
+            DataType::Hrtime => Value::Hrtime(self.int(data_type)?),
+            DataType::Double => Value::Double(self.double()?),
+            DataType::NvList => Value::NvList(self.nested(depth)?),
+            DataType::BooleanArray => Value::BoolArray(self.array(|p| p.bool())?),
+            DataType::ByteArray => Value::ByteArray(self.array(|p| p.int(DataType::Byte))?),
+            DataType::Int8Array => Value::Int8Array(self.array(|p| p.int(DataType::Int8))?),
+            DataType::Uint8Array => Value::Uint8Array(self.array(|p| p.int(DataType::Uint8))?),
+            DataType::Int16Array => Value::Int16Array(self.array(|p| p.int(DataType::Int16))?),
+            DataType::Uint16Array => Value::Uint16Array(self.array(|p| p.int(DataType::Uint16))?),
+            DataType::Int32Array => Value::Int32Array(self.array(|p| p.int(DataType::Int32))?),
+            DataType::Uint32Array => Value::Uint32Array(self.array(|p| p.int(DataType::Uint32))?),
+            DataType::Int64Array => Value::Int64Array(self.array(|p| p.int(DataType::Int64))?),
+            DataType::Uint64Array => Value::Uint64Array(self.array(|p| p.int(DataType::Uint64))?),
+            DataType::NvListArray => Value::NvListArray(self.array(|p| p.nested(depth))?),
+            DataType::Boolean | DataType::String | DataType::StringArray => {
+                unreachable!("inserted by Parser::list")
+            },
+            DataType::Unknown | DataType::DontCare => unreachable!("rejected by Parser::data_type"),
+        })
+    }
+
+    fn nested(&mut self, depth: usize) -> NvResult<NvList> {
+        if self.peek() != Some('{') {
+            return Err(self.error(self.pos, "expected `{`".into()));
+        }
+        self.pos += 1;
+        self.list(depth + 1, true)
+    }
+
+    /// Read elements separated by commas between `[` and `]`. A trailing comma is allowed.
+    fn array<T, F: FnMut(&mut Self) -> NvResult<T>>(&mut self, mut element: F) -> NvResult<Vec<T>> {
+        if self.peek() != Some('[') {
+            return Err(self.error(self.pos, "expected `[`".into()));
+        }
+        self.pos += 1;
+        let mut ret = Vec::new();
+        loop {
+            self.skip();
+            if self.peek() == Some(']') {
+                self.pos += 1;
+                return Ok(ret);
+            }
+            ret.push(element(self)?);
+            self.skip();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => (),
+                _ => return Err(self.error(self.pos, "expected `,` or `]`".into())),
+            }
+        }
+    }
+
+    fn bool(&mut self) -> NvResult<bool> {
+        let offset = self.pos;
+        match self.word() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(self.error(offset, "expected `true` or `false`".into())),
+        }
+    }
+
+    fn int<T: TryFrom<i128>>(&mut self, data_type: DataType) -> NvResult<T> {
+        let offset = self.pos;
+        let word = self.word();
+        let (negative, digits) = match word.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, word),
+        };
+        // Both parsers take a sign of their own, which is only allowed before the prefix.
+        let (unsigned, value) = match digits.strip_prefix("0x") {
+            Some(hex) => (hex, i128::from_str_radix(hex, 16)),
+            None => (digits, digits.parse::<i128>()),
+        };
+        value
+            .ok()
+            .filter(|_| !unsigned.starts_with(['-', '+']))
+            .map(|value| if negative { -value } else { value })
+            .and_then(|value| T::try_from(value).ok())
+            .ok_or_else(|| self.error(offset, format!("{} is not a valid {}", word, data_type)))
+    }
+
+    fn double(&mut self) -> NvResult<f64> {
+        let offset = self.pos;
+        let word = self.word();
+        word.parse().map_err(|_| self.error(offset, format!("{} is not a valid double", word)))
+    }
+
+    /// Read a quoted string. It's returned as bytes, since `\x` escapes can make it invalid
+    /// UTF-8.
+    fn string(&mut self) -> NvResult<Vec<u8>> {
+        if self.peek() != Some('"') {
+            return Err(self.error(self.pos, "expected a string".into()));
+        }
+        self.pos += 1;
+        let mut ret = Vec::new();
+        loop {
+            let offset = self.pos;
+            let c = self.peek().ok_or_else(|| self.error(offset, "unterminated string".into()))?;
+            self.pos += c.len_utf8();
+            let c = match c {
+                '"' => return Ok(ret),
+                '\\' => match self.escape(offset)? {
+                    Escaped::Char(c) => c,
+                    Escaped::Byte(byte) => {
+                        ret.push(byte);
+                        continue;
+                    },
+                },
+                '\0' => return Err(self.error(offset, "strings can't contain NUL".into())),
+                c => c,
+            };
+            ret.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+
+    /// Parse an escape sequence after `\` found at `offset`.
+    fn escape(&mut self, offset: usize) -> NvResult<Escaped> {
+        let c = self.peek();
+        self.pos += c.map_or(0, char::len_utf8);
+        let c = match c {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('x') => {
+                let byte = self.src[self.pos..]
+                    .get(..2)
+                    .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .filter(|byte| *byte != 0)
+                    .ok_or_else(|| self.error(offset, "invalid byte escape".into()))?;
+                self.pos += 2;
+                return Ok(Escaped::Byte(byte));
+            },
+            Some('u') => {
+                let rest = &self.src[self.pos..];
+                let c = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .and_then(|(hex, _)| {
+                        self.pos += hex.len() + 2;
+                        u32::from_str_radix(hex, 16).ok()
+                    })
+                    .and_then(char::from_u32)
+                    .filter(|c| *c != '\0');
+                c.ok_or_else(|| self.error(offset, "invalid unicode escape".into()))?
+            },
+            _ => return Err(self.error(offset, "invalid escape sequence".into())),
+        };
+        Ok(Escaped::Char(c))
+    }
+}
+
+/// Result of [`Parser::escape`].
+enum Escaped {
+    Char(char),
+    /// Byte of a string that isn't valid UTF-8.
+    Byte(u8),
+}
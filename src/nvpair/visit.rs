@@ -0,0 +1,375 @@
+//! Walking nested lists with a [`Visitor`].
+
+use super::{raw_slice, DataType, NvListArrayIter, NvListIter, NvListRef, NvPairRef, Value};
+use crate::NvKey;
+use nvpair_sys as sys;
+use std::{ffi::CStr, fmt, iter::Enumerate, ptr::null_mut};
+
+/// What to do after a [`Visitor`] callback returns.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Flow {
+    /// Keep going.
+    Continue,
+    /// Don't descend into the list or array that is being entered. Same as `Continue` when
+    /// returned from any other callback.
+    Skip,
+    /// Stop the walk.
+    Stop,
+}
+
+/// One step of a [`KeyPath`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Segment {
+    /// Name of a pair.
+    Name(String),
+    /// Index of an element in an array.
+    Index(usize),
+}
+
+/// Location of a value inside the visited list, i.e. `vdev_tree.children[0].guid`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct KeyPath {
+    segments: Vec<Segment>,
+    /// Names as stored in the list, one for every `Segment::Name`.
+    keys:     Vec<NvKey<'static>>,
+}
+
+impl KeyPath {
+    /// Steps from the visited list to the value.
+    pub fn segments(&self) -> &[Segment] { &self.segments }
+
+    /// Name of the pair holding the value, ignoring array indexes.
+    pub fn name(&self) -> Option<&str> {
+        self.segments.iter().rev().find_map(|segment| match segment {
+            Segment::Name(name) => Some(name.as_str()),
+            Segment::Index(_) => None,
+        })
+    }
+
+    /// Like [`KeyPath::name`], but as stored in the list instead of converted to UTF-8.
+    pub fn key(&self) -> Option<&CStr> { self.keys.last().map(NvKey::as_c_str) }
+
+    /// Number of steps from the visited list.
+    pub fn depth(&self) -> usize { self.segments.len() }
+
+    fn push_name(&mut self, key: &CStr) {
+        self.segments.push(Segment::Name(key.to_string_lossy().into_owned()));
+        self.keys.push(NvKey::new(key.to_bytes()).expect("C strings contain no NUL"));
+    }
+
+    fn pop(&mut self) {
+        if let Some(Segment::Name(_)) = self.segments.pop() {
+            self.keys.pop();
+        }
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Name(name) if idx == 0 => f.write_str(name)?,
+                Segment::Name(name) => write!(f, ".{}", name)?,
+                Segment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Callbacks invoked by [`NvListRef::accept`] for every value of a list and lists nested in it.
+///
+/// Typed callbacks fall back to [`Visitor::visit_value`], which does nothing by default, so
+/// implement only the ones you need. Elements of arrays are visited one by one with their index
+/// at the end of the path, between [`Visitor::enter_array`] and [`Visitor::leave_array`].
+#[allow(unused_variables)]
+pub trait Visitor {
+    /// Called for every value that doesn't have its own callback implemented.
+    fn visit_value(&mut self, path: &KeyPath, value: Value) -> Flow { Flow::Continue }
+
+    /// Called for a `boolean` pair that has no value.
+    fn visit_boolean(&mut self, path: &KeyPath) -> Flow {
+        self.visit_value(path, Value::Bool(true))
+    }
+
+    fn visit_bool(&mut self, path: &KeyPath, value: bool) -> Flow {
+        self.visit_value(path, Value::Bool(value))
+    }
+
+    fn visit_byte(&mut self, path: &KeyPath, value: u8) -> Flow {
+        self.visit_value(path, Value::Byte(value))
+    }
+
+    fn visit_i8(&mut self, path: &KeyPath, value: i8) -> Flow {
+        self.visit_value(path, Value::Int8(value))
+    }
+
+    fn visit_u8(&mut self, path: &KeyPath, value: u8) -> Flow {
+        self.visit_value(path, Value::Uint8(value))
+    }
+
+    fn visit_i16(&mut self, path: &KeyPath, value: i16) -> Flow {
+        self.visit_value(path, Value::Int16(value))
+    }
+
+    fn visit_u16(&mut self, path: &KeyPath, value: u16) -> Flow {
+        self.visit_value(path, Value::Uint16(value))
+    }
+
+    fn visit_i32(&mut self, path: &KeyPath, value: i32) -> Flow {
+        self.visit_value(path, Value::Int32(value))
+    }
+
+    fn visit_u32(&mut self, path: &KeyPath, value: u32) -> Flow {
+        self.visit_value(path, Value::Uint32(value))
+    }
+
+    fn visit_i64(&mut self, path: &KeyPath, value: i64) -> Flow {
+        self.visit_value(path, Value::Int64(value))
+    }
+
+    fn visit_u64(&mut self, path: &KeyPath, value: u64) -> Flow {
+        self.visit_value(path, Value::Uint64(value))
+    }
+
+    fn visit_hrtime(&mut self, path: &KeyPath, value: i64) -> Flow {
+        self.visit_value(path, Value::Hrtime(value))
+    }
+
+    fn visit_double(&mut self, path: &KeyPath, value: f64) -> Flow {
+        self.visit_value(path, Value::Double(value))
+    }
+
+    /// Strings that are not valid UTF-8 are converted lossily, implement
+    /// [`Visitor::visit_c_str`] to get them as they are.
+    fn visit_string(&mut self, path: &KeyPath, value: &str) -> Flow {
+        self.visit_value(path, Value::String(value.to_owned()))
+    }
+
+    /// Called for strings and elements of string arrays with the bytes stored in the list.
+    /// Converts them lossily and calls [`Visitor::visit_string`] by default.
+    fn visit_c_str(&mut self, path: &KeyPath, value: &CStr) -> Flow {
+        self.visit_string(path, &value.to_string_lossy())
+    }
+
+    /// Called before pairs of a nested list are visited. Return [`Flow::Skip`] to not descend
+    /// into it, [`Visitor::leave_list`] isn't called then.
+    fn enter_list(&mut self, path: &KeyPath) -> Flow { Flow::Continue }
+
+    /// Called after all pairs of a nested list were visited.
+    fn leave_list(&mut self, path: &KeyPath) -> Flow { Flow::Continue }
+
+    /// Called before elements of an array are visited. `data_type` is the type of the array.
+    /// Return [`Flow::Skip`] to not visit the elements, [`Visitor::leave_array`] isn't called
+    /// then.
+    fn enter_array(&mut self, path: &KeyPath, data_type: DataType, len: usize) -> Flow {
+        Flow::Continue
+    }
+
+    /// Called after all elements of an array were visited.
+    fn leave_array(&mut self, path: &KeyPath) -> Flow { Flow::Continue }
+}
+
+/// Lists that are being walked. Kept on the heap, so deep nesting can't overflow the stack.
+enum Frame<'a> {
+    List(NvListIter<'a>),
+    Array(Enumerate<NvListArrayIter<'a>>),
+}
+
+impl NvListRef {
+    /// Walk the list and every list nested in it, depth first and in order, calling `visitor`
+    /// for each value. The list itself is not reported with [`Visitor::enter_list`]. Returns
+    /// `false` if the visitor stopped the walk with [`Flow::Stop`].
+    ///
+    /// ```
+    /// use libnv::nvpair::{Flow, KeyPath, NvList, Visitor};
+    ///
+    /// struct Guids(Vec<String>);
+    ///
+    /// impl Visitor for Guids {
+    ///     fn visit_u64(&mut self, path: &KeyPath, _: u64) -> Flow {
+    ///         if path.name() == Some("guid") {
+    ///             self.0.push(path.to_string());
+    ///         }
+    ///         Flow::Continue
+    ///     }
+    /// }
+    ///
+    /// let mut child = NvList::default();
+    /// child.insert("guid", 2u64).unwrap();
+    /// let mut tree = NvList::default();
+    /// tree.insert("guid", 1u64).unwrap();
+    /// tree.insert_nvlist_array("children", &[child]).unwrap();
+    ///
+    /// let mut guids = Guids(Vec::new());
+    /// assert!(tree.accept(&mut guids));
+    /// assert_eq!(vec!["guid", "children[0].guid"], guids.0);
+    /// ```
+    pub fn accept<V: Visitor + ?Sized>(&self, visitor: &mut V) -> bool {
+        let mut path = KeyPath::default();
+        let mut stack = vec![Frame::List(self.iter())];
+        while let Some(frame) = stack.last_mut() {
+            let flow = match frame {
+                Frame::List(pairs) => match pairs.next() {
+                    Some(pair) => {
+                        path.push_name(pair.key());
+                        match pair.data_type() {
+                            DataType::NvList => {
+                                let flow = visitor.enter_list(&path);
+                                if flow == Flow::Continue {
+                                    // Nested list is owned by `self`, which outlives the walk.
+                                    let list = unsafe { nested_list(&pair) };
+                                    stack.push(Frame::List(list.iter()));
+                                    continue;
+                                }
+                                flow
+                            },
+                            DataType::NvListArray => {
+                                // Elements are owned by `self`, which outlives the walk.
+                                let lists = unsafe { nested_lists(&pair) };
+                                let flow =
+                                    visitor.enter_array(&path, DataType::NvListArray, lists.len());
+                                if flow == Flow::Continue {
+                                    stack.push(Frame::Array(lists.enumerate()));
+                                    continue;
+                                }
+                                flow
+                            },
+                            _ => visit_pair(visitor, &mut path, &pair),
+                        }
+                    },
+                    None => {
+                        stack.pop();
+                        if stack.is_empty() {
+                            break;
+                        }
+                        visitor.leave_list(&path)
+                    },
+                },
+                Frame::Array(lists) => match lists.next() {
+                    Some((idx, list)) => {
+                        path.segments.push(Segment::Index(idx));
+                        let flow = visitor.enter_list(&path);
+                        if flow == Flow::Continue {
+                            stack.push(Frame::List(list.iter()));
+                            continue;
+                        }
+                        flow
+                    },
+                    None => {
+                        stack.pop();
+                        visitor.leave_array(&path)
+                    },
+                },
+            };
+            if flow == Flow::Stop {
+                return false;
+            }
+            path.pop();
+        }
+        true
+    }
+}
+
+/// Visit a pair that isn't a list or an array of lists.
+fn visit_pair<V: Visitor + ?Sized>(visitor: &mut V, path: &mut KeyPath, pair: &NvPairRef) -> Flow {
+    fn array<V: Visitor + ?Sized, T>(
+        visitor: &mut V,
+        path: &mut KeyPath,
+        data_type: DataType,
+        values: Vec<T>,
+        mut visit: impl FnMut(&mut V, &KeyPath, T) -> Flow,
+    ) -> Flow {
+        match visitor.enter_array(path, data_type, values.len()) {
+            Flow::Continue => (),
+            flow => return flow,
+        }
+        for (idx, value) in values.into_iter().enumerate() {
+            path.segments.push(Segment::Index(idx));
+            let flow = visit(visitor, path, value);
+            path.pop();
+            if flow == Flow::Stop {
+                return flow;
+            }
+        }
+        visitor.leave_array(path)
+    }
+
+    let data_type = pair.data_type();
+    match data_type {
+        DataType::Boolean => return visitor.visit_boolean(path),
+        // Strings are passed as they are, without decoding them into a `Value`.
+        DataType::String => {
+            let mut ptr = null_mut();
+            unsafe { sys::nvpair_value_string(pair.as_ptr(), &mut ptr) };
+            return visitor.visit_c_str(path, unsafe { CStr::from_ptr(ptr) });
+        },
+        DataType::StringArray => {
+            let mut ptr = null_mut();
+            let mut len = 0;
+            unsafe { sys::nvpair_value_string_array(pair.as_ptr(), &mut ptr, &mut len) };
+            let values: Vec<&CStr> = unsafe { raw_slice(ptr, len) }
+                .iter()
+                .map(|value| unsafe { CStr::from_ptr(*value) })
+                .collect();
+            return array(visitor, path, data_type, values, V::visit_c_str);
+        },
+        _ => (),
+    }
+    match pair.value() {
+        Value::Bool(v) => visitor.visit_bool(path, v),
+        Value::Byte(v) => visitor.visit_byte(path, v),
+        Value::Int8(v) => visitor.visit_i8(path, v),
+        Value::Uint8(v) => visitor.visit_u8(path, v),
+        Value::Int16(v) => visitor.visit_i16(path, v),
+        Value::Uint16(v) => visitor.visit_u16(path, v),
+        Value::Int32(v) => visitor.visit_i32(path, v),
+        Value::Uint32(v) => visitor.visit_u32(path, v),
+        Value::Int64(v) => visitor.visit_i64(path, v),
+        Value::Uint64(v) => visitor.visit_u64(path, v),
+        Value::Hrtime(v) => visitor.visit_hrtime(path, v),
+        Value::Double(v) => visitor.visit_double(path, v),
+        Value::BoolArray(v) => array(visitor, path, data_type, v, V::visit_bool),
+        Value::ByteArray(v) => array(visitor, path, data_type, v, V::visit_byte),
+        Value::Int8Array(v) => array(visitor, path, data_type, v, V::visit_i8),
+        Value::Uint8Array(v) => array(visitor, path, data_type, v, V::visit_u8),
+        Value::Int16Array(v) => array(visitor, path, data_type, v, V::visit_i16),
+        Value::Uint16Array(v) => array(visitor, path, data_type, v, V::visit_u16),
+        Value::Int32Array(v) => array(visitor, path, data_type, v, V::visit_i32),
+        Value::Uint32Array(v) => array(visitor, path, data_type, v, V::visit_u32),
+        Value::Int64Array(v) => array(visitor, path, data_type, v, V::visit_i64),
+        Value::Uint64Array(v) => array(visitor, path, data_type, v, V::visit_u64),
+        value @ (Value::Unknown
+        | Value::String(_)
+        | Value::StringArray(_)
+        | Value::NvList(_)
+        | Value::NvListArray(_)) => visitor.visit_value(path, value),
+    }
+}
+
+/// Borrow the list stored in `pair` for as long as the caller wants.
+///
+/// # Safety
+///
+/// Value of `pair` must be a list and the list holding `pair` must outlive `'a`.
+pub(super) unsafe fn nested_list<'a>(pair: &NvPairRef) -> &'a NvListRef {
+    let mut ptr = null_mut();
+    unsafe {
+        sys::nvpair_value_nvlist(pair.as_ptr(), &mut ptr);
+        NvListRef::from_ptr(ptr)
+    }
+}
+
+/// Borrow elements of the nvlist array stored in `pair` for as long as the caller wants.
+///
+/// # Safety
+///
+/// Value of `pair` must be an array of lists and the list holding `pair` must outlive `'a`.
+pub(super) unsafe fn nested_lists<'a>(pair: &NvPairRef) -> NvListArrayIter<'a> {
+    let mut ptr = null_mut();
+    let mut len = 0;
+    unsafe {
+        sys::nvpair_value_nvlist_array(pair.as_ptr(), &mut ptr, &mut len);
+        NvListArrayIter { lists: raw_slice(ptr, len).iter() }
+    }
+}